@@ -0,0 +1,44 @@
+use clap::{App, Arg, ArgMatches, SubCommand};
+use elba::{
+    build::context::BuildProfile,
+    cli::build,
+    util::{config::Config, errors::Res},
+};
+use failure::ResultExt;
+use std::env::current_dir;
+
+pub fn cli() -> App<'static, 'static> {
+    SubCommand::with_name("build")
+        .about("Builds the project")
+        .arg(
+            Arg::with_name("release")
+                .long("release")
+                .help("Builds with the release profile instead of the dev profile"),
+        )
+        .arg(
+            Arg::with_name("backend")
+                .long("backend")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Generates binaries with this codegen backend (e.g. c, javascript, node)"),
+        )
+}
+
+pub fn exec(c: &mut Config, args: &ArgMatches) -> Res<()> {
+    let project = current_dir().context(format_err!(
+        "couldn't get current dir; doesn't exist or no permissions..."
+    ))?;
+
+    let mut ctx = build::BuildCtx::from_config(c, project)?;
+    if args.is_present("release") {
+        ctx.profile = BuildProfile::Release;
+    }
+    if let Some(backends) = args.values_of("backend") {
+        ctx.backends = backends.map(|b| b.to_owned()).collect();
+    }
+
+    build::build(&ctx)?;
+
+    Ok(())
+}
@@ -0,0 +1,33 @@
+use clap::{App, Arg, ArgMatches, SubCommand};
+use elba::{
+    build::context::BuildProfile,
+    cli::build,
+    util::{config::Config, errors::Res},
+};
+use failure::ResultExt;
+use std::env::current_dir;
+
+pub fn cli() -> App<'static, 'static> {
+    SubCommand::with_name("doc")
+        .about("Builds the project along with the documentation of its library")
+        .arg(
+            Arg::with_name("release")
+                .long("release")
+                .help("Builds with the release profile instead of the dev profile"),
+        )
+}
+
+pub fn exec(c: &mut Config, args: &ArgMatches) -> Res<()> {
+    let project = current_dir().context(format_err!(
+        "couldn't get current dir; doesn't exist or no permissions..."
+    ))?;
+
+    let mut ctx = build::BuildCtx::from_config(c, project)?;
+    if args.is_present("release") {
+        ctx.profile = BuildProfile::Release;
+    }
+
+    build::doc(&ctx)?;
+
+    Ok(())
+}
@@ -0,0 +1,27 @@
+use clap::{App, AppSettings, ArgMatches, SubCommand};
+use elba::{
+    cli::{index, Env},
+    util::{config::Config, errors::Res},
+};
+
+pub fn cli() -> App<'static, 'static> {
+    SubCommand::with_name("index")
+        .about("Manages the indices in use")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(SubCommand::with_name("update").about("Updates every index in the config"))
+}
+
+pub fn exec(c: &mut Config, args: &ArgMatches) -> Res<()> {
+    match args.subcommand() {
+        ("update", Some(_)) => update(c),
+        (cmd, _) => bail!("unknown index subcommand `{}`", cmd),
+    }
+}
+
+fn update(c: &mut Config) -> Res<()> {
+    let ctx = index::IndexCtx {
+        env: Env::from_config(c)?,
+    };
+
+    index::update(&ctx)
+}
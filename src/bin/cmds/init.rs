@@ -2,7 +2,10 @@ use clap::{App, Arg, ArgMatches, SubCommand};
 use elba::{
     cli::new,
     package::Name,
-    util::{config::Config, errors::Res},
+    util::{
+        config::{Config, Profile},
+        errors::Res,
+    },
 };
 use failure::ResultExt;
 use std::env::current_dir;
@@ -29,10 +32,13 @@ pub fn exec(c: &mut Config, args: &ArgMatches) -> Res<()> {
         .into_owned();
     let name = Name::new(name.clone(), name);
     let bin = !args.is_present("lib");
-    let author = if let Some(profile) = &c.profile {
-        Some((profile.name.clone(), profile.email.clone()))
-    } else {
-        None
+    let author = match &c.profile {
+        Some(Profile {
+            name: Some(name),
+            email: Some(email),
+            ..
+        }) => Some((name.clone(), email.clone())),
+        _ => None,
     };
 
     let new_ctx = new::NewCtx {
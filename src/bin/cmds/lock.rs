@@ -4,7 +4,6 @@ use elba::{
     util::{config::Config, errors::Res},
 };
 use failure::ResultExt;
-use slog::{Discard, Logger};
 use std::env::current_dir;
 
 pub fn cli() -> App<'static, 'static> {
@@ -15,19 +14,8 @@ pub fn exec(c: &mut Config, args: &ArgMatches) -> Res<()> {
     let project = current_dir().context(format_err!(
         "couldn't get current dir; doesn't exist or no permissions..."
     ))?;
-    let indices = c.indices.iter().cloned().collect::<Vec<_>>();
-    let global_cache = c.directories.cache.clone();
-
-    // TODO: Proper log output etc.
-    let logger = Logger::root(Discard, o!());
-
-    let ctx = build::BuildCtx {
-        project,
-        indices,
-        global_cache,
-        logger,
-    };
 
+    let ctx = build::BuildCtx::from_config(c, project)?;
     build::lock(&ctx)?;
 
     Ok(())
@@ -0,0 +1,56 @@
+use clap::{App, Arg, ArgMatches, SubCommand};
+use elba::{
+    cli::login,
+    package::resolution::DirectRes,
+    util::{config::Config, errors::Res},
+};
+use failure::ResultExt;
+use std::{io, str::FromStr};
+
+pub fn cli() -> App<'static, 'static> {
+    SubCommand::with_name("login")
+        .about("Saves an API token for an index")
+        .arg(
+            Arg::with_name("index")
+                .required(true)
+                .help("The name or url of the index"),
+        )
+        .arg(
+            Arg::with_name("token")
+                .long("token")
+                .takes_value(true)
+                .help("The token to save; if not given, it's read from stdin"),
+        )
+}
+
+pub fn exec(c: &mut Config, args: &ArgMatches) -> Res<()> {
+    let index = &*args.value_of_lossy("index").unwrap();
+    let index = c
+        .indices
+        .resolve(index)
+        .map(|i| i.res)
+        .or_else(|_| DirectRes::from_str(index))
+        .context(format_err!("the index `{}` is invalid.", index))?;
+
+    let token = if let Some(token) = args.value_of("token") {
+        token.to_owned()
+    } else {
+        let mut token = String::new();
+        io::stdin()
+            .read_line(&mut token)
+            .context(format_err!("could not read token from stdin"))?;
+        token
+    };
+
+    if token.trim().is_empty() {
+        bail!("no token given")
+    }
+
+    let ctx = login::LoginCtx {
+        credentials: c.directories.rest.join("credentials.toml"),
+        index,
+        token,
+    };
+
+    login::login(ctx)
+}
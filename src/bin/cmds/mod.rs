@@ -1,6 +1,14 @@
+mod build;
+mod doc;
+mod index;
 mod init;
 mod lock;
+mod login;
 mod new;
+mod package;
+mod publish;
+mod search;
+mod yank;
 
 use clap::{App, ArgMatches};
 use elba::util::{config::Config, errors::Res};
@@ -9,7 +17,19 @@ use failure::Error;
 pub type Exec = fn(&mut Config, &ArgMatches) -> Res<()>;
 
 pub fn subcommands() -> Vec<App<'static, 'static>> {
-    vec![new::cli(), init::cli(), lock::cli()]
+    vec![
+        new::cli(),
+        init::cli(),
+        lock::cli(),
+        build::cli(),
+        doc::cli(),
+        login::cli(),
+        index::cli(),
+        package::cli(),
+        publish::cli(),
+        yank::cli(),
+        search::cli(),
+    ]
 }
 
 pub fn execute_internal(cmd: &str) -> Option<Exec> {
@@ -17,6 +37,14 @@ pub fn execute_internal(cmd: &str) -> Option<Exec> {
         "new" => Some(new::exec),
         "init" => Some(init::exec),
         "lock" => Some(lock::exec),
+        "build" => Some(build::exec),
+        "doc" => Some(doc::exec),
+        "login" => Some(login::exec),
+        "index" => Some(index::exec),
+        "package" => Some(package::exec),
+        "publish" => Some(publish::exec),
+        "yank" => Some(yank::exec),
+        "search" => Some(search::exec),
         _ => None,
     }
 }
@@ -2,7 +2,10 @@ use clap::{App, Arg, ArgMatches, SubCommand};
 use elba::{
     cli::new,
     package::Name,
-    util::{config::Config, errors::Res},
+    util::{
+        config::{Config, Profile},
+        errors::Res,
+    },
 };
 use failure::ResultExt;
 use std::{env::current_dir, str::FromStr};
@@ -23,10 +26,13 @@ pub fn exec(c: &mut Config, args: &ArgMatches) -> Res<()> {
     let name = &*args.value_of_lossy("name").unwrap();
     let name = Name::from_str(name).context(format_err!("the name `{}` is invalid.", name))?;
     let bin = !args.is_present("lib");
-    let author = if let Some(profile) = &c.profile {
-        Some((profile.name.clone(), profile.email.clone()))
-    } else {
-        None
+    let author = match &c.profile {
+        Some(Profile {
+            name: Some(name),
+            email: Some(email),
+            ..
+        }) => Some((name.clone(), email.clone())),
+        _ => None,
     };
     let cdir = current_dir().context(format_err!(
         "couldn't get current dir; doesn't exist or no permissions..."
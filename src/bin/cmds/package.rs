@@ -0,0 +1,41 @@
+use clap::{App, Arg, ArgMatches, SubCommand};
+use elba::{
+    cli::{build, package},
+    util::{config::Config, errors::Res},
+};
+use failure::ResultExt;
+use std::env::current_dir;
+
+pub fn cli() -> App<'static, 'static> {
+    SubCommand::with_name("package")
+        .about("Packs the project into a tarball, as it would be published")
+        .arg(
+            Arg::with_name("list")
+                .long("list")
+                .short("l")
+                .help("Lists the files which would be packaged instead of packing them"),
+        )
+        .arg(
+            Arg::with_name("no-verify")
+                .long("no-verify")
+                .help("Doesn't check that the packaged copy builds on its own"),
+        )
+}
+
+pub fn exec(c: &mut Config, args: &ArgMatches) -> Res<()> {
+    let project = current_dir().context(format_err!(
+        "couldn't get current dir; doesn't exist or no permissions..."
+    ))?;
+
+    if args.is_present("list") {
+        for file in package::list(&project)? {
+            println!("{}", file.display());
+        }
+        return Ok(());
+    }
+
+    let ctx = build::BuildCtx::from_config(c, project)?;
+    package::package(&ctx, !args.is_present("no-verify"))?;
+
+    Ok(())
+}
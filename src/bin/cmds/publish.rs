@@ -0,0 +1,37 @@
+use clap::{App, Arg, ArgMatches, SubCommand};
+use elba::{
+    cli::{build::BuildCtx, publish},
+    util::{config::Config, errors::Res},
+};
+use failure::ResultExt;
+use std::env::current_dir;
+
+pub fn cli() -> App<'static, 'static> {
+    SubCommand::with_name("publish")
+        .about("Packages the project and publishes it to an index")
+        .arg(
+            Arg::with_name("index")
+                .long("index")
+                .takes_value(true)
+                .help("The name or url of the index to publish to; defaults to the default index"),
+        )
+        .arg(
+            Arg::with_name("no-verify")
+                .long("no-verify")
+                .help("Doesn't check that the packaged copy builds on its own"),
+        )
+}
+
+pub fn exec(c: &mut Config, args: &ArgMatches) -> Res<()> {
+    let project = current_dir().context(format_err!(
+        "couldn't get current dir; doesn't exist or no permissions..."
+    ))?;
+    let index = args.value_of("index").map(|s| s.to_owned());
+    let ctx = publish::PublishCtx {
+        build: BuildCtx::from_config(c, project)?,
+        index,
+        verify: !args.is_present("no-verify"),
+    };
+
+    publish::publish(&ctx)
+}
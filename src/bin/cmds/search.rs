@@ -0,0 +1,58 @@
+use clap::{App, Arg, ArgMatches, SubCommand};
+use elba::{
+    cli::{search, Env},
+    util::{config::Config, errors::Res},
+};
+use serde_json;
+
+pub fn cli() -> App<'static, 'static> {
+    SubCommand::with_name("search")
+        .about("Searches for packages in the indices")
+        .arg(
+            Arg::with_name("query")
+                .required(true)
+                .help("What to look for in the names of packages"),
+        )
+        .arg(
+            Arg::with_name("regex")
+                .long("regex")
+                .help("Treats the query as a regex instead of fuzzy-matching it"),
+        )
+        .arg(
+            Arg::with_name("index")
+                .long("index")
+                .takes_value(true)
+                .help("The name or url of the only index to search"),
+        )
+        .arg(
+            Arg::with_name("json")
+                .long("json")
+                .help("Prints the results as JSON"),
+        )
+}
+
+pub fn exec(c: &mut Config, args: &ArgMatches) -> Res<()> {
+    let query = args.value_of("query").unwrap().to_owned();
+    let index = args.value_of("index").map(|s| s.to_owned());
+    let ctx = search::SearchCtx {
+        query,
+        regex: args.is_present("regex"),
+        index,
+        env: Env::from_config(c)?,
+    };
+
+    let results = search::search(&ctx)?;
+
+    if args.is_present("json") {
+        println!("{}", serde_json::to_string_pretty(&results)?);
+    } else {
+        for r in results {
+            match r.description {
+                Some(d) => println!("{} = \"{}\" ({}) # {}", r.name, r.version, r.index, d),
+                None => println!("{} = \"{}\" ({})", r.name, r.version, r.index),
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,53 @@
+use clap::{App, Arg, ArgMatches, SubCommand};
+use elba::{
+    cli::{yank, Env},
+    package::Name,
+    util::{config::Config, errors::Res},
+};
+use failure::ResultExt;
+use semver::Version;
+use std::str::FromStr;
+
+pub fn cli() -> App<'static, 'static> {
+    SubCommand::with_name("yank")
+        .about("Stops new projects from depending on a published version of a package")
+        .arg(
+            Arg::with_name("package")
+                .required(true)
+                .help("The version to yank, like `group/name@1.0.0`"),
+        )
+        .arg(
+            Arg::with_name("undo")
+                .long("undo")
+                .help("Takes back a yank, allowing new projects to depend on the version again"),
+        )
+        .arg(
+            Arg::with_name("index")
+                .long("index")
+                .takes_value(true)
+                .help("The name or url of the index the package is in"),
+        )
+}
+
+pub fn exec(c: &mut Config, args: &ArgMatches) -> Res<()> {
+    let package = args.value_of("package").unwrap();
+    let mut parts = package.splitn(2, '@');
+    let name = Name::from_str(parts.next().unwrap())
+        .context(format_err!("the package `{}` is invalid.", package))?;
+    let version = parts
+        .next()
+        .ok_or_else(|| format_err!("`{}` needs a version, like `{}@1.0.0`", package, name))?;
+    let version =
+        Version::parse(version).context(format_err!("the version `{}` is invalid.", version))?;
+
+    let index = args.value_of("index").map(|s| s.to_owned());
+    let ctx = yank::YankCtx {
+        name,
+        version,
+        undo: args.is_present("undo"),
+        index,
+        env: Env::from_config(c)?,
+    };
+
+    yank::yank(&ctx)
+}
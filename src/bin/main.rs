@@ -6,8 +6,9 @@ extern crate clap;
 extern crate elba;
 #[macro_use]
 extern crate failure;
+extern crate semver;
+extern crate serde_json;
 extern crate toml;
-#[macro_use]
 extern crate slog;
 extern crate slog_term;
 
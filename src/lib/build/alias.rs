@@ -0,0 +1,255 @@
+//! Aliasing packages under a different module prefix.
+//!
+//! Every Idris module lives in one global namespace, so two packages which both declare a module
+//! `Me.Lightyear` can't be used together. To get around this, a dependency can be aliased under a
+//! different prefix. To alias `me/lightyear` (with the root module `Me.Lightyear`) as
+//! `Yeet.Lightyeet`, we generate a shim for each of its modules: a copy of the module with every
+//! mention of `Me.Lightyear` swapped for `Yeet.Lightyeet`, so that `Me/Lightyear/Parser.idr`
+//! becomes
+//!
+//! ```idris
+//! module Yeet.Lightyeet.Parser
+//!
+//! import Yeet.Lightyeet.Lexer
+//! ```
+//!
+//! The shims are built as their own package, with the dependencies of the package they alias,
+//! and the build is kept in the global cache. The alias's build then goes on the import path of
+//! the root package instead of the original's. Since the shims don't import the original modules,
+//! nothing under `Me.Lightyear` is left for the root package to see.
+
+use super::{
+    compile::{BuildDir, CompileInvocation},
+    context::BuildContext,
+    module::{is_under, module_path},
+};
+use failure::{Error, ResultExt};
+use indexmap::IndexMap;
+use package::Name;
+use regex::{self, Captures, Regex};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+use util::lock::DirLock;
+
+/// A dependency which is imported under a different module prefix than its own.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Alias {
+    pub name: Name,
+    /// The module prefix which replaces the package's root module.
+    pub prefix: String,
+}
+
+/// A generated copy of a module of an aliased package, renamed under the alias's prefix.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Shim {
+    /// The name of the shim module.
+    pub module: String,
+    /// The module it's a copy of.
+    pub original: String,
+    /// The source file of the original module, relative to the package's library.
+    pub original_path: PathBuf,
+}
+
+impl Alias {
+    pub fn new(name: Name, prefix: String) -> Self {
+        Alias { name, prefix }
+    }
+
+    /// The name of the shim for `module`, if it's under the `root` module of the aliased package.
+    pub fn rename(&self, root: &str, module: &str) -> Option<String> {
+        if is_under(module, root) {
+            Some(format!("{}{}", self.prefix, &module[root.len()..]))
+        } else {
+            None
+        }
+    }
+
+    /// The shims for every module of the aliased package which is under its `root` module, given
+    /// the source files of its library and their modules.
+    pub fn shims(&self, root: &str, modules: &[(PathBuf, String)]) -> Vec<Shim> {
+        modules
+            .iter()
+            .filter_map(|(path, original)| {
+                self.rename(root, original).map(|module| Shim {
+                    module,
+                    original: original.clone(),
+                    original_path: path.clone(),
+                })
+            })
+            .collect()
+    }
+
+    /// Swaps `root` for the alias's prefix wherever it's used as a module (or the start of one) in
+    /// `source`: in the module header, in imports and in qualified names.
+    pub fn rewrite(&self, root: &str, source: &str) -> String {
+        let pattern = format!(r"(^|[^\w.']){}\b", regex::escape(root));
+        Regex::new(&pattern)
+            .unwrap()
+            .replace_all(source, |caps: &Captures| {
+                format!("{}{}", &caps[1], self.prefix)
+            })
+            .into_owned()
+    }
+}
+
+impl Shim {
+    /// Where the source of the shim goes, relative to the root of the build. Literate modules stay
+    /// literate.
+    pub fn path(&self) -> PathBuf {
+        let ext = self
+            .original_path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or("idr");
+        module_path(&self.module, ext)
+    }
+}
+
+/// Builds the shims of `alias` into the directory `lock`, where `src` is the directory of the
+/// library of the package it aliases, `modules` are the source files of that library along with
+/// their modules, `root` is the package's root module and `deps` are the builds of its
+/// dependencies.
+///
+/// Only the artifacts of the shims end up in the directory. If every shim has already been built
+/// into it, nothing is done.
+pub fn build(
+    alias: &Alias,
+    root: &str,
+    src: &Path,
+    modules: &[(PathBuf, String)],
+    deps: &[PathBuf],
+    lock: DirLock,
+    bcx: &mut BuildContext,
+) -> Result<(), Error> {
+    let ext = bcx.compiler.family.artifact_ext();
+    let dir = lock.path().to_path_buf();
+    let shims = alias.shims(root, modules);
+    if shims.is_empty() {
+        bail!(
+            "{} has no modules under `{}` to alias as `{}`",
+            alias.name,
+            root,
+            alias.prefix
+        )
+    }
+
+    let built = |shim: &Shim| dir.join(module_path(&shim.module, ext)).exists();
+    if shims.iter().all(|s| built(s)) {
+        return Ok(());
+    }
+
+    let shim_src = dir.join("src");
+    let _ = fs::remove_dir_all(&shim_src);
+    for shim in &shims {
+        let original = src.join(&shim.original_path);
+        let source = fs::read_to_string(&original)
+            .context(format_err!("could not read `{}`", original.display()))?;
+        let path = shim_src.join(shim.path());
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(&path, alias.rewrite(root, &source))
+            .context(format_err!("could not write `{}`", path.display()))?;
+    }
+
+    let res = CompileInvocation::new(
+        shim_src.clone(),
+        shims.iter().map(|s| (s.path(), s.module.clone())).collect(),
+        deps.to_vec(),
+        BuildDir::new(lock)?,
+    )
+    .execute(bcx);
+    let _ = fs::remove_dir_all(&shim_src);
+
+    res.context(format_err!(
+        "could not build the alias `{}` for {}",
+        alias.prefix,
+        alias.name
+    ))?;
+
+    Ok(())
+}
+
+/// Builds the import path for a package out of the builds of its dependencies, swapping the
+/// builds of aliased dependencies for the builds of their aliases.
+pub fn import_paths(deps: &[(Name, PathBuf)], aliases: &IndexMap<Name, PathBuf>) -> Vec<PathBuf> {
+    deps.iter()
+        .map(|(name, build)| aliases.get(name).unwrap_or(build).clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn alias() -> Alias {
+        Alias::new(
+            Name::from_str("me/lightyear").unwrap(),
+            "Yeet.Lightyeet".to_owned(),
+        )
+    }
+
+    #[test]
+    fn alias_shims() {
+        let modules = vec![
+            (PathBuf::from("Me/Lightyear.idr"), "Me.Lightyear".to_owned()),
+            (
+                PathBuf::from("Me/Lightyear/Parser.lidr"),
+                "Me.Lightyear.Parser".to_owned(),
+            ),
+            (
+                PathBuf::from("Me/LightyearX.idr"),
+                "Me.LightyearX".to_owned(),
+            ),
+            (PathBuf::from("Other.idr"), "Other".to_owned()),
+        ];
+        let shims = alias().shims("Me.Lightyear", &modules);
+
+        assert_eq!(
+            shims.iter().map(|s| s.module.as_str()).collect::<Vec<_>>(),
+            vec!["Yeet.Lightyeet", "Yeet.Lightyeet.Parser"]
+        );
+        assert_eq!(shims[1].path(), PathBuf::from("Yeet/Lightyeet/Parser.lidr"));
+    }
+
+    #[test]
+    fn alias_rewrite() {
+        let source = "module Me.Lightyear.Parser\n\n\
+                      import Me.Lightyear\n\
+                      import public Me.Lightyear.Lexer as L\n\
+                      import Me.LightyearX\n\
+                      import You.Me.Lightyear\n\n\
+                      p : Me.Lightyear.Parser Int\n";
+
+        assert_eq!(
+            alias().rewrite("Me.Lightyear", source),
+            "module Yeet.Lightyeet.Parser\n\n\
+             import Yeet.Lightyeet\n\
+             import public Yeet.Lightyeet.Lexer as L\n\
+             import Me.LightyearX\n\
+             import You.Me.Lightyear\n\n\
+             p : Yeet.Lightyeet.Parser Int\n"
+        );
+    }
+
+    #[test]
+    fn alias_import_paths() {
+        let me = Name::from_str("me/lightyear").unwrap();
+        let you = Name::from_str("you/lightyear").unwrap();
+        let deps = vec![
+            (me.clone(), PathBuf::from("build/me")),
+            (you, PathBuf::from("build/you")),
+        ];
+        let mut aliases = IndexMap::new();
+        aliases.insert(me, PathBuf::from("build/me~Yeet.Lightyeet"));
+
+        assert_eq!(
+            import_paths(&deps, &aliases),
+            vec![
+                PathBuf::from("build/me~Yeet.Lightyeet"),
+                PathBuf::from("build/you"),
+            ]
+        );
+    }
+}
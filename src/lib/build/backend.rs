@@ -0,0 +1,175 @@
+//! Codegen backends, which turn Idris code into something which can actually be run.
+//!
+//! The Idris compiler generates code through separate executables named
+//! `idris-codegen-<backend>`, which it finds on the `PATH`; the backends which come with the
+//! compiler (`c`, `javascript` and `node`) are no different. Because of this, the backends which
+//! can be used are just the ones with an executable on the `PATH`.
+//!
+//! Idris 2 has its backends built in instead, so none of this applies to it (see
+//! `Compiler::backends`).
+
+use failure::Error;
+use std::{
+    env::{self, consts::EXE_EXTENSION},
+    ffi::OsStr,
+    fs,
+    path::{Path, PathBuf},
+};
+use util::is_executable;
+
+/// The backend binaries are built with by Idris 1 if nothing else is asked for.
+pub const DEFAULT_BACKEND: &str = "c";
+
+const PREFIX: &str = "idris-codegen-";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Backend {
+    pub name: String,
+    /// The executable which implements the backend.
+    pub path: PathBuf,
+}
+
+impl Backend {
+    /// The extension of the files which the backend generates. Native executables don't have one
+    /// (except on Windows).
+    pub fn extension(&self) -> &str {
+        match self.name.as_str() {
+            "c" => EXE_EXTENSION,
+            "javascript" | "node" => "js",
+            "php" => "php",
+            "python" => "py",
+            "ruby" => "rb",
+            "lua" => "lua",
+            _ => "",
+        }
+    }
+
+    /// The name of the file which the binary `name` gets generated as.
+    pub fn output(&self, name: &str) -> PathBuf {
+        let mut output = PathBuf::from(name);
+        if !self.extension().is_empty() {
+            output.set_extension(self.extension());
+        }
+        output
+    }
+}
+
+/// Finds every backend in the directories of `path`, which is formatted like the `PATH`
+/// environment variable. If a backend is in more than one directory, the first one wins, just as
+/// it would when the compiler looks it up. The backends are sorted by name.
+pub fn discover(path: &OsStr) -> Vec<Backend> {
+    let mut backends: Vec<Backend> = vec![];
+
+    for dir in env::split_paths(path) {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+
+        for entry in entries.filter_map(|e| e.ok()) {
+            let path = entry.path();
+            let name = match backend_name(&path) {
+                Some(name) => name,
+                None => continue,
+            };
+
+            if is_executable(&path) && !backends.iter().any(|b| b.name == name) {
+                backends.push(Backend { name, path });
+            }
+        }
+    }
+
+    backends.sort_by(|a, b| a.name.cmp(&b.name));
+    backends
+}
+
+/// Picks the backends called `names` out of the `available` ones.
+pub fn select(available: &[Backend], names: &[String]) -> Result<Vec<Backend>, Error> {
+    names
+        .iter()
+        .map(|name| {
+            available
+                .iter()
+                .find(|b| &b.name == name)
+                .cloned()
+                .ok_or_else(|| {
+                    let found = available
+                        .iter()
+                        .map(|b| b.name.as_str())
+                        .collect::<Vec<_>>();
+                    format_err!(
+                        "the codegen backend `{}` isn't available (there's no `{}{}` on the \
+                         PATH); the available backends are: {}",
+                        name,
+                        PREFIX,
+                        name,
+                        if found.is_empty() {
+                            "none".to_owned()
+                        } else {
+                            found.join(", ")
+                        }
+                    )
+                })
+        })
+        .collect()
+}
+
+fn backend_name(path: &Path) -> Option<String> {
+    let file_name = if !EXE_EXTENSION.is_empty() {
+        if path.extension()? != EXE_EXTENSION {
+            return None;
+        }
+        path.file_stem()?
+    } else {
+        path.file_name()?
+    };
+
+    let file_name = file_name.to_str()?;
+    if file_name.starts_with(PREFIX) && file_name.len() > PREFIX.len() {
+        Some(file_name[PREFIX.len()..].to_owned())
+    } else {
+        None
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::{env, os::unix::fs::PermissionsExt};
+
+    fn stub(dir: &Path, name: &str, mode: u32) {
+        let path = dir.join(name);
+        fs::write(&path, "#!/bin/sh\n").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(mode)).unwrap();
+    }
+
+    #[test]
+    fn backend_discover() {
+        let base = env::temp_dir().join("elba-backend-test-discover");
+        let _ = fs::remove_dir_all(&base);
+        let (first, second) = (base.join("first"), base.join("second"));
+        fs::create_dir_all(&first).unwrap();
+        fs::create_dir_all(&second).unwrap();
+
+        stub(&first, "idris-codegen-node", 0o755);
+        stub(&first, "idris-codegen-notexec", 0o644);
+        stub(&first, "idris-codegen-", 0o755);
+        stub(&second, "idris-codegen-node", 0o755);
+        stub(&second, "idris-codegen-c", 0o755);
+        stub(&second, "idris", 0o755);
+
+        let path = env::join_paths(vec![&first, &base.join("missing"), &second]).unwrap();
+        let backends = discover(&path);
+        assert_eq!(
+            backends.iter().map(|b| b.name.as_str()).collect::<Vec<_>>(),
+            vec!["c", "node"]
+        );
+        assert_eq!(backends[1].path, first.join("idris-codegen-node"));
+
+        let node = select(&backends, &["node".to_owned()]).unwrap();
+        assert_eq!(node[0].output("hello"), PathBuf::from("hello.js"));
+        assert!(select(&backends, &["php".to_owned()]).is_err());
+
+        let _ = fs::remove_dir_all(&base);
+    }
+}
@@ -2,99 +2,261 @@
 
 use std::fs;
 use std::path::PathBuf;
+use std::process::Output;
 
 use failure::{err_msg, Error};
 
-use super::context::BuildContext;
-use package::Name;
+use super::{backend::Backend, context::BuildContext, module::module_path};
 use util::lock::DirLock;
 
+/// Builds all of the modules of a library.
 #[derive(Debug)]
 pub struct CompileInvocation {
+    /// The directory the library's modules live in.
     src: PathBuf,
-    dep_builds: Vec<(Name, PathBuf)>,
+    /// The source files of the library's modules, relative to `src`, along with their modules.
+    modules: Vec<(PathBuf, String)>,
+    /// The builds of the library's dependencies, which imported modules are looked up in.
+    deps: Vec<PathBuf>,
     build_dir: BuildDir,
 }
 
 impl CompileInvocation {
+    pub fn new(
+        src: PathBuf,
+        modules: Vec<(PathBuf, String)>,
+        deps: Vec<PathBuf>,
+        build_dir: BuildDir,
+    ) -> Self {
+        CompileInvocation {
+            src,
+            modules,
+            deps,
+            build_dir,
+        }
+    }
+
+    /// Builds the library, leaving the artifact of each of its modules in the root of the build
+    /// directory, laid out by module (e.g. `Data/Vect.ibc` for `Data.Vect`).
     pub fn execute(&mut self, bcx: &mut BuildContext) -> Result<(), Error> {
-        // set up file structure of dependencies
-        for (name, dep_build) in &self.dep_builds {
-            if dep_build
-                .extension()
-                .filter(|ext| ext.to_str() == Some("ibc"))
-                .is_none()
-            {
-                bail!("Dependency build '{:?}' is supposed to be .ibc", dep_build);
+        if self.modules.is_empty() {
+            bail!("There are no modules in {}", self.src.to_string_lossy());
+        }
+
+        // copy the sources over, keeping the module hierarchy
+        for (path, _) in &self.modules {
+            let dest = self.build_dir.build.join(path);
+            fs::create_dir_all(dest.parent().unwrap())?;
+            fs::copy(self.src.join(path), &dest)?;
+        }
+
+        // invoke compiler
+        let paths = self
+            .modules
+            .iter()
+            .map(|(path, _)| path.clone())
+            .collect::<Vec<_>>();
+        for args in bcx.compiler.check_args(&paths) {
+            let mut process = bcx.process();
+            bcx.compiler.add_imports(&mut process, &self.deps)?;
+            let output = process
+                .current_dir(&self.build_dir.build)
+                .args(args)
+                .output()?;
+
+            if !output.status.success() {
+                bail!(
+                    "Compilation of {} failed:\n{}",
+                    self.src.to_string_lossy(),
+                    String::from_utf8_lossy(&output.stdout)
+                );
+            }
+
+            if bcx.config.warnings_as_errors && warned(&output) {
+                bail!(
+                    "Compilation of {} generated warnings, which are errors in the {} profile",
+                    self.src.to_string_lossy(),
+                    bcx.config.profile.name()
+                );
+            }
+        }
+
+        // collect the artifacts
+        let ext = bcx.compiler.family.artifact_ext();
+        let artifacts = bcx.compiler.artifact_dir(&self.build_dir.build);
+        for (_, module) in &self.modules {
+            let path = module_path(module, ext);
+            let target = artifacts.join(&path);
+            if !target.exists() {
+                bail!("Compilation of {} does not generate binary", module);
             }
 
-            let mut dep_file_dest = self.build_dir.build.join(name.group());
-            dep_file_dest.set_file_name(name.name());
-            dep_file_dest.set_extension("ibc");
+            let dest = self.build_dir.root.join(&path);
+            fs::create_dir_all(dest.parent().unwrap())?;
+            fs::copy(&target, &dest)?;
+        }
+
+        fs::remove_dir_all(&self.build_dir.build)?;
+
+        Ok(())
+    }
+}
 
-            fs::copy(dep_build, dep_file_dest)?;
+/// Generates a runnable binary out of a `Main` module with a codegen backend.
+#[derive(Debug)]
+pub struct CodegenInvocation {
+    src: PathBuf,
+    output: PathBuf,
+    backend: Backend,
+    /// The directories which imported modules are looked up in, besides the one `src` is in.
+    imports: Vec<PathBuf>,
+    /// Where the compiler is run, and where it leaves the artifacts of the modules it goes
+    /// through, so that none of them end up next to the sources.
+    build_dir: BuildDir,
+}
+
+impl CodegenInvocation {
+    pub fn new(
+        src: PathBuf,
+        output: PathBuf,
+        backend: Backend,
+        imports: Vec<PathBuf>,
+        build_dir: BuildDir,
+    ) -> Self {
+        CodegenInvocation {
+            src,
+            output,
+            backend,
+            imports,
+            build_dir,
         }
+    }
 
-        // TODO: Error struct
-        let src_file_name = &self
+    pub fn execute(&mut self, bcx: &mut BuildContext) -> Result<(), Error> {
+        if let Some(parent) = self.output.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let src_dir = self
             .src
-            .file_name()
+            .parent()
             .ok_or_else(|| err_msg("Src refs to a non-file"))?;
-        let src_dest = self.build_dir.build.join(src_file_name);
-        fs::copy(&self.src, &src_dest)?;
-
-        let mut target = src_dest.clone();
-        target.set_extension("ibc");
 
         // invoke compiler
-        bcx.compiler
-            .process()
+        let mut process = bcx.process();
+        bcx.compiler.add_imports(&mut process, &self.imports)?;
+
+        let status = process
             .current_dir(&self.build_dir.build)
-            .arg("--check")
-            .arg(src_file_name)
-            .spawn()?;
+            .args(bcx.compiler.codegen_dirs(src_dir, &self.build_dir.build))
+            .args(
+                bcx.compiler
+                    .codegen_args(&self.backend, &self.output, &bcx.config.codegen_opts),
+            )
+            .arg(&self.src)
+            .status()?;
 
-        if !target.exists() {
+        if !status.success() || !self.output.exists() {
             bail!(
-                "Compilation of {} does not generate binary",
-                self.src.to_string_lossy()
+                "Codegen of {} with the {} backend failed",
+                self.src.to_string_lossy(),
+                self.backend.name
             );
         }
 
-        fs::copy(
-            &target,
-            self.build_dir.root.join(&target.file_name().unwrap()),
-        )?;
-
         fs::remove_dir_all(&self.build_dir.build)?;
 
         Ok(())
     }
 }
 
+/// Generates the documentation of a library with `--mkdoc`.
 #[derive(Debug)]
-pub struct CodegenInvocation {
+pub struct DocInvocation {
+    /// The name of the package, as it goes in the generated ipkg.
+    name: String,
+    /// The directory the library's modules live in.
     src: PathBuf,
-    output: String,
-    backend: String,
+    /// The source files of the library's modules, relative to `src`, along with their modules.
+    modules: Vec<(PathBuf, String)>,
+    /// The builds of the library's dependencies, which imported modules are looked up in.
+    deps: Vec<PathBuf>,
     build_dir: BuildDir,
 }
 
-impl CodegenInvocation {
+impl DocInvocation {
+    pub fn new(
+        name: String,
+        src: PathBuf,
+        modules: Vec<(PathBuf, String)>,
+        deps: Vec<PathBuf>,
+        build_dir: BuildDir,
+    ) -> Self {
+        DocInvocation {
+            name,
+            src,
+            modules,
+            deps,
+            build_dir,
+        }
+    }
+
+    /// Generates the documentation, leaving it in the root of the build directory.
+    ///
+    /// `--mkdoc` only works on packages, so an ipkg which lists the library's modules is written
+    /// next to the copied sources first.
     pub fn execute(&mut self, bcx: &mut BuildContext) -> Result<(), Error> {
-        // invoke compiler
-        bcx.compiler
-            .process()
-            .current_dir(&self.build_dir.root)
-            .args(&["--codegen", &self.backend])
-            .args(&["-o", &self.output])
-            .arg(&self.src)
-            .spawn()?;
+        for (path, _) in &self.modules {
+            let dest = self.build_dir.build.join(path);
+            fs::create_dir_all(dest.parent().unwrap())?;
+            fs::copy(self.src.join(path), &dest)?;
+        }
+
+        let ipkg = PathBuf::from(format!("{}.ipkg", self.name));
+        let modules = self
+            .modules
+            .iter()
+            .map(|(_, module)| module.as_str())
+            .collect::<Vec<_>>();
+        fs::write(
+            self.build_dir.build.join(&ipkg),
+            format!(
+                "package {}\n\nmodules = {}\n",
+                self.name,
+                modules.join(", ")
+            ),
+        )?;
+
+        let mut process = bcx.process();
+        bcx.compiler.add_imports(&mut process, &self.deps)?;
+        let output = process
+            .current_dir(&self.build_dir.build)
+            .args(bcx.compiler.doc_args(&ipkg))
+            .output()?;
+        let docs = bcx.compiler.doc_dir(&self.build_dir.build, &self.name);
+        if !output.status.success() || !docs.is_dir() {
+            bail!(
+                "Generating the documentation of {} failed:\n{}",
+                self.src.to_string_lossy(),
+                String::from_utf8_lossy(&output.stdout)
+            );
+        }
+
+        for entry in fs::read_dir(&docs)? {
+            let entry = entry?;
+            fs::rename(entry.path(), self.build_dir.root.join(entry.file_name()))?;
+        }
+        fs::remove_dir_all(&self.build_dir.build)?;
 
         Ok(())
     }
 }
 
+/// Whether the compiler warned about anything.
+fn warned(output: &Output) -> bool {
+    let is_warning = |out: &[u8]| String::from_utf8_lossy(out).contains("Warning");
+    is_warning(&output.stdout) || is_warning(&output.stderr)
+}
+
 #[derive(Debug)]
 pub struct BuildDir {
     lock: DirLock,
@@ -112,8 +274,7 @@ impl BuildDir {
             build: root.join("build"),
         };
 
-        fs::create_dir(&layout.root)?;
-        fs::create_dir(&layout.build)?;
+        fs::create_dir_all(&layout.build)?;
 
         Ok(layout)
     }
@@ -1,6 +1,19 @@
-use package::Summary;
-use petgraph::Graph;
-use std::{path::PathBuf, process::Command};
+use super::backend::{self, Backend, DEFAULT_BACKEND};
+use failure::{Error, ResultExt};
+use indexmap::IndexMap;
+use package::{manifest::TargetOpts, version::Constraint, Name};
+use resolve::solve::Solve;
+use semver::Version;
+use std::{
+    env::{self, consts::EXE_EXTENSION},
+    ffi::{OsStr, OsString},
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+    time::UNIX_EPOCH,
+};
+use toml;
+use util::is_executable;
 
 /// An unit that elba knows how to build it
 // #[derive(Debug)]
@@ -21,38 +34,623 @@ use std::{path::PathBuf, process::Command};
 // TODO: triple target
 pub struct BuildContext<'a> {
     pub compiler: Compiler,
-    pub resolve: &'a Graph<Summary, ()>,
+    pub resolve: &'a Solve,
+    /// The compiler settings of the target being built.
+    pub opts: TargetOpts,
+    pub config: BuildConfig,
 }
 
-// TODO: Verbosity, Total checking
-#[derive(Debug)]
-pub struct BuildConfig {}
+impl<'a> BuildContext<'a> {
+    /// Get a process set up to use the found compiler with the settings of the target being built
+    /// and of the build's profile
+    pub fn process(&self) -> Command {
+        let mut process = self.compiler.process_with(&self.opts);
+        if self.config.total {
+            process.arg("--total");
+        }
+        process
+    }
+}
+
+/// Which set of settings a build uses.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BuildProfile {
+    /// For day-to-day development.
+    Dev,
+    /// For builds which get shipped.
+    Release,
+}
+
+impl BuildProfile {
+    pub fn name(self) -> &'static str {
+        match self {
+            BuildProfile::Dev => "dev",
+            BuildProfile::Release => "release",
+        }
+    }
+}
+
+impl Default for BuildProfile {
+    fn default() -> Self {
+        BuildProfile::Dev
+    }
+}
+
+/// The `[profile.dev]` and `[profile.release]` sections of a manifest or config file.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Profiles {
+    #[serde(default)]
+    pub dev: ProfileSettings,
+    #[serde(default)]
+    pub release: ProfileSettings,
+}
+
+impl Profiles {
+    pub fn get(&self, profile: BuildProfile) -> &ProfileSettings {
+        match profile {
+            BuildProfile::Dev => &self.dev,
+            BuildProfile::Release => &self.release,
+        }
+    }
+
+    pub fn get_mut(&mut self, profile: BuildProfile) -> &mut ProfileSettings {
+        match profile {
+            BuildProfile::Dev => &mut self.dev,
+            BuildProfile::Release => &mut self.release,
+        }
+    }
+}
+
+/// The settings of a profile. Anything which isn't set is left as it was.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub struct ProfileSettings {
+    pub total: Option<bool>,
+    pub codegen_opts: Option<Vec<String>>,
+    pub warnings_as_errors: Option<bool>,
+    pub docs: Option<bool>,
+}
+
+/// The settings a build runs with.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BuildConfig {
+    pub profile: BuildProfile,
+    /// Whether every function has to be total (`--total`).
+    pub total: bool,
+    /// Flags for the code generator, each of which gets passed with `--cg-opt` (or `--directive`
+    /// with Idris 2).
+    pub codegen_opts: Vec<String>,
+    /// Whether the build fails if the compiler warns about anything.
+    pub warnings_as_errors: bool,
+    /// Whether to generate the documentation of the package's library.
+    pub docs: bool,
+}
+
+impl BuildConfig {
+    /// The settings of `profile`, starting from its defaults and overridden by the settings in
+    /// each of `layers` in turn (e.g. the manifest's and then the config file's).
+    ///
+    /// Codegen flags mean something different to every backend, so no profile has any by default.
+    pub fn new(profile: BuildProfile, layers: &[&Profiles]) -> Self {
+        let mut config = BuildConfig {
+            profile,
+            total: false,
+            codegen_opts: vec![],
+            warnings_as_errors: false,
+            docs: false,
+        };
+
+        for layer in layers {
+            let settings = layer.get(profile);
+            if let Some(total) = settings.total {
+                config.total = total;
+            }
+            if let Some(codegen_opts) = &settings.codegen_opts {
+                config.codegen_opts = codegen_opts.clone();
+            }
+            if let Some(warnings_as_errors) = settings.warnings_as_errors {
+                config.warnings_as_errors = warnings_as_errors;
+            }
+            if let Some(docs) = settings.docs {
+                config.docs = docs;
+            }
+        }
+
+        config
+    }
+}
+
+/// The environment variable which can point at the compiler to use, overriding everything else.
+pub const COMPILER_ENV: &str = "ELBA_IDRIS";
+
+/// The compilers which are looked for on the `PATH` if none is given, in order.
+const COMPILERS: &[&str] = &["idris", "idris2"];
+
+/// The codegen backends which come built into Idris 2, rather than being separate executables.
+const IDRIS2_BACKENDS: &[&str] = &["chez", "gambit", "javascript", "node", "racket", "refc"];
+
+/// The kinds of Idris compiler, whose command lines and build outputs differ.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CompilerFamily {
+    /// Idris 1, which builds `.ibc` files next to the sources it checks.
+    Idris1,
+    /// Idris 2 (and Blodwen, which it grew out of), which builds `.ttc` files into a separate
+    /// build directory.
+    Idris2,
+}
+
+impl CompilerFamily {
+    /// Makes out which kind of compiler printed `output` with `--version`. Idris 1 only prints
+    /// its version, so anything which doesn't say otherwise is taken to be Idris 1.
+    pub fn detect(output: &str) -> Self {
+        if output.contains("Idris 2") || output.contains("Blodwen") {
+            CompilerFamily::Idris2
+        } else {
+            CompilerFamily::Idris1
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            CompilerFamily::Idris1 => "Idris 1",
+            CompilerFamily::Idris2 => "Idris 2",
+        }
+    }
+
+    /// The extension of the files which checking a module builds.
+    pub fn artifact_ext(self) -> &'static str {
+        match self {
+            CompilerFamily::Idris1 => "ibc",
+            CompilerFamily::Idris2 => "ttc",
+        }
+    }
+
+    /// The backend binaries are built with if nothing else is asked for.
+    pub fn default_backend(self) -> &'static str {
+        match self {
+            CompilerFamily::Idris1 => DEFAULT_BACKEND,
+            CompilerFamily::Idris2 => "chez",
+        }
+    }
+}
 
 /// Information on the compiler executable
-// TODO: Support args and envs
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Compiler {
     /// The location of the exe
     pub path: PathBuf,
+    /// The version of the compiler, going by what it says with `--version`
+    pub version: Version,
+    pub family: CompilerFamily,
 }
 
 impl Compiler {
-    /// Run the compiler at `path` to learn various pieces of information about it.
-    // TODO: Actually lookup the compiler instead of the hard-coded string.
-    pub fn new() -> Compiler {
-        Compiler::default()
+    /// Look up the compiler and run it to learn its version.
+    ///
+    /// The compiler is `given`, if there is one (e.g. from `ELBA_IDRIS` or the config file), or
+    /// else `idris` or `idris2`. Names without a directory are looked up in the directories of
+    /// `search_path`, which is formatted like the `PATH` environment variable. Versions are cached
+    /// in the file `cache`, so a compiler only gets run again once it changes.
+    pub fn find(
+        given: Option<&Path>,
+        search_path: &OsStr,
+        cache: &Path,
+    ) -> Result<Compiler, Error> {
+        let found = match given {
+            Some(path) => locate(path, search_path),
+            None => COMPILERS
+                .iter()
+                .filter_map(|c| locate(Path::new(c), search_path))
+                .next(),
+        };
+        let found = found.ok_or_else(|| {
+            let wanted = match given {
+                Some(path) => path.display().to_string(),
+                None => COMPILERS.join("` or `"),
+            };
+            format_err!(
+                "could not find the Idris compiler `{}` (it can be set with {} or `compiler` in \
+                 the config)",
+                wanted,
+                COMPILER_ENV
+            )
+        })?;
+
+        Compiler::at(found, cache)
+    }
+
+    /// Run the compiler at `path` to learn its version, unless it's already in the file `cache`.
+    pub fn at(path: PathBuf, cache: &Path) -> Result<Compiler, Error> {
+        let key = path.to_string_lossy().into_owned();
+        let modified = fs::metadata(&path)?
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        let mut versions: IndexMap<String, CachedVersion> = fs::read_to_string(cache)
+            .ok()
+            .and_then(|s| toml::from_str(&s).ok())
+            .unwrap_or_default();
+        if let Some(cached) = versions.get(&key) {
+            if cached.modified == modified {
+                return Ok(Compiler {
+                    path,
+                    version: cached.version.clone(),
+                    family: cached.family,
+                });
+            }
+        }
+
+        let output = Command::new(&path)
+            .arg("--version")
+            .output()
+            .context(format_err!("could not run `{}`", path.display()))?;
+        if !output.status.success() {
+            bail!(
+                "could not get the version of `{}`, as `--version` failed:\n{}",
+                path.display(),
+                String::from_utf8_lossy(&output.stderr)
+            )
+        }
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let version = parse_version(&stdout).ok_or_else(|| {
+            format_err!(
+                "could not make out the version of `{}` from `{}`",
+                path.display(),
+                stdout.trim()
+            )
+        })?;
+        let family = CompilerFamily::detect(&stdout);
+
+        versions.insert(
+            key,
+            CachedVersion {
+                modified,
+                version: version.clone(),
+                family,
+            },
+        );
+        // The cache is only there to save time, so failing to write it isn't a problem.
+        let _ = toml::to_string(&versions).map(|s| fs::write(cache, s));
+
+        Ok(Compiler {
+            path,
+            version,
+            family,
+        })
+    }
+
+    /// Checks that the compiler is one which `name` can be built with.
+    pub fn check(&self, name: &Name, constraint: &Constraint) -> Result<(), Error> {
+        if !constraint.satisfies(&self.version) {
+            bail!(
+                "{} needs a version of the Idris compiler which matches `{}`, but `{}` is {} \
+                 version {}",
+                name,
+                constraint,
+                self.path.display(),
+                self.family.name(),
+                self.version
+            )
+        }
+
+        Ok(())
     }
 
     /// Get a process set up to use the found compiler
     pub fn process(&self) -> Command {
         Command::new(&self.path)
     }
+
+    /// Get a process set up to use the found compiler, with the extra flags, packages and
+    /// environment variables of a target
+    pub fn process_with(&self, opts: &TargetOpts) -> Command {
+        let mut process = self.process();
+        for package in &opts.packages {
+            process.args(&["-p", package]);
+        }
+        process.args(&opts.idris_opts).envs(&opts.env);
+        process
+    }
+
+    /// The directory which the artifacts of checking modules in `dir` end up in.
+    pub fn artifact_dir(&self, dir: &Path) -> PathBuf {
+        match self.family {
+            CompilerFamily::Idris1 => dir.to_path_buf(),
+            CompilerFamily::Idris2 => dir.join("build").join("ttc"),
+        }
+    }
+
+    /// The arguments of each compiler invocation it takes to check `files`, run from the directory
+    /// the files are relative to. Idris 1 can check every file at once, but Idris 2 only takes a
+    /// single file at a time.
+    pub fn check_args(&self, files: &[PathBuf]) -> Vec<Vec<OsString>> {
+        match self.family {
+            CompilerFamily::Idris1 => {
+                let mut args = vec![OsString::from("--check")];
+                args.extend(files.iter().map(|f| f.clone().into_os_string()));
+                vec![args]
+            }
+            CompilerFamily::Idris2 => files
+                .iter()
+                .map(|f| vec![OsString::from("--check"), f.clone().into_os_string()])
+                .collect(),
+        }
+    }
+
+    /// Adds `dirs` to the directories which `process` looks up imported modules in.
+    pub fn add_imports(&self, process: &mut Command, dirs: &[PathBuf]) -> Result<(), Error> {
+        match self.family {
+            CompilerFamily::Idris1 => {
+                for dir in dirs {
+                    process.arg("-i").arg(dir);
+                }
+            }
+            CompilerFamily::Idris2 => {
+                // Idris 2 finds its own libraries through `IDRIS2_PATH` too, so anything which is
+                // already in it has to stay.
+                if !dirs.is_empty() {
+                    let mut paths = dirs.to_vec();
+                    if let Some(existing) = env::var_os("IDRIS2_PATH") {
+                        paths.extend(env::split_paths(&existing));
+                    }
+                    process.env("IDRIS2_PATH", env::join_paths(paths)?);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The arguments which generate the documentation of the package described by `ipkg`.
+    pub fn doc_args(&self, ipkg: &Path) -> Vec<OsString> {
+        vec![OsString::from("--mkdoc"), ipkg.as_os_str().to_owned()]
+    }
+
+    /// The directory which the documentation of the package `name` ends up in, when it's generated
+    /// from an ipkg in `dir`.
+    pub fn doc_dir(&self, dir: &Path, name: &str) -> PathBuf {
+        match self.family {
+            CompilerFamily::Idris1 => dir.join(format!("{}_doc", name)),
+            CompilerFamily::Idris2 => dir.join("build").join("docs"),
+        }
+    }
+
+    /// The arguments which generate `output` with `backend`, passing it each of `opts`.
+    pub fn codegen_args(&self, backend: &Backend, output: &Path, opts: &[String]) -> Vec<OsString> {
+        let (codegen, opt) = match self.family {
+            CompilerFamily::Idris1 => ("--codegen", "--cg-opt"),
+            CompilerFamily::Idris2 => ("--cg", "--directive"),
+        };
+
+        let mut args = vec![];
+        for o in opts {
+            args.push(OsString::from(opt));
+            args.push(OsString::from(o));
+        }
+        args.push(OsString::from(codegen));
+        args.push(OsString::from(&backend.name));
+
+        match self.family {
+            CompilerFamily::Idris1 => {
+                args.push(OsString::from("-o"));
+                args.push(output.as_os_str().to_owned());
+            }
+            CompilerFamily::Idris2 => {
+                // Idris 2 puts outputs in its own directory unless it's told otherwise.
+                let dir = output.parent().unwrap_or_else(|| Path::new("."));
+                let name = output.file_name().unwrap_or_default();
+                args.push(OsString::from("--output-dir"));
+                args.push(dir.as_os_str().to_owned());
+                args.push(OsString::from("-o"));
+                args.push(name.to_owned());
+            }
+        }
+
+        args
+    }
+
+    /// The arguments which make a codegen invocation find the sources next to its main module in
+    /// `src` and leave its intermediate artifacts in `build`, rather than wherever it's run from.
+    pub fn codegen_dirs(&self, src: &Path, build: &Path) -> Vec<OsString> {
+        let (sources, artifacts) = match self.family {
+            CompilerFamily::Idris1 => ("--sourcepath", "--ibcsubdir"),
+            CompilerFamily::Idris2 => ("--source-dir", "--build-dir"),
+        };
+
+        vec![
+            OsString::from(sources),
+            src.as_os_str().to_owned(),
+            OsString::from(artifacts),
+            build.as_os_str().to_owned(),
+        ]
+    }
+
+    /// The codegen backends which can be used with this compiler, given the directories in
+    /// `path`, which is formatted like the `PATH` environment variable.
+    pub fn backends(&self, path: &OsStr) -> Vec<Backend> {
+        match self.family {
+            CompilerFamily::Idris1 => backend::discover(path),
+            CompilerFamily::Idris2 => IDRIS2_BACKENDS
+                .iter()
+                .map(|name| Backend {
+                    name: name.to_string(),
+                    path: self.path.clone(),
+                })
+                .collect(),
+        }
+    }
 }
 
-impl Default for Compiler {
-    fn default() -> Self {
-        Compiler {
+#[derive(Deserialize, Serialize)]
+struct CachedVersion {
+    /// When the compiler was last modified, in seconds since the Unix epoch.
+    modified: u64,
+    version: Version,
+    family: CompilerFamily,
+}
+
+/// Finds the executable `path`, looking it up in `search_path` if it's just a name.
+fn locate(path: &Path, search_path: &OsStr) -> Option<PathBuf> {
+    if path.components().count() > 1 {
+        return if is_executable(path) {
+            Some(path.to_path_buf())
+        } else {
+            None
+        };
+    }
+
+    let name = path.with_extension(EXE_EXTENSION);
+    env::split_paths(search_path)
+        .map(|dir| dir.join(&name))
+        .find(|path| is_executable(path))
+}
+
+/// Makes out the version of the compiler from what it prints with `--version`, which is usually
+/// either just the version (`1.3.0`, maybe with a suffix like `-git:abc123`) or something like
+/// `Idris 2, version 0.2.1`.
+fn parse_version(output: &str) -> Option<Version> {
+    let word = output
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .find(|word| word.starts_with(|c: char| c.is_ascii_digit()))?;
+    let mut parts = word
+        .split(|c: char| !c.is_ascii_digit() && c != '.')
+        .next()?
+        .split('.')
+        .filter(|part| !part.is_empty())
+        .map(|part| part.parse::<u64>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    parts.resize(3, 0);
+
+    Some(Version::new(parts[0], parts[1], parts[2]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compiler_target_opts() {
+        let mut opts = TargetOpts::default();
+        opts.idris_opts = vec!["--total".to_owned()];
+        opts.packages = vec!["contrib".to_owned(), "effects".to_owned()];
+
+        let compiler = Compiler {
             path: PathBuf::from("idris"),
-        }
+            version: Version::new(1, 3, 0),
+            family: CompilerFamily::Idris1,
+        };
+        let process = compiler.process_with(&opts);
+        assert_eq!(
+            format!("{:?}", process),
+            r#""idris" "-p" "contrib" "-p" "effects" "--total""#
+        );
+    }
+
+    #[test]
+    fn compiler_version() {
+        assert_eq!(parse_version("1.3.0\n"), Some(Version::new(1, 3, 0)));
+        assert_eq!(
+            parse_version("1.3.1-git:1a2b3c4\n"),
+            Some(Version::new(1, 3, 1))
+        );
+        assert_eq!(
+            parse_version("Idris 2, version 0.2.1-3f9a1b\n"),
+            Some(Version::new(0, 2, 1))
+        );
+        assert_eq!(parse_version("Blodwen 0.1"), Some(Version::new(0, 1, 0)));
+        assert_eq!(parse_version("no version here"), None);
+    }
+
+    #[test]
+    fn compiler_families() {
+        assert_eq!(CompilerFamily::detect("1.3.1\n"), CompilerFamily::Idris1);
+        assert_eq!(
+            CompilerFamily::detect("Idris 2, version 0.2.1\n"),
+            CompilerFamily::Idris2
+        );
+        assert_eq!(
+            CompilerFamily::detect("Blodwen version 0.1\n"),
+            CompilerFamily::Idris2
+        );
+
+        let idris1 = Compiler {
+            path: PathBuf::from("idris"),
+            version: Version::new(1, 3, 1),
+            family: CompilerFamily::Idris1,
+        };
+        let idris2 = Compiler {
+            path: PathBuf::from("idris2"),
+            version: Version::new(0, 2, 1),
+            family: CompilerFamily::Idris2,
+        };
+        let files = vec![PathBuf::from("A.idr"), PathBuf::from("A/B.idr")];
+        assert_eq!(idris1.check_args(&files).len(), 1);
+        assert_eq!(idris2.check_args(&files).len(), 2);
+        assert_eq!(idris1.artifact_dir(Path::new("out")), PathBuf::from("out"));
+        assert_eq!(
+            idris2.artifact_dir(Path::new("out")),
+            PathBuf::from("out/build/ttc")
+        );
+
+        let node = Backend {
+            name: "node".to_owned(),
+            path: PathBuf::from("idris2"),
+        };
+        let opts = vec!["-O2".to_owned()];
+        let output = Path::new("target/node/hello.js");
+        let os = |args: &[&str]| args.iter().map(OsString::from).collect::<Vec<_>>();
+        assert_eq!(
+            idris1.codegen_args(&node, output, &opts),
+            os(&[
+                "--cg-opt",
+                "-O2",
+                "--codegen",
+                "node",
+                "-o",
+                "target/node/hello.js"
+            ])
+        );
+        assert_eq!(
+            idris2.codegen_args(&node, output, &opts),
+            os(&[
+                "--directive",
+                "-O2",
+                "--cg",
+                "node",
+                "--output-dir",
+                "target/node",
+                "-o",
+                "hello.js"
+            ])
+        );
+        assert!(idris2.backends(OsStr::new("")).contains(&node));
+    }
+
+    #[test]
+    fn profile_layers() {
+        let mut manifest = Profiles::default();
+        manifest.release.total = Some(true);
+        manifest.release.warnings_as_errors = Some(true);
+        manifest.release.docs = Some(true);
+        let mut config = Profiles::default();
+        config.release.warnings_as_errors = Some(false);
+        config.release.codegen_opts = Some(vec!["-O3".to_owned()]);
+
+        let dev = BuildConfig::new(BuildProfile::Dev, &[&manifest, &config]);
+        assert!(!dev.total);
+        assert!(!dev.docs);
+        assert!(dev.codegen_opts.is_empty());
+
+        let release = BuildConfig::new(BuildProfile::Release, &[&manifest, &config]);
+        assert!(release.total);
+        assert!(!release.warnings_as_errors);
+        assert!(release.docs);
+        assert_eq!(release.codegen_opts, vec!["-O3"]);
+        assert!(BuildConfig::new(BuildProfile::Release, &[])
+            .codegen_opts
+            .is_empty());
     }
 }
@@ -1,5 +1,8 @@
 //! Actually building Idris packages.
 
+pub mod alias;
+pub mod backend;
 pub mod compile;
 pub mod context;
 pub mod job;
+pub mod module;
@@ -0,0 +1,200 @@
+//! Finding the modules of packages, and making sure that packages don't step on each other's.
+//!
+//! Every package declares the root modules which all of its library's modules live under (by
+//! default, `Group.Name`). Since Idris only has a single namespace for modules, two packages in
+//! the same build can't export modules under the same root; this is checked before anything gets
+//! built, rather than leaving it to the compiler to trip over halfway through the build.
+
+use failure::Error;
+use package::{manifest::Manifest, Summary};
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// The path of a module's file with the extension `ext`, like `Data/Vect.ibc` for `Data.Vect`.
+pub fn module_path(module: &str, ext: &str) -> PathBuf {
+    let mut path = module.split('.').collect::<PathBuf>();
+    path.set_extension(ext);
+    path
+}
+
+/// Finds the modules in `dir` whose files have one of the extensions in `exts`, along with the
+/// path of each file relative to `dir`, sorted by path.
+pub fn find_modules(dir: &Path, exts: &[&str]) -> Vec<(PathBuf, String)> {
+    let mut modules = WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .filter_map(|e| {
+            let ext = e.path().extension()?.to_str()?;
+            if !exts.contains(&ext) {
+                return None;
+            }
+
+            let path = e.path().strip_prefix(dir).ok()?.to_path_buf();
+            let parts = path
+                .with_extension("")
+                .components()
+                .map(|c| c.as_os_str().to_str().map(|s| s.to_owned()))
+                .collect::<Option<Vec<_>>>()?;
+            Some((path, parts.join(".")))
+        })
+        .collect::<Vec<_>>();
+    modules.sort();
+    modules
+}
+
+/// Whether `module` is `root` itself or one of the modules under it.
+pub fn is_under(module: &str, root: &str) -> bool {
+    module == root || (module.starts_with(root) && module[root.len()..].starts_with('.'))
+}
+
+/// Finds the source files of the library of the package at `root`, along with their modules, like
+/// `find_modules`. The main files of binaries can live next to the library's modules, so they're
+/// left out.
+pub fn lib_sources(root: &Path, manifest: &Manifest) -> Vec<(PathBuf, String)> {
+    let lib = match manifest.lib() {
+        Some(lib) => root.join(lib.path()),
+        None => return vec![],
+    };
+    let mains = manifest
+        .targets()
+        .bins()
+        .iter()
+        .map(|bin| root.join(bin.main()))
+        .collect::<Vec<_>>();
+
+    find_modules(&lib, &["idr", "lidr"])
+        .into_iter()
+        .filter(|(path, _)| !mains.contains(&lib.join(path)))
+        .collect()
+}
+
+/// Checks that every source file of the library of the package at `root` is under one of the
+/// modules the package declares.
+pub fn check_sources(root: &Path, manifest: &Manifest) -> Result<(), Error> {
+    let lib = match manifest.lib() {
+        Some(lib) => root.join(lib.path()),
+        None => return Ok(()),
+    };
+    let roots = manifest.modules();
+
+    for (path, module) in lib_sources(root, manifest) {
+        if !roots.iter().any(|root| is_under(&module, root)) {
+            bail!(
+                "`{}` is the module `{}`, which isn't under any of the modules of {} (`{}`)",
+                lib.join(path).display(),
+                module,
+                manifest.name(),
+                roots.join("`, `")
+            )
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks that no two packages in a build export modules under the same module path, given the
+/// root modules of each package.
+///
+/// Roots conflict if they're the same or one is under the other, since either way, both packages
+/// could end up trying to define the same module.
+pub fn check_conflicts(pkgs: &[(Summary, Vec<String>)]) -> Result<(), Error> {
+    for (i, (a, a_roots)) in pkgs.iter().enumerate() {
+        for (b, b_roots) in &pkgs[i + 1..] {
+            for a_root in a_roots {
+                for b_root in b_roots {
+                    let shared = if is_under(a_root, b_root) {
+                        b_root
+                    } else if is_under(b_root, a_root) {
+                        a_root
+                    } else {
+                        continue;
+                    };
+
+                    bail!(
+                        "{} and {} both export modules under `{}` (as `{}` and `{}`); one of \
+                         them should be given an alias in [aliases]",
+                        a,
+                        b,
+                        shared,
+                        a_root,
+                        b_root
+                    )
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use package::{resolution::Resolution, PackageId};
+    use semver::Version;
+    use std::{env, fs, str::FromStr};
+
+    fn summary(name: &str) -> Summary {
+        let id = PackageId::new(name.parse().unwrap(), Resolution::Root);
+        Summary::new(id, Version::parse("1.0.0").unwrap())
+    }
+
+    #[test]
+    fn module_under() {
+        assert!(is_under("Me.Lightyear", "Me.Lightyear"));
+        assert!(is_under("Me.Lightyear.Parser", "Me.Lightyear"));
+        assert!(!is_under("Me.LightyearX", "Me.Lightyear"));
+        assert!(!is_under("Me", "Me.Lightyear"));
+    }
+
+    #[test]
+    fn module_conflicts() {
+        let pkgs = vec![
+            (summary("me/lightyear"), vec!["Me.Lightyear".to_owned()]),
+            (summary("you/lightyear"), vec!["You.Lightyear".to_owned()]),
+        ];
+        assert!(check_conflicts(&pkgs).is_ok());
+
+        let pkgs = vec![
+            (summary("me/lightyear"), vec!["Me.Lightyear".to_owned()]),
+            (summary("me/all"), vec!["Me".to_owned()]),
+        ];
+        let err = check_conflicts(&pkgs).unwrap_err().to_string();
+        assert!(err.contains("me/lightyear") && err.contains("me/all"));
+    }
+
+    #[test]
+    fn module_sources() {
+        let root = env::temp_dir().join("elba-module-test-sources");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("src/Me/Lightyear")).unwrap();
+        fs::write(root.join("src/Me/Lightyear.idr"), "module Me.Lightyear").unwrap();
+        fs::write(root.join("src/Me/Lightyear/Parser.lidr"), "").unwrap();
+        fs::write(root.join("src/Me/Lightyear/README.md"), "").unwrap();
+
+        let manifest = |modules: &str| {
+            Manifest::from_str(&format!(
+                "[package]\nname = 'me/lightyear'\nversion = '1.0.0'\nauthors = []\n\n\
+                 [targets.lib]\npath = 'src/'\n{}",
+                modules
+            ))
+            .unwrap()
+        };
+
+        assert_eq!(
+            find_modules(&root.join("src"), &["idr", "lidr"]),
+            vec![
+                (PathBuf::from("Me/Lightyear.idr"), "Me.Lightyear".to_owned()),
+                (
+                    PathBuf::from("Me/Lightyear/Parser.lidr"),
+                    "Me.Lightyear.Parser".to_owned()
+                ),
+            ]
+        );
+        assert!(check_sources(&root, &manifest("")).is_ok());
+        assert!(check_sources(&root, &manifest("modules = ['Me.Lightyear.Parser']")).is_err());
+
+        let _ = fs::remove_dir_all(&root);
+    }
+}
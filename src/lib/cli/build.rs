@@ -1,41 +1,412 @@
-use failure::ResultExt;
-use package::{
-    lockfile::LockfileToml,
-    manifest::Manifest,
-    resolution::{DirectRes, IndexRes},
+use super::Env;
+use build::{
+    alias::{self, import_paths, Alias},
+    backend,
+    compile::{BuildDir, CodegenInvocation, CompileInvocation, DocInvocation},
+    context::{BuildConfig, BuildContext, BuildProfile, Compiler, Profiles, COMPILER_ENV},
+    module::{self, module_path},
 };
+use failure::ResultExt;
+use indexmap::IndexMap;
+use package::{lockfile::LockfileToml, manifest::Manifest, Name, Summary};
 use resolve::{solve::Solve, Resolver};
-use retrieve::cache::Cache;
+use retrieve::cache::{Build, Cache};
 use retrieve::Retriever;
-use slog::Logger;
-use std::{fs, io::prelude::*, path::PathBuf, str::FromStr};
+use std::{
+    env,
+    ffi::OsString,
+    fs,
+    io::prelude::*,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 use toml;
-use util::errors::Res;
+use util::{config::Config, errors::Res, lock::DirLock, net::Net};
 
+#[derive(Clone)]
 pub struct BuildCtx {
     pub project: PathBuf,
-    pub indices: Vec<DirectRes>,
-    pub global_cache: PathBuf,
-    pub logger: Logger,
+    pub env: Env,
+    pub profile: BuildProfile,
+    /// The profile settings from the config file, which override the manifest's.
+    pub profiles: Profiles,
+    /// The codegen backends to build every binary with, instead of the ones in the manifest.
+    pub backends: Vec<String>,
+    /// The compiler to build with, if one was asked for rather than looked up.
+    pub compiler: Option<PathBuf>,
+    /// The directories which the compiler and codegen backends are looked up in, formatted like
+    /// the `PATH` environment variable.
+    pub path: OsString,
 }
 
-pub fn lock(ctx: &BuildCtx) -> Res<(Cache, Solve)> {
-    let mut manifest = fs::File::open(ctx.project.join("elba.toml"))
-        .context(format_err!("failed to read manifest file."))?;
-    let mut contents = String::new();
-    manifest.read_to_string(&mut contents)?;
+impl BuildCtx {
+    /// The context for building `project` in the dev profile, with the settings in the config.
+    /// The compiler is the one `ELBA_IDRIS` points at, if it's set, and else the config's.
+    pub fn from_config(c: &Config, project: PathBuf) -> Res<Self> {
+        Ok(BuildCtx {
+            project,
+            env: Env::from_config(c)?,
+            profile: BuildProfile::Dev,
+            profiles: c
+                .profile
+                .as_ref()
+                .map(|p| p.build.clone())
+                .unwrap_or_default(),
+            backends: vec![],
+            compiler: env::var_os(COMPILER_ENV)
+                .map(PathBuf::from)
+                .or_else(|| c.compiler.clone()),
+            path: env::var_os("PATH").unwrap_or_default(),
+        })
+    }
+}
 
-    let manifest = Manifest::from_str(&contents).context(format_err!("invalid manifest format"))?;
+/// Builds the package: makes sure the compiler is a version it can be built with, locks its
+/// dependencies, checks that its modules don't conflict with any of theirs, builds the libraries
+/// of its dependencies and then its own, and generates each of its binaries with each of their
+/// backends. Everything of its own goes into the build directory of the selected profile: the
+/// library's artifacts into `lib/`, its documentation into `doc/` (if the profile asks for it),
+/// and the binaries into `<backend>/<name>`.
+pub fn build(ctx: &BuildCtx) -> Res<BuildConfig> {
+    let manifest = read_manifest(&ctx.project)?;
+    let compiler = find_compiler(ctx, &manifest)?;
+    let (cache, solve) = lock(ctx)?;
+    let sources = load_sources(ctx, &cache, &solve)?;
+    check_modules(ctx, &solve, &sources)?;
 
-    let def_index = if ctx.indices.is_empty() {
-        IndexRes::from_str("index+dir+file://none").unwrap()
-    } else {
-        ctx.indices[0].clone().into()
+    let config = build_config(ctx, &manifest);
+    let target = target_dir(ctx);
+    fs::create_dir_all(&target).context(format_err!("could not create `{}`", target.display()))?;
+
+    let mut imports = build_deps(ctx, &compiler, &config, &cache, &solve, &sources)?;
+
+    if let Some(lib) = manifest.lib() {
+        let out = target.join("lib");
+        let _ = fs::remove_dir_all(&out);
+        let build_dir = BuildDir::new(DirLock::acquire(&out)?)?;
+        let mut bcx = BuildContext {
+            compiler: compiler.clone(),
+            resolve: &solve,
+            opts: lib.opts().clone(),
+            config: config.clone(),
+        };
+        CompileInvocation::new(
+            ctx.project.join(lib.path()),
+            module::lib_sources(&ctx.project, &manifest),
+            imports.clone(),
+            build_dir,
+        )
+        .execute(&mut bcx)?;
+
+        ctx.env.shell.status(
+            "compiled",
+            &format!("{} ({})", manifest.name(), out.display()),
+        );
+
+        if config.docs {
+            let doc = target.join("doc");
+            let _ = fs::remove_dir_all(&doc);
+            DocInvocation::new(
+                doc_name(manifest.name()),
+                ctx.project.join(lib.path()),
+                module::lib_sources(&ctx.project, &manifest),
+                imports.clone(),
+                BuildDir::new(DirLock::acquire(&doc)?)?,
+            )
+            .execute(&mut bcx)?;
+
+            ctx.env.shell.status(
+                "documented",
+                &format!("{} ({})", manifest.name(), doc.display()),
+            );
+        }
+
+        imports.push(out);
+    }
+
+    let available = compiler.backends(&ctx.path);
+
+    for bin in manifest.targets().bins() {
+        let names = if !ctx.backends.is_empty() {
+            ctx.backends.clone()
+        } else if !bin.backends().is_empty() {
+            bin.backends().to_vec()
+        } else {
+            vec![compiler.family.default_backend().to_owned()]
+        };
+
+        let mut bcx = BuildContext {
+            compiler: compiler.clone(),
+            resolve: &solve,
+            opts: bin.opts().clone(),
+            config: config.clone(),
+        };
+        for backend in backend::select(&available, &names)? {
+            let output = target.join(&backend.name).join(backend.output(bin.name()));
+            let name = backend.name.clone();
+            let build_dir = BuildDir::new(DirLock::acquire(&target.join(&backend.name))?)?;
+            CodegenInvocation::new(
+                ctx.project.join(bin.main()),
+                output.clone(),
+                backend,
+                imports.clone(),
+                build_dir,
+            )
+            .execute(&mut bcx)?;
+
+            ctx.env.shell.status(
+                "generated",
+                &format!("{} with {} ({})", bin.name(), name, output.display()),
+            );
+        }
+    }
+
+    ctx.env.shell.status(
+        "finished",
+        &format!(
+            "{} {} [{}]",
+            manifest.name(),
+            manifest.version(),
+            config.profile.name()
+        ),
+    );
+
+    Ok(config)
+}
+
+/// Builds the package with the documentation of its library, whatever the selected profile says
+/// about docs, and returns the directory the documentation went into.
+pub fn doc(ctx: &BuildCtx) -> Res<PathBuf> {
+    let manifest = read_manifest(&ctx.project)?;
+    if manifest.lib().is_none() {
+        bail!("{} has no library to document", manifest.name())
+    }
+
+    let mut ctx = ctx.clone();
+    let profile = ctx.profile;
+    ctx.profiles.get_mut(profile).docs = Some(true);
+    build(&ctx)?;
+
+    Ok(target_dir(&ctx).join("doc"))
+}
+
+/// The name of the package `name` in the ipkg its documentation is generated from, which has to
+/// be a valid Idris identifier.
+fn doc_name(name: &Name) -> String {
+    format!("{}_{}", name.group(), name.name()).replace('-', "_")
+}
+
+/// Builds the library of every package which the root (transitively) depends on into the global
+/// cache, each one after its own dependencies, along with the aliases of the ones the root
+/// aliases. `sources` are where the packages in `solve` were loaded (see `load_sources`).
+///
+/// Returns the import path of the root: the builds of its dependencies, with the builds of the
+/// aliases in place of the packages they alias. Builds which are already in the cache are used as
+/// they are.
+pub fn build_deps(
+    ctx: &BuildCtx,
+    compiler: &Compiler,
+    config: &BuildConfig,
+    cache: &Cache,
+    solve: &Solve,
+    sources: &IndexMap<Summary, DirLock>,
+) -> Res<Vec<PathBuf>> {
+    let manifest = read_manifest(&ctx.project)?;
+    let root = manifest.summary();
+
+    // Warnings in dependencies are for their authors to deal with, not the root's.
+    let config = BuildConfig {
+        warnings_as_errors: false,
+        ..config.clone()
     };
+    let ext = compiler.family.artifact_ext();
+
+    let mut builds = indexmap!();
+    for sum in solve.deps_first(&root).into_iter().flatten() {
+        let src = match sources.get(sum) {
+            Some(src) => src.path(),
+            None => continue,
+        };
+        let dep = read_manifest(src)?;
+        let lib = match dep.lib() {
+            Some(lib) => lib,
+            None => continue,
+        };
+
+        let modules = module::lib_sources(src, &dep);
+        let mut bcx = BuildContext {
+            compiler: compiler.clone(),
+            resolve: solve,
+            opts: lib.opts().clone(),
+            config: config.clone(),
+        };
+        let lock = cache
+            .checkout_build(Build::new(sum.clone(), &bcx))?
+            .binary_path;
+        let out = lock.path().to_path_buf();
+        let built = modules
+            .iter()
+            .all(|(_, module)| out.join(module_path(module, ext)).exists());
+
+        if !built {
+            let imports = dep_builds(solve, sum, &builds)
+                .into_iter()
+                .map(|(_, build)| build)
+                .collect();
+            CompileInvocation::new(src.join(lib.path()), modules, imports, BuildDir::new(lock)?)
+                .execute(&mut bcx)?;
+
+            ctx.env
+                .shell
+                .status("compiled", &format!("{} ({})", sum, out.display()));
+        }
+
+        builds.insert(sum.clone(), out);
+    }
+
+    let mut aliases = indexmap!();
+    for sum in builds.keys() {
+        let prefix = match manifest.aliases().get(sum.name()) {
+            Some(prefix) => prefix,
+            None => continue,
+        };
+        let src = sources[sum].path();
+        let dep = read_manifest(src)?;
+        let lib = match dep.lib() {
+            Some(lib) => lib,
+            None => continue,
+        };
+        let roots = dep.modules();
+        if roots.len() != 1 {
+            bail!(
+                "{} can't be aliased as `{}`, since it has more than one root module",
+                sum,
+                prefix
+            )
+        }
+
+        // The alias is a copy of the package under another name, so it's built against the
+        // package's dependencies rather than the package itself.
+        let mut bcx = BuildContext {
+            compiler: compiler.clone(),
+            resolve: solve,
+            opts: lib.opts().clone(),
+            config: config.clone(),
+        };
+        let lock = cache.checkout_alias(&Build::new(sum.clone(), &bcx), prefix)?;
+        let out = lock.path().to_path_buf();
+        let deps = dep_builds(solve, sum, &builds)
+            .into_iter()
+            .map(|(_, build)| build)
+            .collect::<Vec<_>>();
+        let alias = Alias::new(sum.name().clone(), prefix.clone());
+        alias::build(
+            &alias,
+            &roots[0],
+            &src.join(lib.path()),
+            &module::lib_sources(src, &dep),
+            &deps,
+            lock,
+            &mut bcx,
+        )?;
+
+        aliases.insert(sum.name().clone(), out);
+    }
+
+    let mut imports = import_paths(&dep_builds(solve, &root, &builds), &aliases);
+
+    // The builds of the root's other dependencies can't be loaded without the packages they use,
+    // so those stay on the import path even when the root aliases them (`check_modules` counts
+    // their modules as well as their aliases).
+    for (sum, build) in &builds {
+        if aliases.contains_key(sum.name()) && used_by_deps(solve, &root, sum) {
+            imports.push(build.clone());
+        }
+    }
+
+    Ok(imports)
+}
+
+/// Whether a package in `solve` other than the `root` depends on `pkg`.
+fn used_by_deps(solve: &Solve, root: &Summary, pkg: &Summary) -> bool {
+    solve
+        .iter()
+        .filter(|sum| *sum != root && *sum != pkg)
+        .any(|sum| {
+            solve
+                .sub_tree(sum)
+                .into_iter()
+                .flatten()
+                .any(|dep| dep == pkg)
+        })
+}
+
+/// The builds of every package which `pkg` (transitively) depends on, which are where the modules
+/// it imports get looked up.
+fn dep_builds(
+    solve: &Solve,
+    pkg: &Summary,
+    builds: &IndexMap<Summary, PathBuf>,
+) -> Vec<(Name, PathBuf)> {
+    solve
+        .sub_tree(pkg)
+        .into_iter()
+        .flatten()
+        .filter(|dep| *dep != pkg)
+        .filter_map(|dep| builds.get(dep).map(|b| (dep.name().clone(), b.clone())))
+        .collect()
+}
+
+/// Finds the compiler to build the package with, and checks that it's a version the package can
+/// be built with.
+pub fn find_compiler(ctx: &BuildCtx, manifest: &Manifest) -> Res<Compiler> {
+    let compiler = Compiler::find(
+        ctx.compiler.as_ref().map(|p| p.as_path()),
+        &ctx.path,
+        &ctx.env.global_cache.join("compilers.toml"),
+    )?;
+    if let Some(constraint) = manifest.idris_version() {
+        compiler.check(manifest.name(), constraint)?;
+    }
+
+    Ok(compiler)
+}
+
+/// The settings to build the package with in the selected profile, from the profile's defaults,
+/// the manifest and the config file, in that order.
+pub fn build_config(ctx: &BuildCtx, manifest: &Manifest) -> BuildConfig {
+    BuildConfig::new(ctx.profile, &[manifest.profiles(), &ctx.profiles])
+}
+
+/// Where the outputs of builds in the selected profile go. Each profile gets its own directory, so
+/// that switching between them doesn't throw away the other's builds.
+pub fn target_dir(ctx: &BuildCtx) -> PathBuf {
+    ctx.project.join("target").join(ctx.profile.name())
+}
+
+pub fn lock(ctx: &BuildCtx) -> Res<(Cache, Solve)> {
+    let manifest = read_manifest(&ctx.project)?;
+
+    let def_index = ctx.env.indices.default_or_dummy();
+    let reses = ctx
+        .env
+        .indices
+        .indices()
+        .map(|i| i.res.clone())
+        .collect::<Vec<_>>();
 
     // TODO: Get indices from config & cache.
-    let cache = Cache::from_disk(&ctx.logger, ctx.global_cache.clone(), def_index.clone());
-    let indices = cache.get_indices(&ctx.indices);
+    let cache = Cache::from_disk(
+        &ctx.env.logger,
+        ctx.env.global_cache.clone(),
+        def_index.clone(),
+    )
+    .with_names(ctx.env.indices.clone())
+    .with_limits(ctx.env.extract)
+    .with_net(Net::new(ctx.env.net.clone(), ctx.env.credentials.clone())?)
+    .with_policy(ctx.env.index_update)
+    .with_shell(ctx.env.shell.clone());
+    let indices = cache.get_indices(&reses)?;
 
     let op = || -> Res<Solve> {
         let mut f = fs::File::open(&ctx.project.join("elba.lock"))?;
@@ -61,7 +432,7 @@ pub fn lock(ctx: &BuildCtx) -> Res<(Cache, Solve)> {
         .chain(manifest.dev_dependencies.iter())
     {
         let dep = dep.clone();
-        let (pid, c) = dep.into_dep(def_index.clone(), n.clone());
+        let (pid, c) = dep.into_dep(def_index.clone(), &ctx.env.indices, n.clone())?;
         deps.push((pid, c));
     }
 
@@ -83,3 +454,77 @@ pub fn lock(ctx: &BuildCtx) -> Res<(Cache, Solve)> {
 
     Ok((cache, solve))
 }
+
+/// Loads every package in `solve` other than the root into the cache, returning the directory
+/// each one was loaded into.
+pub fn load_sources(
+    ctx: &BuildCtx,
+    cache: &Cache,
+    solve: &Solve,
+) -> Res<IndexMap<Summary, DirLock>> {
+    let reses = ctx
+        .env
+        .indices
+        .indices()
+        .map(|i| i.res.clone())
+        .collect::<Vec<_>>();
+    let mut indices = cache.get_indices(&reses)?;
+
+    cache.load_all(solve, &mut indices)
+}
+
+/// Makes sure that the sources of the root package are under the modules it declares, and that
+/// no two packages in `solve` export modules under the same module path. `sources` are where the
+/// packages were loaded (see `load_sources`).
+///
+/// Dependencies which the root aliases count as exporting their aliases instead of their own
+/// modules, unless another dependency uses them, in which case they export both.
+pub fn check_modules(
+    ctx: &BuildCtx,
+    solve: &Solve,
+    sources: &IndexMap<Summary, DirLock>,
+) -> Res<()> {
+    let manifest = read_manifest(&ctx.project)?;
+    module::check_sources(&ctx.project, &manifest)?;
+    let root = manifest.summary();
+
+    let mut pkgs = vec![];
+    for sum in solve.iter() {
+        let dir = match sources.get(sum) {
+            Some(dir) => dir,
+            None => {
+                pkgs.push((sum.clone(), manifest.modules()));
+                continue;
+            }
+        };
+
+        let own = read_manifest(dir.path())?.modules();
+        let modules = match manifest.aliases().get(sum.name()) {
+            Some(prefix) if used_by_deps(solve, &root, sum) => {
+                let mut modules = vec![prefix.clone()];
+                modules.extend(own);
+                modules
+            }
+            Some(prefix) => vec![prefix.clone()],
+            None => own,
+        };
+        pkgs.push((sum.clone(), modules));
+    }
+
+    module::check_conflicts(&pkgs)
+}
+
+/// Reads the manifest of the package at `project`, filling in and checking its targets.
+pub fn read_manifest(project: &Path) -> Res<Manifest> {
+    let mut manifest = fs::File::open(project.join("elba.toml"))
+        .context(format_err!("failed to read manifest file."))?;
+    let mut contents = String::new();
+    manifest.read_to_string(&mut contents)?;
+
+    let mut manifest =
+        Manifest::from_str(&contents).context(format_err!("invalid manifest format"))?;
+    manifest.fill_targets(project);
+    manifest.validate_targets(project)?;
+
+    Ok(manifest)
+}
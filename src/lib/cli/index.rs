@@ -0,0 +1,26 @@
+use super::Env;
+use retrieve::cache::Cache;
+use util::{errors::Res, net::Net};
+
+pub struct IndexCtx {
+    pub env: Env,
+}
+
+/// Updates every configured index, regardless of how recently it was last updated.
+pub fn update(ctx: &IndexCtx) -> Res<()> {
+    let def_index = ctx.env.indices.default_or_dummy();
+    let reses = ctx
+        .env
+        .indices
+        .indices()
+        .map(|i| i.res.clone())
+        .collect::<Vec<_>>();
+
+    let cache = Cache::from_disk(&ctx.env.logger, ctx.env.global_cache.clone(), def_index)
+        .with_limits(ctx.env.extract)
+        .with_net(Net::new(ctx.env.net.clone(), ctx.env.credentials.clone())?)
+        .with_shell(ctx.env.shell.clone());
+    cache.update_indices(&reses)?;
+
+    Ok(())
+}
@@ -0,0 +1,34 @@
+use failure::ResultExt;
+use package::resolution::DirectRes;
+use std::path::PathBuf;
+use util::{credentials::Credentials, errors::Res};
+
+pub struct LoginCtx {
+    /// The credentials file to write to.
+    pub credentials: PathBuf,
+    pub index: DirectRes,
+    pub token: String,
+}
+
+/// Saves the API token for the given index, replacing any token which was already there for the
+/// same host.
+pub fn login(ctx: LoginCtx) -> Res<()> {
+    let url = match &ctx.index {
+        DirectRes::Tar { url, .. } => url,
+        DirectRes::Git { repo, .. } => repo,
+        DirectRes::Sparse { url } => url,
+        DirectRes::Dir { .. } => bail!("local indices don't need a login"),
+    };
+    let host = url
+        .host_str()
+        .ok_or_else(|| format_err!("the index `{}` has no host", ctx.index))?
+        .to_owned();
+
+    let mut creds = Credentials::load(&ctx.credentials)?;
+    creds.tokens.insert(host, ctx.token.trim().to_owned());
+    creds
+        .save(&ctx.credentials)
+        .context(format_err!("could not save credentials"))?;
+
+    Ok(())
+}
@@ -1,4 +1,49 @@
 //! Handlers for all of the command-line actions of the binary.
 
 pub mod build;
+pub mod index;
+pub mod login;
 pub mod new;
+pub mod package;
+pub mod publish;
+pub mod search;
+pub mod yank;
+
+use index::IndexNames;
+use retrieve::cache::UpdatePolicy;
+use slog::{Discard, Logger};
+use std::path::PathBuf;
+use util::{
+    archive::ExtractLimits, config::Config, credentials::Credentials, errors::Res, net::NetConfig,
+    shell::Shell,
+};
+
+/// The settings which every command that deals with indices or packages needs, all of which come
+/// from the config.
+#[derive(Clone)]
+pub struct Env {
+    pub indices: IndexNames,
+    pub global_cache: PathBuf,
+    pub extract: ExtractLimits,
+    pub net: NetConfig,
+    pub index_update: UpdatePolicy,
+    pub credentials: Credentials,
+    pub shell: Shell,
+    pub logger: Logger,
+}
+
+impl Env {
+    pub fn from_config(c: &Config) -> Res<Self> {
+        Ok(Env {
+            indices: c.indices.clone(),
+            global_cache: c.directories.cache.clone(),
+            extract: c.extract,
+            net: c.net.clone(),
+            index_update: c.index_update,
+            credentials: Credentials::load(&c.directories.rest.join("credentials.toml"))?,
+            shell: Shell::new(c.term.verbosity, c.term.color),
+            // TODO: Proper log output etc.
+            logger: Logger::root(Discard, o!()),
+        })
+    }
+}
@@ -1,5 +1,5 @@
+use build::module::module_path;
 use failure::ResultExt;
-use inflector::Inflector;
 use package::Name;
 use std::{fs, path::PathBuf};
 use util::{errors::Res, write};
@@ -68,22 +68,10 @@ authors = [{}]
     )?;
 
     if !ctx.bin {
-        fs::create_dir_all(path.join(format!("src/{}", name.group().to_pascal_case())))
+        let module = path.join("src").join(module_path(&name.module(), "idr"));
+        fs::create_dir_all(module.parent().unwrap())
             .context(format_err!("could not create dir {}", path.display()))?;
-        write(
-            &path.join(format!(
-                "src/{}/{}.idr",
-                name.group().to_pascal_case(),
-                name.name().to_pascal_case()
-            )),
-            format!(
-                r#"module {}.{}
-
-"#,
-                name.group().to_pascal_case(),
-                name.name().to_pascal_case()
-            ).as_bytes(),
-        )?;
+        write(&module, format!("module {}\n\n", name.module()).as_bytes())?;
     } else {
         fs::create_dir_all(path.join("src"))
             .context(format_err!("could not create dir {}", path.display()))?;
@@ -0,0 +1,125 @@
+use super::build::{build, read_manifest, BuildCtx};
+use failure::ResultExt;
+use flate2::read::GzDecoder;
+use package::{manifest::Manifest, Checksum, ChecksumFmt};
+use sha2::{Digest, Sha256};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+use tar::Archive;
+use util::{archive, errors::Res, hexify_hash, lock::DirLock};
+
+/// A package's tarball, as a `DirectRes::Tar` would later download and unpack it.
+#[derive(Debug)]
+pub struct Packaged {
+    /// The files in the tarball, relative to the root of the project.
+    pub files: Vec<PathBuf>,
+    pub tarball: Vec<u8>,
+    pub cksum: Checksum,
+    /// Where the tarball was written to.
+    pub path: PathBuf,
+}
+
+/// Lists the files which would be packaged, following the `include` and `exclude` globs of the
+/// manifest.
+pub fn list(project: &Path) -> Res<Vec<PathBuf>> {
+    let manifest = read_manifest(project)?;
+    archive::package_files(project, manifest.include(), manifest.exclude())
+}
+
+/// Packs the project into `target/package/<group>_<name>-<version>.tar.gz`.
+///
+/// The tarball only depends on which files get packaged and what's in them, so packaging the same
+/// tree twice gives byte-identical archives.
+pub fn pack(project: &Path, manifest: &Manifest) -> Res<Packaged> {
+    let files = archive::package_files(project, manifest.include(), manifest.exclude())?;
+
+    // The index entry points at the readme, so it has to make it into the tarball.
+    if let Some(readme) = manifest.readme() {
+        if !project.join(readme).is_file() {
+            bail!("the readme `{}` doesn't exist", readme.display())
+        }
+        if !files.iter().any(|f| f == readme) {
+            bail!(
+                "the readme `{}` isn't included in the package",
+                readme.display()
+            )
+        }
+    }
+
+    let tarball = archive::pack(project, &files)?;
+    let cksum = Checksum {
+        fmt: ChecksumFmt::Sha256,
+        hash: hexify_hash(Sha256::digest(&tarball).as_slice()),
+    };
+
+    let path = project
+        .join("target")
+        .join("package")
+        .join(format!("{}.tar.gz", package_name(manifest)));
+    fs::create_dir_all(path.parent().unwrap())?;
+    fs::write(&path, &tarball).context(format_err!("could not write `{}`", path.display()))?;
+
+    Ok(Packaged {
+        files,
+        tarball,
+        cksum,
+        path,
+    })
+}
+
+/// Packs the project, and if `verify` is set, makes sure that the packaged copy builds on its own.
+pub fn package(ctx: &BuildCtx, verify: bool) -> Res<Packaged> {
+    let manifest = read_manifest(&ctx.project)?;
+    let packaged = pack(&ctx.project, &manifest)?;
+
+    if verify {
+        self::verify(ctx, &manifest, &packaged)?;
+    }
+
+    ctx.env.shell.status(
+        "packaged",
+        &format!(
+            "{} {} ({})",
+            manifest.name(),
+            manifest.version(),
+            packaged.cksum
+        ),
+    );
+
+    Ok(packaged)
+}
+
+/// Unpacks the tarball into `target/package/<group>_<name>-<version>/` and builds it there, so
+/// that a file which is needed but didn't make it into the package is caught before anyone else
+/// tries to use it.
+fn verify(ctx: &BuildCtx, manifest: &Manifest, packaged: &Packaged) -> Res<()> {
+    let dir = packaged.path.with_file_name(package_name(manifest));
+    let _lock = DirLock::acquire(&dir)?;
+    let _ = fs::remove_dir_all(&dir);
+
+    let mut tarball = Archive::new(GzDecoder::new(&packaged.tarball[..]));
+    let res = archive::unpack(&mut tarball, &dir, &ctx.env.extract).and_then(|_| {
+        let bcx = BuildCtx {
+            project: dir.clone(),
+            ..ctx.clone()
+        };
+        build(&bcx).map(|_| ())
+    });
+    let _ = fs::remove_dir_all(&dir);
+
+    res.context(format_err!(
+        "the packaged copy of {} {} doesn't build on its own",
+        manifest.name(),
+        manifest.version()
+    ))?;
+
+    Ok(())
+}
+
+/// The name of the package's tarball, without the extension.
+fn package_name(manifest: &Manifest) -> String {
+    let name = manifest.name();
+    format!("{}_{}-{}", name.group(), name.name(), manifest.version())
+}
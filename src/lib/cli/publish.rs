@@ -0,0 +1,381 @@
+use super::{
+    build::{read_manifest, BuildCtx},
+    package::{package, Packaged},
+};
+use base64;
+use failure::ResultExt;
+use git2::{self, IndexEntry as GitEntry, IndexTime, Oid, RemoteCallbacks, Repository, Signature};
+use index::{Dep, Index, IndexEntry};
+use package::{
+    manifest::PkgGitSpecifier,
+    resolution::{DirectRes, Resolution},
+    Name,
+};
+use retrieve::cache::Cache;
+use semver::Version;
+use serde_json::{self, Value};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+use url::Url;
+use util::{errors::Res, net::Net};
+
+pub struct PublishCtx {
+    /// The project to publish, and what to verify its packaged copy with.
+    pub build: BuildCtx,
+    /// The name or url of the index to publish to. If there isn't one, the default index is used.
+    pub index: Option<String>,
+    /// Whether to make sure that the packaged copy builds on its own before publishing it.
+    pub verify: bool,
+}
+
+/// What gets sent to the registry API of an index when a package is published.
+#[derive(Serialize)]
+struct Upload {
+    /// The index entry of the package, just as it'd be written in the index.
+    entry: Value,
+    /// The package's tarball, in base64.
+    tarball: String,
+}
+
+/// Packages the project and adds it to an index.
+///
+/// If the index has a registry API, the tarball and the new entry are uploaded to it with a PUT
+/// to `<api>/packages/<group>/<name>/<version>`. Otherwise, the index has to be a local or git
+/// index: the entry is appended to the index directly, the tarball is copied to where the `dl` of
+/// the index points (which has to be a local path), and both are committed (see `commit`).
+///
+/// A copy of the tarball is always left in `target/package`.
+pub fn publish(ctx: &PublishCtx) -> Res<()> {
+    let env = &ctx.build.env;
+    let manifest = read_manifest(&ctx.build.project)?;
+    if manifest.license().is_none() {
+        bail!("packages need a `license` in their manifest to be published")
+    }
+
+    let def_index = env.indices.default_or_dummy();
+    let target = match &ctx.index {
+        Some(index) => env.indices.resolve(index)?,
+        None => env
+            .indices
+            .default_index()
+            .cloned()
+            .ok_or_else(|| format_err!("there's no index to publish to"))?,
+    };
+
+    let name = manifest.name().clone();
+    let version = manifest.version().clone();
+
+    // Publishing has to happen on top of the latest version of the index.
+    let net = Net::new(env.net.clone(), env.credentials.clone())?;
+    let cache = Cache::from_disk(&env.logger, env.global_cache.clone(), def_index.clone())
+        .with_names(env.indices.clone())
+        .with_limits(env.extract)
+        .with_net(net.clone())
+        .with_shell(env.shell.clone());
+    let indices = cache.update_indices(&[target.res.clone()])?;
+    let index = indices
+        .index(&target)
+        .ok_or_else(|| format_err!("could not load the index `{}`", target))?;
+
+    if let Ok(entries) = index.entries(&name) {
+        if entries.contains_key(&version) {
+            bail!(
+                "{} {} has already been published to `{}`",
+                name,
+                version,
+                target
+            )
+        }
+    }
+
+    let mut dependencies = vec![];
+    for (n, dep) in manifest.dependencies.clone() {
+        let (pkg, req) = dep.into_dep(def_index.clone(), &env.indices, n)?;
+        match pkg.resolution() {
+            Resolution::Index(index) => dependencies.push(Dep {
+                name: pkg.name().clone(),
+                index: index.clone(),
+                req,
+            }),
+            Resolution::Direct(_) | Resolution::Root => bail!(
+                "the dependency `{}` doesn't come from an index, so this package can't be \
+                 published",
+                pkg.name()
+            ),
+        }
+    }
+
+    let url = location(index, &name, &version)?;
+    let api = index.config.api();
+    if api.is_none() {
+        if !index.config.keys().is_empty() {
+            bail!(
+                "the index `{}` is signed, so packages can only be published to it through a \
+                 registry API",
+                target
+            )
+        }
+        if url.scheme() != "file" {
+            bail!(
+                "the index `{}` has no registry API, and its packages are downloaded from `{}`, \
+                 which the tarball can't be copied to",
+                target,
+                url
+            )
+        }
+    }
+
+    let Packaged { tarball, cksum, .. } = package(&ctx.build, ctx.verify)?;
+    let entry = IndexEntry {
+        name: name.clone(),
+        version: version.clone(),
+        dependencies,
+        yanked: false,
+        location: DirectRes::Tar {
+            url: url.clone(),
+            cksum: Some(cksum),
+        },
+        metadata: manifest.metadata(),
+    };
+    let line = index.to_line(entry)?;
+
+    if let Some(api) = api {
+        upload(&net, api, &name, &version, &line, &tarball)?;
+    } else {
+        match &target.res {
+            DirectRes::Dir { .. } | DirectRes::Git { .. } => {
+                let path = url
+                    .to_file_path()
+                    .map_err(|_| format_err!("invalid download location `{}`", url))?;
+                let mut changes =
+                    vec![(PathBuf::from(name.as_str()), append(index, &name, &line)?)];
+                // A tarball which lives in the index gets committed along with the entry.
+                match path.strip_prefix(index.path.path()) {
+                    Ok(rel) => changes.push((rel.to_path_buf(), tarball.clone())),
+                    Err(_) => write_file(&path, &tarball)?,
+                }
+                commit(
+                    &net,
+                    index,
+                    &changes,
+                    &format!("Publish {} {}", name, version),
+                )?;
+            }
+            _ => bail!(
+                "the index `{}` has no registry API, and only local and git indices can be \
+                 published to without one",
+                target
+            ),
+        }
+    }
+
+    env.shell
+        .status("published", &format!("{} {} to {}", name, version, target));
+
+    Ok(())
+}
+
+/// Where the tarball of a package in `index` gets downloaded from.
+///
+/// This comes from the `dl` of the index's config; local indices without one keep their tarballs
+/// in `dl/<group>/<name>/<version>.tar.gz` inside the index.
+fn location(index: &Index, name: &Name, version: &Version) -> Res<Url> {
+    match (index.config.dl(), &index.id.res) {
+        (Some(dl), _) => {
+            let url = dl
+                .replace("{group}", name.group())
+                .replace("{name}", name.name())
+                .replace("{version}", &version.to_string());
+            Ok(Url::parse(&url).context(format_err!("invalid download location `{}`", url))?)
+        }
+        (None, DirectRes::Dir { .. }) => {
+            let path = index
+                .path
+                .path()
+                .join("dl")
+                .join(name.as_str())
+                .join(format!("{}.tar.gz", version));
+            Url::from_file_path(&path)
+                .map_err(|_| format_err!("invalid download location `{}`", path.display()))
+        }
+        (None, _) => bail!(
+            "the index `{}` doesn't say where its packages are downloaded from (its `dl`)",
+            index.id
+        ),
+    }
+}
+
+/// The url of a version of a package in the registry API `api`, which is
+/// `<api>/packages/<group>/<name>/<version>`.
+///
+/// Every change to a registry has to be authenticated, so this makes sure that we have a token for
+/// the registry first.
+pub fn package_url(net: &Net, api: &Url, name: &Name, version: &Version) -> Res<Url> {
+    let url = Url::parse(&format!(
+        "{}/packages/{}/{}",
+        api.as_str().trim_right_matches('/'),
+        name.as_str(),
+        version
+    ))?;
+
+    if net.credentials().token(&url).is_none() {
+        bail!(
+            "there's no token for `{}`; run `elba login` first",
+            url.host_str().unwrap_or("")
+        )
+    }
+
+    Ok(url)
+}
+
+fn upload(
+    net: &Net,
+    api: &Url,
+    name: &Name,
+    version: &Version,
+    line: &str,
+    tarball: &[u8],
+) -> Res<()> {
+    let url = package_url(net, api, name, version)?;
+    let body = Upload {
+        entry: serde_json::from_str(line)?,
+        tarball: base64::encode(tarball),
+    };
+    net.retry(|| net.put_json(&url, &body).map(|_| ()))
+        .context(format_err!(
+            "could not upload {} {} to `{}`",
+            name,
+            version,
+            api
+        ))?;
+
+    Ok(())
+}
+
+/// The entries for `name` in a local copy of an index, with `line` added to the end.
+fn append(index: &Index, name: &Name, line: &str) -> Res<Vec<u8>> {
+    let path = index.path.path().join(name.as_str());
+    let mut contents = if path.exists() {
+        fs::read(&path).context(format_err!("could not read `{}`", path.display()))?
+    } else {
+        vec![]
+    };
+    contents.extend_from_slice(line.as_bytes());
+    contents.push(b'\n');
+
+    Ok(contents)
+}
+
+/// Makes `changes` (the new contents of files, relative to the root of the index) to a local copy
+/// of an index, and commits them.
+///
+/// A local index is changed in place. If it's the root of a git repository, the changes are also
+/// committed on top of its `HEAD`; otherwise there's nothing to commit to.
+///
+/// A git index is left as it is. The commit is made on top of its `HEAD` without checking anything
+/// out, and pushed to the branch the index is on; our copy only changes once it's next updated.
+/// The push isn't forced, so if someone else changed the index in the meantime, it fails rather
+/// than throwing their changes away.
+pub fn commit(net: &Net, index: &Index, changes: &[(PathBuf, Vec<u8>)], msg: &str) -> Res<()> {
+    let (repo, tag) = match &index.id.res {
+        DirectRes::Git { repo, tag } => (repo, tag),
+        _ => {
+            for (path, contents) in changes {
+                write_file(&index.path.path().join(path), contents)?;
+            }
+
+            if let Ok(r) = Repository::open(index.path.path()) {
+                let mut ix = r.index()?;
+                for (path, _) in changes {
+                    ix.add_path(path)?;
+                }
+                ix.write()?;
+                commit_to(&r, &mut ix, msg, Some("HEAD"))?;
+            }
+            return Ok(());
+        }
+    };
+
+    let branch = match tag {
+        PkgGitSpecifier::Branch(b) => b,
+        _ => bail!(
+            "the git index `{}` isn't on a branch, so it can't be changed",
+            index.id
+        ),
+    };
+
+    // The commit goes on a branch of its own, so that our copy of the index stays the same as the
+    // remote one until it's next updated.
+    let r = Repository::open(index.path.path())?;
+    let mut ix = git2::Index::new()?;
+    ix.read_tree(&r.find_commit(r.refname_to_id("HEAD")?)?.tree()?)?;
+    for (path, contents) in changes {
+        ix.add(&GitEntry {
+            ctime: IndexTime::new(0, 0),
+            mtime: IndexTime::new(0, 0),
+            dev: 0,
+            ino: 0,
+            mode: 0o100_644,
+            uid: 0,
+            gid: 0,
+            file_size: contents.len() as u32,
+            id: r.blob(contents)?,
+            flags: 0,
+            flags_extended: 0,
+            path: git_path(path).into_bytes(),
+        })?;
+    }
+    let oid = commit_to(&r, &mut ix, msg, None)?;
+    r.reference("refs/heads/elba-publish", oid, true, msg)?;
+
+    let refspec = format!("refs/heads/elba-publish:refs/heads/{}", branch);
+    net.retry(|| {
+        let mut callbacks = RemoteCallbacks::new();
+        callbacks.push_update_reference(|refname, status| match status {
+            Some(status) => Err(::git2::Error::from_str(&format!(
+                "the remote rejected `{}`: {}",
+                refname, status
+            ))),
+            None => Ok(()),
+        });
+        let mut opts = net.push_options(repo, callbacks);
+        r.find_remote("origin")?
+            .push(&[refspec.as_str()], Some(&mut opts))?;
+        Ok(())
+    })
+    .context(format_err!("could not push to `{}`", repo))?;
+
+    Ok(())
+}
+
+/// Commits the tree in `ix` on top of `HEAD`, updating `update_ref` to point at the commit.
+fn commit_to(
+    r: &Repository,
+    ix: &mut git2::Index,
+    msg: &str,
+    update_ref: Option<&str>,
+) -> Res<Oid> {
+    let tree = r.find_tree(ix.write_tree_to(r)?)?;
+    let parent = r.find_commit(r.refname_to_id("HEAD")?)?;
+    let sig = r
+        .signature()
+        .or_else(|_| Signature::now("elba", "elba@localhost"))?;
+
+    Ok(r.commit(update_ref, &sig, &sig, msg, &tree, &[&parent])?)
+}
+
+/// The path of a file in a git tree, which always uses forward slashes.
+fn git_path(path: &Path) -> String {
+    path.components()
+        .map(|c| c.as_os_str().to_string_lossy().into_owned())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn write_file(path: &Path, contents: &[u8]) -> Res<()> {
+    fs::create_dir_all(path.parent().unwrap())?;
+    fs::write(path, contents).context(format_err!("could not write `{}`", path.display()))?;
+    Ok(())
+}
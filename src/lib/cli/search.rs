@@ -0,0 +1,165 @@
+use super::Env;
+use failure::ResultExt;
+use index::{Index, IndexEntry};
+use package::{resolution::IndexRes, Name};
+use regex::{Regex, RegexBuilder};
+use retrieve::cache::Cache;
+use semver::Version;
+use util::{errors::Res, net::Net};
+
+pub struct SearchCtx {
+    pub query: String,
+    /// Whether the query is a regex, rather than something to fuzzy-match against.
+    pub regex: bool,
+    /// The name or url of the only index to search. If there isn't one, every index is searched.
+    pub index: Option<String>,
+    pub env: Env,
+}
+
+/// A package which matched a search.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct SearchResult {
+    pub name: Name,
+    /// The latest version of the package which hasn't been yanked.
+    pub version: Version,
+    pub index: IndexRes,
+    /// The description of the latest version, if it has one.
+    pub description: Option<String>,
+}
+
+/// How a query is matched against packages.
+enum Matcher {
+    Regex(Regex),
+    /// The characters of the query have to show up in the same order, but not necessarily next to
+    /// each other, ignoring case.
+    Fuzzy(Vec<char>),
+}
+
+impl Matcher {
+    fn new(query: &str, regex: bool) -> Res<Self> {
+        if regex {
+            let re = RegexBuilder::new(query)
+                .case_insensitive(true)
+                .build()
+                .context(format_err!("invalid regex `{}`", query))?;
+            Ok(Matcher::Regex(re))
+        } else {
+            Ok(Matcher::Fuzzy(query.to_lowercase().chars().collect()))
+        }
+    }
+
+    fn matches(&self, s: &str) -> bool {
+        match self {
+            Matcher::Regex(re) => re.is_match(s),
+            Matcher::Fuzzy(query) => {
+                let mut query = query.iter().peekable();
+                for c in s.to_lowercase().chars() {
+                    if query.peek() == Some(&&c) {
+                        query.next();
+                    }
+                }
+                query.peek().is_none()
+            }
+        }
+    }
+}
+
+/// Searches the names and descriptions of the packages in every loaded index (or just the one in
+/// `ctx.index`), returning the latest non-yanked version of each match, sorted by name.
+///
+/// Packages whose versions have all been yanked don't show up at all, and neither do packages
+/// whose entries can't be read.
+pub fn search(ctx: &SearchCtx) -> Res<Vec<SearchResult>> {
+    let matcher = Matcher::new(&ctx.query, ctx.regex)?;
+
+    let only = match &ctx.index {
+        Some(index) => Some(ctx.env.indices.resolve(index)?),
+        None => None,
+    };
+    let reses = match &only {
+        Some(index) => vec![index.res.clone()],
+        None => ctx
+            .env
+            .indices
+            .indices()
+            .map(|i| i.res.clone())
+            .collect::<Vec<_>>(),
+    };
+    let def_index = ctx.env.indices.default_or_dummy();
+
+    let cache = Cache::from_disk(&ctx.env.logger, ctx.env.global_cache.clone(), def_index)
+        .with_names(ctx.env.indices.clone())
+        .with_limits(ctx.env.extract)
+        .with_net(Net::new(ctx.env.net.clone(), ctx.env.credentials.clone())?)
+        .with_policy(ctx.env.index_update)
+        .with_shell(ctx.env.shell.clone());
+    let indices = cache.get_indices(&reses)?;
+
+    let mut res = vec![];
+    for index in indices.iter() {
+        if only.as_ref().map_or(false, |only| only != &index.id) {
+            continue;
+        }
+
+        for name in index.names()? {
+            // One broken entry shouldn't keep every other package from being found.
+            let entry = match latest(index, &name) {
+                Ok(Some(entry)) => entry,
+                Ok(None) => continue,
+                Err(e) => {
+                    ctx.env
+                        .shell
+                        .status("skipping", &format!("{} in {}: {}", name, index.id, e));
+                    continue;
+                }
+            };
+            let description = entry.metadata.description;
+            let matched = matcher.matches(name.as_str())
+                || description.as_ref().map_or(false, |d| matcher.matches(d));
+
+            if matched {
+                res.push(SearchResult {
+                    name,
+                    version: entry.version,
+                    index: index.id.clone(),
+                    description,
+                });
+            }
+        }
+    }
+
+    res.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(res)
+}
+
+/// The entry for the latest version of `name` in `index` which hasn't been yanked.
+fn latest(index: &Index, name: &Name) -> Res<Option<IndexEntry>> {
+    let entries = index.entries(name)?;
+
+    Ok(entries
+        .into_iter()
+        .map(|(_, e)| e)
+        .filter(|e| !e.yanked)
+        .max_by(|a, b| a.version.cmp(&b.version)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fuzzy_match() {
+        let m = Matcher::new("lgt", false).unwrap();
+        assert!(m.matches("me/lightyear"));
+        assert!(m.matches("me/LiGhT"));
+        assert!(!m.matches("me/tgl"));
+    }
+
+    #[test]
+    fn regex_match() {
+        let m = Matcher::new("^me/light", true).unwrap();
+        assert!(m.matches("me/lightyear"));
+        assert!(!m.matches("you/lightyear"));
+        assert!(Matcher::new("(", true).is_err());
+    }
+}
@@ -0,0 +1,165 @@
+use super::{
+    publish::{commit, package_url},
+    Env,
+};
+use failure::ResultExt;
+use index::Index;
+use package::{resolution::DirectRes, Name};
+use retrieve::cache::Cache;
+use semver::Version;
+use serde_json::{self, Value};
+use std::{fs, path::PathBuf};
+use url::Url;
+use util::{errors::Res, net::Net};
+
+pub struct YankCtx {
+    pub name: Name,
+    pub version: Version,
+    /// Whether to take back a yank instead.
+    pub undo: bool,
+    /// The name or url of the index the package is in. If there isn't one, the default index is
+    /// used.
+    pub index: Option<String>,
+    pub env: Env,
+}
+
+/// What gets sent to the registry API of an index to (un)yank a package.
+#[derive(Serialize)]
+struct Yank {
+    yanked: bool,
+}
+
+/// Marks a published version of a package as yanked, or with `undo`, as not yanked anymore.
+///
+/// If the index has a registry API, it's sent a PUT to the `yank` of the package's url (see
+/// `package_url`). Otherwise, the entry is edited in place in a local or git index, and the change
+/// is committed (see `publish::commit`). Either way, this needs the same credentials as
+/// publishing.
+pub fn yank(ctx: &YankCtx) -> Res<()> {
+    let yanked = !ctx.undo;
+    let (name, version) = (&ctx.name, &ctx.version);
+
+    let target = match &ctx.index {
+        Some(index) => ctx.env.indices.resolve(index)?,
+        None => ctx
+            .env
+            .indices
+            .default_index()
+            .cloned()
+            .ok_or_else(|| format_err!("there's no index to yank from"))?,
+    };
+
+    let net = Net::new(ctx.env.net.clone(), ctx.env.credentials.clone())?;
+    let cache = Cache::from_disk(
+        &ctx.env.logger,
+        ctx.env.global_cache.clone(),
+        target.clone(),
+    )
+    .with_names(ctx.env.indices.clone())
+    .with_limits(ctx.env.extract)
+    .with_net(net.clone())
+    .with_shell(ctx.env.shell.clone());
+    let indices = cache.update_indices(&[target.res.clone()])?;
+    let index = indices
+        .index(&target)
+        .ok_or_else(|| format_err!("could not load the index `{}`", target))?;
+
+    let entries = index
+        .entries(name)
+        .context(format_err!("{} isn't in `{}`", name, target))?;
+    match entries.get(version) {
+        Some(entry) if entry.yanked == yanked => {
+            ctx.env.shell.status(
+                "note",
+                &format!(
+                    "{} {} is already {}",
+                    name,
+                    version,
+                    if yanked { "yanked" } else { "not yanked" }
+                ),
+            );
+            return Ok(());
+        }
+        Some(_) => {}
+        None => bail!("{} {} isn't in the index `{}`", name, version, target),
+    }
+
+    if let Some(api) = index.config.api() {
+        let url = package_url(&net, api, name, version)?;
+        let url = Url::parse(&format!("{}/yank", url))?;
+        net.retry(|| net.put_json(&url, &Yank { yanked }).map(|_| ()))
+            .context(format_err!(
+                "could not yank {} {} from `{}`",
+                name,
+                version,
+                api
+            ))?;
+    } else {
+        // Editing an entry would invalidate its signature.
+        if !index.config.keys().is_empty() {
+            bail!(
+                "the index `{}` is signed, so packages can only be yanked through a registry API",
+                target
+            )
+        }
+
+        match &target.res {
+            DirectRes::Dir { .. } | DirectRes::Git { .. } => {
+                let entries = with_yanked(index, name, version, yanked)?;
+                let msg = format!(
+                    "{} {} {}",
+                    if yanked { "Yank" } else { "Unyank" },
+                    name,
+                    version
+                );
+                commit(
+                    &net,
+                    index,
+                    &[(PathBuf::from(name.as_str()), entries.into_bytes())],
+                    &msg,
+                )?;
+            }
+            _ => bail!(
+                "the index `{}` has no registry API, and only local and git indices can be \
+                 changed without one",
+                target
+            ),
+        }
+    }
+
+    ctx.env.shell.status(
+        if yanked { "yanked" } else { "unyanked" },
+        &format!("{} {} from {}", name, version, target),
+    );
+
+    Ok(())
+}
+
+/// The entries for `name` in a local copy of an index, with the entry for `version` rewritten and
+/// every other entry left exactly as it was.
+fn with_yanked(index: &Index, name: &Name, version: &Version, yanked: bool) -> Res<String> {
+    let path = index.path.path().join(name.as_str());
+    let contents =
+        fs::read_to_string(&path).context(format_err!("could not read `{}`", path.display()))?;
+
+    let mut res = String::new();
+    for line in contents.lines() {
+        let mut entry: Value = serde_json::from_str(line)
+            .context(format_err!("invalid entry in `{}`", path.display()))?;
+        let is_version = entry
+            .get("version")
+            .and_then(|v| v.as_str())
+            .and_then(|v| Version::parse(v).ok())
+            .map_or(false, |v| &v == version);
+
+        if is_version {
+            entry["yanked"] = Value::Bool(yanked);
+            res.push_str(&serde_json::to_string(&entry)?);
+        } else {
+            res.push_str(line);
+        }
+        res.push('\n');
+    }
+
+    Ok(res)
+}
@@ -1,9 +1,15 @@
 //! Configuration for Indices.
 
+use super::{
+    keys::{PublicKey, Rotation},
+    IndexNames,
+};
 use failure::{Error, ResultExt};
-use package::resolution::IndexRes;
+use indexmap::IndexSet;
 use std::str::FromStr;
 use toml;
+use url::Url;
+use url_serde;
 use util::errors::ErrorKind;
 
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
@@ -11,6 +17,38 @@ pub struct IndexConfig {
     index: IndexConfInner,
 }
 
+impl IndexConfig {
+    pub fn secure(&self) -> bool {
+        self.index.secure
+    }
+
+    /// The indices which packages in this index can depend on, by the names that the index's
+    /// entries refer to them by.
+    pub fn dependencies(&self) -> &IndexNames {
+        &self.index.dependencies
+    }
+
+    /// The keys which the entries of this index are signed with.
+    pub fn keys(&self) -> &IndexSet<PublicKey> {
+        &self.index.keys
+    }
+
+    pub fn rotations(&self) -> &[Rotation] {
+        &self.index.rotations
+    }
+
+    /// The registry API which packages are published through, if the index has one.
+    pub fn api(&self) -> Option<&Url> {
+        self.index.api.as_ref()
+    }
+
+    /// Where the tarballs of published packages are downloaded from, as a url in which `{group}`,
+    /// `{name}` and `{version}` get replaced with those of the package.
+    pub fn dl(&self) -> Option<&str> {
+        self.index.dl.as_ref().map(|s| s.as_str())
+    }
+}
+
 impl FromStr for IndexConfig {
     type Err = Error;
 
@@ -24,14 +62,27 @@ impl FromStr for IndexConfig {
 #[derive(Clone, Debug, Deserialize, Serialize)]
 struct IndexConfInner {
     secure: bool,
-    dependencies: Vec<IndexRes>,
+    #[serde(default)]
+    dependencies: IndexNames,
+    #[serde(default)]
+    keys: IndexSet<PublicKey>,
+    #[serde(default)]
+    rotations: Vec<Rotation>,
+    #[serde(default, with = "url_serde")]
+    api: Option<Url>,
+    #[serde(default)]
+    dl: Option<String>,
 }
 
 impl Default for IndexConfInner {
     fn default() -> Self {
         IndexConfInner {
             secure: false,
-            dependencies: vec![],
+            dependencies: IndexNames::default(),
+            keys: IndexSet::new(),
+            rotations: vec![],
+            api: None,
+            dl: None,
         }
     }
 }
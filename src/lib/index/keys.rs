@@ -0,0 +1,307 @@
+//! Public keys for verifying signed index entries, and keeping track of which keys we trust.
+//!
+//! An index publishes its public keys in its `index.toml`. The first time an index is used, the
+//! Cache pins whatever keys it publishes; from then on, the index can only be signed with pinned
+//! keys. If an index wants to switch to a new key, it has to publish a rotation record: the new
+//! key, signed with the key it replaces.
+//!
+//! ```toml
+//! [index]
+//! secure = true
+//! keys = ["ed25519:<base64 public key>"]
+//!
+//! [[index.rotations]]
+//! old = "ed25519:<base64 public key>"
+//! new = "ed25519:<base64 public key>"
+//! signature = "<base64 signature of the new key by the old key>"
+//! ```
+
+use base64;
+use failure::{Error, ResultExt};
+use indexmap::IndexSet;
+use package::resolution::IndexRes;
+use ring::signature::{self, ED25519};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::{fmt, fs, path::Path, str::FromStr};
+use toml;
+use untrusted::Input;
+
+/// A public key which index entries can be signed with.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PublicKey {
+    bytes: Vec<u8>,
+}
+
+impl PublicKey {
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        PublicKey {
+            bytes: bytes.to_vec(),
+        }
+    }
+
+    /// Checks if `sig` is a valid signature of `msg` by this key.
+    pub fn verify(&self, msg: &[u8], sig: &[u8]) -> bool {
+        signature::verify(
+            &ED25519,
+            Input::from(&self.bytes),
+            Input::from(msg),
+            Input::from(sig),
+        )
+        .is_ok()
+    }
+
+    /// Checks if `sig` is a valid base64-encoded signature of `msg` by this key.
+    pub fn verify_base64(&self, msg: &[u8], sig: &str) -> bool {
+        match base64::decode(sig) {
+            Ok(sig) => self.verify(msg, &sig),
+            Err(_) => false,
+        }
+    }
+}
+
+impl FromStr for PublicKey {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, ':');
+        let kind = parts.next().unwrap();
+        let key = parts
+            .next()
+            .ok_or_else(|| KeyError::InvalidKey(s.to_owned()))?;
+
+        match kind {
+            "ed25519" => {
+                let bytes = base64::decode(key).context(KeyError::InvalidKey(s.to_owned()))?;
+                if bytes.len() != 32 {
+                    return Err(KeyError::InvalidKey(s.to_owned()))?;
+                }
+                Ok(PublicKey { bytes })
+            }
+            _ => Err(KeyError::InvalidKey(s.to_owned()))?,
+        }
+    }
+}
+
+impl fmt::Display for PublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ed25519:{}", base64::encode(&self.bytes))
+    }
+}
+
+impl Serialize for PublicKey {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for PublicKey {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        FromStr::from_str(&s).map_err(de::Error::custom)
+    }
+}
+
+/// A record of an index replacing one of its keys with a new one.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Rotation {
+    pub old: PublicKey,
+    pub new: PublicKey,
+    /// The signature of the new key (in its `ed25519:<base64>` form) by the old key, in base64.
+    pub signature: String,
+}
+
+impl Rotation {
+    pub fn is_valid(&self) -> bool {
+        self.old
+            .verify_base64(self.new.to_string().as_bytes(), &self.signature)
+    }
+}
+
+/// The reasons the keys of an index can be rejected.
+#[derive(Clone, Debug, PartialEq, Eq, Fail)]
+pub enum KeyError {
+    #[fail(display = "invalid public key `{}`", _0)]
+    InvalidKey(String),
+    #[fail(display = "the secure index `{}` doesn't publish any keys", _0)]
+    NoKeys(IndexRes),
+    #[fail(
+        display = "the index `{}` stopped publishing keys, but it had keys pinned for it",
+        _0
+    )]
+    KeysRemoved(IndexRes),
+    #[fail(
+        display = "the index `{}` publishes the key `{}`, which isn't pinned and wasn't rotated in \
+                   from a pinned key",
+        index, key
+    )]
+    Untrusted { index: IndexRes, key: PublicKey },
+}
+
+/// The keys which we trust for a single index.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct PinnedKeys {
+    pub keys: IndexSet<PublicKey>,
+}
+
+impl PinnedKeys {
+    /// Reads the keys pinned at `path`, if any have been pinned.
+    pub fn load(path: &Path) -> Result<Option<Self>, Error> {
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let contents = fs::read_to_string(path).context(format_err!(
+            "could not read pinned keys `{}`",
+            path.display()
+        ))?;
+        let pinned = toml::from_str(&contents)
+            .context(format_err!("invalid pinned keys `{}`", path.display()))?;
+
+        Ok(Some(pinned))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        fs::write(path, toml::to_string(self)?).context(format_err!(
+            "could not write pinned keys `{}`",
+            path.display()
+        ))?;
+
+        Ok(())
+    }
+
+    /// Works out which keys to trust for `index` from the keys pinned for it (if any), the keys it
+    /// currently publishes, and its rotation records.
+    ///
+    /// If no keys have been pinned yet, the published keys are trusted as-is. Otherwise, every
+    /// valid rotation away from a trusted key replaces it with the new key, and every published
+    /// key has to end up trusted.
+    pub fn check(
+        pinned: Option<PinnedKeys>,
+        index: &IndexRes,
+        keys: &IndexSet<PublicKey>,
+        rotations: &[Rotation],
+    ) -> Result<PinnedKeys, KeyError> {
+        let mut trusted = match pinned {
+            Some(ref pinned) if !pinned.keys.is_empty() => pinned.keys.clone(),
+            _ => return Ok(PinnedKeys { keys: keys.clone() }),
+        };
+
+        if keys.is_empty() {
+            return Err(KeyError::KeysRemoved(index.clone()));
+        }
+
+        for rotation in rotations {
+            if trusted.contains(&rotation.old) && rotation.is_valid() {
+                trusted.remove(&rotation.old);
+                trusted.insert(rotation.new.clone());
+            }
+        }
+
+        for key in keys {
+            if !trusted.contains(key) {
+                return Err(KeyError::Untrusted {
+                    index: index.clone(),
+                    key: key.clone(),
+                });
+            }
+        }
+
+        Ok(PinnedKeys { keys: trusted })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::signature::Ed25519KeyPair;
+
+    fn keypair(seed: u8) -> (Ed25519KeyPair, PublicKey) {
+        let pair = Ed25519KeyPair::from_seed_unchecked(Input::from(&[seed; 32])).unwrap();
+        let key = PublicKey::from_bytes(pair.public_key_bytes());
+        (pair, key)
+    }
+
+    fn rotation(old: &(Ed25519KeyPair, PublicKey), new: &PublicKey) -> Rotation {
+        Rotation {
+            old: old.1.clone(),
+            new: new.clone(),
+            signature: base64::encode(old.0.sign(new.to_string().as_bytes()).as_ref()),
+        }
+    }
+
+    fn index() -> IndexRes {
+        IndexRes::from_str("index+dir+file://index").unwrap()
+    }
+
+    #[test]
+    fn key_roundtrip() {
+        let (pair, key) = keypair(1);
+        assert_eq!(PublicKey::from_str(&key.to_string()).unwrap(), key);
+
+        let sig = base64::encode(pair.sign(b"hello").as_ref());
+        assert!(key.verify_base64(b"hello", &sig));
+        assert!(!key.verify_base64(b"hellp", &sig));
+        assert!(PublicKey::from_str("ed25519:AAAA").is_err());
+    }
+
+    #[test]
+    fn keys_pinned_on_first_use() {
+        let (_, key) = keypair(1);
+        let keys = indexset!(key);
+
+        let pinned = PinnedKeys::check(None, &index(), &keys, &[]).unwrap();
+        assert_eq!(pinned.keys, keys);
+        assert_eq!(
+            PinnedKeys::check(Some(pinned.clone()), &index(), &keys, &[]).unwrap(),
+            pinned
+        );
+    }
+
+    #[test]
+    fn keys_untrusted() {
+        let (_, old) = keypair(1);
+        let (_, new) = keypair(2);
+        let pinned = PinnedKeys {
+            keys: indexset!(old),
+        };
+
+        assert_eq!(
+            PinnedKeys::check(Some(pinned.clone()), &index(), &indexset!(new.clone()), &[]),
+            Err(KeyError::Untrusted {
+                index: index(),
+                key: new,
+            })
+        );
+        assert_eq!(
+            PinnedKeys::check(Some(pinned), &index(), &indexset!(), &[]),
+            Err(KeyError::KeysRemoved(index()))
+        );
+    }
+
+    #[test]
+    fn keys_rotated() {
+        let old = keypair(1);
+        let (_, new) = keypair(2);
+        let (_, evil) = keypair(3);
+        let pinned = PinnedKeys {
+            keys: indexset!(old.1.clone()),
+        };
+
+        let rotated = PinnedKeys::check(
+            Some(pinned.clone()),
+            &index(),
+            &indexset!(new.clone()),
+            &[rotation(&old, &new)],
+        )
+        .unwrap();
+        assert_eq!(rotated.keys, indexset!(new.clone()));
+
+        // A rotation has to be signed by the key it replaces.
+        let mut forged = rotation(&old, &new);
+        forged.new = evil.clone();
+        assert!(PinnedKeys::check(Some(pinned), &index(), &indexset!(evil), &[forged]).is_err());
+    }
+}
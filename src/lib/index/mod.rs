@@ -23,40 +23,205 @@
 //! [unofficial registries](https://github.com/rust-lang/rfcs/blob/master/text/2141-alternative-registries.md).
 
 mod config;
+pub mod keys;
 
-use self::config::IndexConfig;
+use self::{config::IndexConfig, keys::KeyError};
 use failure::{Error, ResultExt};
 use indexmap::IndexMap;
 use package::{
-    resolution::{DirectRes, IndexRes, Resolution},
+    resolution::{sparse_url, DirectRes, IndexRes, Resolution},
     version::Constraint,
     *,
 };
+use reqwest::{Response, StatusCode};
 use semver::Version;
-use serde_json;
+use serde_json::{self, Value};
 use std::{
     fs,
     io::{self, prelude::*, BufReader},
-    str::FromStr,
+    str::{self, FromStr},
 };
-use util::{errors::ErrorKind, lock::DirLock};
+use toml;
+use url::Url;
+use util::{
+    errors::ErrorKind,
+    lock::DirLock,
+    net::{Net, StatusError},
+};
+
+/// Short, user-friendly names for indices.
+///
+/// Indices are referred to by name in the `[indices]` section of the config, in the `registry`
+/// of a dependency in a manifest, and in the dependencies of an index's `index.toml`. Anywhere a
+/// name is expected, a full index url can be given instead.
+///
+/// In the config, the first index is the default index.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct IndexNames(IndexMap<String, IndexRes>);
+
+impl IndexNames {
+    pub fn new(names: IndexMap<String, IndexRes>) -> Self {
+        IndexNames(names)
+    }
+
+    /// The first index, which packages come from unless they say otherwise.
+    pub fn default_index(&self) -> Option<&IndexRes> {
+        self.0.values().next()
+    }
+
+    /// The default index, or a dummy index if there are none at all; the cache always needs some
+    /// index to attribute packages to, even if nothing will ever be fetched from it.
+    pub fn default_or_dummy(&self) -> IndexRes {
+        self.default_index()
+            .cloned()
+            .unwrap_or_else(|| IndexRes::from_str("index+dir+file://none").unwrap())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&IndexRes> {
+        self.0.get(name)
+    }
+
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item = (&'a String, &'a IndexRes)> + 'a {
+        self.0.iter()
+    }
+
+    pub fn indices<'a>(&'a self) -> impl Iterator<Item = &'a IndexRes> + 'a {
+        self.0.values()
+    }
+
+    /// Turns either the name of an index or a full index url into the index it stands for.
+    pub fn resolve(&self, name: &str) -> Result<IndexRes, Error> {
+        if let Some(index) = self.0.get(name) {
+            return Ok(index.clone());
+        }
+
+        IndexRes::from_str(name)
+            .context(format_err!("unknown index `{}`", name))
+            .map_err(Error::from)
+    }
+}
+
+/// The reasons an index entry can be rejected.
+#[derive(Clone, Debug, PartialEq, Eq, Fail)]
+pub enum EntryError {
+    /// The entry depends on a package from an index which its own index doesn't declare as one of
+    /// its dependencies.
+    #[fail(
+        display = "`{}` depends on `{}` from `{}`, which isn't a dependency of the index `{}`",
+        package, dep, dep_index, index
+    )]
+    UndeclaredIndex {
+        package: String,
+        dep: String,
+        dep_index: IndexRes,
+        index: IndexRes,
+    },
+    /// The entry is in a secure index, but its location isn't a tarball with a checksum.
+    #[fail(
+        display = "`{}` in the secure index `{}` must be a tarball with a checksum, not `{}`",
+        package, index, location
+    )]
+    Insecure {
+        package: String,
+        index: IndexRes,
+        location: DirectRes,
+    },
+    /// The entry is in the file of a different package, so it could be used to pass one package
+    /// off as another.
+    #[fail(
+        display = "the entries for `{}` in the index `{}` include one for `{}`",
+        package, index, found
+    )]
+    WrongName {
+        package: String,
+        found: String,
+        index: IndexRes,
+    },
+    /// The entry is in an index with keys, but it isn't signed.
+    #[fail(display = "`{}` in the signed index `{}` isn't signed", package, index)]
+    Unsigned { package: String, index: IndexRes },
+    /// The entry's signature doesn't match any of its index's keys.
+    #[fail(
+        display = "the signature of `{}` {} doesn't match any of the keys of the index `{}`; the \
+                   entry may have been tampered with",
+        package, version, index
+    )]
+    BadSignature {
+        package: String,
+        version: Version,
+        index: IndexRes,
+    },
+}
 
 /// A dependency.
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
 pub struct Dep {
     pub name: Name,
-    // TODO: Ideally, instead of having the index be an IndexRes, we just let it be a String
-    // corresponding to a key in the Index's config which points to an actual IndexRes.
     pub index: IndexRes,
     pub req: Constraint,
 }
 
+/// Information about a package which isn't needed to resolve or build it, but helps people find
+/// and learn about it. This comes from the package's manifest when it's published.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub struct Metadata {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub license: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repository: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub homepage: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub documentation: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keywords: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub categories: Vec<String>,
+    /// The path of the package's readme, relative to the root of its tarball.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub readme: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idris_version: Option<Constraint>,
+}
+
+impl Metadata {
+    fn is_empty(&self) -> bool {
+        *self == Metadata::default()
+    }
+}
+
+/// An entry as it's written in an index, before the indices of its dependencies are resolved.
+#[derive(Deserialize, Serialize)]
+struct RawEntry {
+    name: Name,
+    version: Version,
+    dependencies: Vec<RawDep>,
+    yanked: bool,
+    location: DirectRes,
+    #[serde(default, skip_serializing_if = "Metadata::is_empty")]
+    metadata: Metadata,
+}
+
+#[derive(Deserialize, Serialize)]
+struct RawDep {
+    name: Name,
+    /// Either one of the names in the dependencies of the index, or a full index url. If there's
+    /// no index, the dependency comes from the same index as the package depending on it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    index: Option<String>,
+    req: Constraint,
+}
+
 #[derive(Debug)]
 pub struct Indices {
     /// The indices being used.
     ///
     /// It is assumed that all dependent indices have been resolved, and that this mapping contains
-    /// every index mentioned or depended on.
+    /// every index mentioned or depended on; `Cache::get_indices` takes care of that.
     indices: IndexMap<IndexRes, Index>,
     cache: IndexMap<PackageId, IndexMap<Version, IndexEntry>>,
 }
@@ -69,6 +234,14 @@ impl Indices {
         Indices { indices, cache }
     }
 
+    pub fn index(&self, res: &IndexRes) -> Option<&Index> {
+        self.indices.get(res)
+    }
+
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item = &'a Index> + 'a {
+        self.indices.values()
+    }
+
     pub fn select(&mut self, pkg: &Summary) -> Result<&IndexEntry, Error> {
         let entry = self
             .entries(pkg.id())?
@@ -115,6 +288,8 @@ pub struct IndexEntry {
     pub dependencies: Vec<Dep>,
     pub yanked: bool,
     pub location: DirectRes,
+    #[serde(default)]
+    pub metadata: Metadata,
 }
 
 // TODO: Dealing with where to download the Index, using the Config to get that info.
@@ -131,6 +306,17 @@ pub struct Index {
     pub path: DirLock,
     /// The configuration of this index.
     pub config: IndexConfig,
+    /// The network client used to fetch entries from a sparse index. Without one, a sparse index
+    /// only has whatever entries were fetched before.
+    net: Option<Net>,
+}
+
+/// What we know about the copy of an entry file we last fetched from a sparse index, so that the
+/// server only has to send it again if it's changed.
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct SparseMeta {
+    etag: Option<String>,
+    modified: Option<String>,
 }
 
 impl Index {
@@ -145,10 +331,64 @@ impl Index {
             .context(ErrorKind::InvalidIndex)?;
         let config = IndexConfig::from_str(&contents).context(ErrorKind::InvalidIndex)?;
 
-        Ok(Index { id, path, config })
+        if config.secure() && config.keys().is_empty() {
+            return Err(KeyError::NoKeys(id))?;
+        }
+
+        Ok(Index {
+            id,
+            path,
+            config,
+            net: None,
+        })
+    }
+
+    pub fn with_net(mut self, net: Net) -> Self {
+        self.net = Some(net);
+        self
+    }
+
+    /// The names of every package in this index. For sparse indices, that's only the packages
+    /// which we've fetched the entries of before.
+    pub fn names(&self) -> Result<Vec<Name>, Error> {
+        let mut names = vec![];
+
+        for group in fs::read_dir(self.path.path()).context(ErrorKind::InvalidIndex)? {
+            let group = group.context(ErrorKind::InvalidIndex)?;
+            // Hidden directories (e.g. the `.git/` of a git index) never hold packages.
+            let hidden = group.file_name().to_string_lossy().starts_with('.');
+            if hidden || !group.file_type()?.is_dir() {
+                continue;
+            }
+
+            for pkg in fs::read_dir(group.path()).context(ErrorKind::InvalidIndex)? {
+                let pkg = pkg.context(ErrorKind::InvalidIndex)?;
+                if !pkg.file_type()?.is_file() {
+                    continue;
+                }
+
+                // Anything that isn't a package (e.g. the tarballs of a local index, or the
+                // metadata of a sparse index) won't have a valid name.
+                let name = format!(
+                    "{}/{}",
+                    group.file_name().to_string_lossy(),
+                    pkg.file_name().to_string_lossy()
+                );
+                if let Ok(name) = Name::from_str(&name) {
+                    names.push(name);
+                }
+            }
+        }
+
+        names.sort();
+        Ok(names)
     }
 
     pub fn entries(&self, name: &Name) -> Result<IndexMap<Version, IndexEntry>, Error> {
+        if let DirectRes::Sparse { url } = &self.id.res {
+            self.fetch(url, name)?;
+        }
+
         let mut res = indexmap!();
         let path = self.path.path().join(name.as_str());
         let file = fs::File::open(path).context(ErrorKind::PackageNotFound)?;
@@ -156,11 +396,271 @@ impl Index {
 
         for line in r.lines() {
             let line = line.context(ErrorKind::InvalidIndex)?;
-            let entry: IndexEntry = serde_json::from_str(&line).context(ErrorKind::InvalidIndex)?;
+            let mut value: Value = serde_json::from_str(&line).context(ErrorKind::InvalidIndex)?;
+
+            // The signature covers everything in the entry except for the signature itself.
+            let signature = match value.as_object_mut().and_then(|o| o.remove("signature")) {
+                Some(Value::String(sig)) => Some(sig),
+                Some(_) => return Err(ErrorKind::InvalidIndex)?,
+                None => None,
+            };
+            let message = serde_json::to_string(&value).context(ErrorKind::InvalidIndex)?;
+
+            let entry: RawEntry = serde_json::from_value(value).context(ErrorKind::InvalidIndex)?;
+            if &entry.name != name {
+                return Err(EntryError::WrongName {
+                    package: name.to_string(),
+                    found: entry.name.to_string(),
+                    index: self.id.clone(),
+                })?;
+            }
+            self.verify(&entry, &message, signature)?;
+            let entry = self.resolve(entry)?;
 
             res.insert(entry.version.clone(), entry);
         }
 
         Ok(res)
     }
+
+    /// Brings our copy of the entries for `name` in a sparse index up to date. The entries live at
+    /// `<index url>/<group>/<name>`, and are only downloaded again if the server says they've
+    /// changed since we last fetched them.
+    ///
+    /// If the server can't be reached but we already have a copy of the entries, that copy is
+    /// used instead.
+    fn fetch(&self, base: &Url, name: &Name) -> Result<(), Error> {
+        let net = match &self.net {
+            Some(net) => net,
+            None => return Ok(()),
+        };
+
+        let path = self.path.path().join(name.as_str());
+        let meta_path = self
+            .path
+            .path()
+            .join(".sparse")
+            .join(format!("{}.toml", name.as_str()));
+        let meta = if path.exists() {
+            fs::read_to_string(&meta_path)
+                .ok()
+                .and_then(|s| toml::from_str::<SparseMeta>(&s).ok())
+                .unwrap_or_default()
+        } else {
+            SparseMeta::default()
+        };
+
+        let url = sparse_url(base, name.as_str())?;
+        let fetched = net.retry(|| {
+            let etag = meta.etag.as_ref().map(|s| s.as_str());
+            let modified = meta.modified.as_ref().map(|s| s.as_str());
+            let mut r = match net.get_if_modified(&url, etag, modified)? {
+                Some(r) => r,
+                None => return Ok(None),
+            };
+
+            let meta = SparseMeta {
+                etag: header(&r, "ETag"),
+                modified: header(&r, "Last-Modified"),
+            };
+            let mut buf = vec![];
+            r.read_to_end(&mut buf)?;
+
+            Ok(Some((buf, meta)))
+        });
+
+        match fetched {
+            Ok(Some((buf, meta))) => {
+                // The new entries are written next to the metadata first, so that nobody ever
+                // reads a half-written file.
+                let part = meta_path.with_extension("part");
+                fs::create_dir_all(meta_path.parent().unwrap())
+                    .and_then(|_| fs::create_dir_all(path.parent().unwrap()))
+                    .and_then(|_| fs::write(&part, buf))
+                    .and_then(|_| fs::rename(&part, &path))
+                    .context(ErrorKind::CannotDownload)?;
+                fs::write(&meta_path, toml::to_string(&meta)?)
+                    .context(ErrorKind::CannotDownload)?;
+
+                Ok(())
+            }
+            Ok(None) => Ok(()),
+            Err(ref e) if is_not_found(e) => {
+                // The package is gone, so our copy of it shouldn't stick around either.
+                let _ = fs::remove_file(&path);
+                let _ = fs::remove_file(&meta_path);
+                Err(ErrorKind::PackageNotFound)?
+            }
+            Err(_) if path.exists() => Ok(()),
+            Err(e) => Err(e.context(ErrorKind::CannotDownload))?,
+        }
+    }
+
+    /// Checks the signature of an entry against the keys of the index. Indices which don't
+    /// publish any keys don't need their entries to be signed.
+    ///
+    /// The signed message is the entry with its `signature` removed, serialized as compact JSON
+    /// with its keys in sorted order.
+    fn verify(
+        &self,
+        entry: &RawEntry,
+        message: &str,
+        signature: Option<String>,
+    ) -> Result<(), Error> {
+        let keys = self.config.keys();
+        if keys.is_empty() {
+            return Ok(());
+        }
+
+        let signature = signature.ok_or_else(|| EntryError::Unsigned {
+            package: entry.name.to_string(),
+            index: self.id.clone(),
+        })?;
+
+        if keys
+            .iter()
+            .any(|key| key.verify_base64(message.as_bytes(), &signature))
+        {
+            Ok(())
+        } else {
+            Err(EntryError::BadSignature {
+                package: entry.name.to_string(),
+                version: entry.version.clone(),
+                index: self.id.clone(),
+            })?
+        }
+    }
+
+    /// Turns `entry` into a line which can be added to this index. Its dependencies refer to their
+    /// indices by the names this index knows them by, so they have to come from either this index
+    /// or one of its dependencies.
+    pub fn to_line(&self, entry: IndexEntry) -> Result<String, Error> {
+        let IndexEntry {
+            name,
+            version,
+            dependencies,
+            yanked,
+            location,
+            metadata,
+        } = entry;
+        let deps = self.config.dependencies();
+
+        let dependencies = dependencies
+            .into_iter()
+            .map(|dep| {
+                let index = if dep.index == self.id {
+                    None
+                } else {
+                    match deps.iter().find(|(_, index)| **index == dep.index) {
+                        Some((n, _)) => Some(n.clone()),
+                        None => {
+                            return Err(EntryError::UndeclaredIndex {
+                                package: name.to_string(),
+                                dep: dep.name.to_string(),
+                                dep_index: dep.index,
+                                index: self.id.clone(),
+                            })?
+                        }
+                    }
+                };
+
+                Ok(RawDep {
+                    name: dep.name,
+                    index,
+                    req: dep.req,
+                })
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        let entry = RawEntry {
+            name,
+            version,
+            dependencies,
+            yanked,
+            location,
+            metadata,
+        };
+
+        Ok(serde_json::to_string(&entry)?)
+    }
+
+    /// Resolves the names of the indices which an entry's dependencies come from, making sure
+    /// that each of those indices is either this index or one of its declared dependencies.
+    ///
+    /// If this index is secure, the entry also has to point to a tarball with a checksum, so that
+    /// whatever gets downloaded for it can be verified.
+    fn resolve(&self, entry: RawEntry) -> Result<IndexEntry, Error> {
+        let RawEntry {
+            name,
+            version,
+            dependencies,
+            yanked,
+            location,
+            metadata,
+        } = entry;
+        let deps = self.config.dependencies();
+
+        if self.config.secure() {
+            match location {
+                DirectRes::Tar { cksum: Some(_), .. } => {}
+                _ => {
+                    return Err(EntryError::Insecure {
+                        package: name.to_string(),
+                        index: self.id.clone(),
+                        location,
+                    })?
+                }
+            }
+        }
+
+        let dependencies = dependencies
+            .into_iter()
+            .map(|dep| {
+                let index = match dep.index {
+                    Some(name) => deps.resolve(&name).context(ErrorKind::InvalidIndex)?,
+                    None => self.id.clone(),
+                };
+
+                if index != self.id && deps.indices().all(|i| i != &index) {
+                    return Err(EntryError::UndeclaredIndex {
+                        package: name.to_string(),
+                        dep: dep.name.to_string(),
+                        dep_index: index,
+                        index: self.id.clone(),
+                    })?;
+                }
+
+                Ok(Dep {
+                    name: dep.name,
+                    index,
+                    req: dep.req,
+                })
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        Ok(IndexEntry {
+            name,
+            version,
+            dependencies,
+            yanked,
+            location,
+            metadata,
+        })
+    }
+}
+
+/// The value of the header `name` in `r`, if it's there and is valid UTF-8.
+fn header(r: &Response, name: &str) -> Option<String> {
+    r.headers()
+        .get_raw(name)
+        .and_then(|raw| raw.one())
+        .and_then(|v| str::from_utf8(v).ok())
+        .map(|v| v.to_owned())
+}
+
+fn is_not_found(e: &Error) -> bool {
+    e.causes().any(|c| match c.downcast_ref::<StatusError>() {
+        Some(e) => e.status == StatusCode::NotFound,
+        None => false,
+    })
 }
@@ -2,19 +2,24 @@
 
 //! A package manager for the Idris language.
 
+extern crate atty;
+extern crate base64;
 extern crate copy_dir;
 extern crate directories;
 #[macro_use]
 extern crate failure;
 extern crate flate2;
 extern crate git2;
+extern crate glob;
 #[macro_use]
 extern crate indexmap;
 extern crate inflector;
 extern crate itertools;
 #[macro_use]
 extern crate nom;
+extern crate regex;
 extern crate reqwest;
+extern crate ring;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
@@ -28,8 +33,10 @@ extern crate petgraph;
 extern crate symlink;
 extern crate tar;
 extern crate toml;
+extern crate untrusted;
 extern crate url;
 extern crate url_serde;
+extern crate walkdir;
 
 pub mod build;
 pub mod cli;
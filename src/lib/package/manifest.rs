@@ -5,36 +5,22 @@ use super::{
     resolution::{DirectRes, IndexRes, Resolution},
     *,
 };
+use build::context::Profiles;
 use failure::{Error, ResultExt};
+use index::{IndexNames, Metadata};
 use indexmap::IndexMap;
 use semver::Version;
-use std::{path::PathBuf, str::FromStr};
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+};
 use toml;
 use url::Url;
 use url_serde;
 use util::errors::*;
 
-// TODO: Package aliasing. Have dummy alias files in the root target folder.
-//
-// e.g. to alias `me/lightyear` with default root module `Me.Lightyear` as the module
-// `Yeet.Lightyeet`, in the target folder, we make the following file in the proper directory
-// (directory won't matter for Blodwen/Idris 2):
-//
-// ```idris
-// module Yeet.Lightyeet
-//
-// import public Me.Lightyear
-// ```
-//
-// Behind the scenes, we build this as its own package with the package it's aliasing as
-// its only dependency, throw it in the global cache, and add this to the import dir of the root
-// package instead of the original.
-//
-// I guess this also means that each package should declare their (root) module(s), so that we
-// can identify conflicts ahead of time without having to guess that it's always gonna be Group.Name
-//
-// With this in place, we can safely avoid module namespace conflicts.
-
 #[derive(Deserialize, Debug)]
 pub struct Manifest {
     package: PackageInfo,
@@ -42,9 +28,17 @@ pub struct Manifest {
     pub dependencies: IndexMap<Name, DepReq>,
     #[serde(default = "IndexMap::new")]
     pub dev_dependencies: IndexMap<Name, DepReq>,
+    #[serde(default)]
     targets: Targets,
     #[serde(default)]
     workspace: IndexMap<Name, String>,
+    /// Dependencies which should be imported under a different module prefix, to avoid clashing
+    /// with the modules of other packages. For example, `'me/lightyear' = 'Yeet.Lightyeet'` makes
+    /// the module `Me.Lightyear.Parser` available as `Yeet.Lightyeet.Parser`.
+    #[serde(default)]
+    aliases: IndexMap<Name, String>,
+    #[serde(default)]
+    profile: Profiles,
 }
 
 impl Manifest {
@@ -53,18 +47,248 @@ impl Manifest {
         Summary::new(pid, self.package.version.clone())
     }
 
+    pub fn name(&self) -> &Name {
+        &self.package.name
+    }
+
     pub fn version(&self) -> &Version {
         &self.package.version
     }
+
+    pub fn license(&self) -> Option<&str> {
+        self.package.license.as_ref().map(|s| s.as_str())
+    }
+
+    /// Globs for the files which should be packaged. If there aren't any, everything is.
+    pub fn include(&self) -> &[String] {
+        &self.package.include
+    }
+
+    /// Globs for the files which should never be packaged.
+    pub fn exclude(&self) -> &[String] {
+        &self.package.exclude
+    }
+
+    pub fn readme(&self) -> Option<&Path> {
+        self.package.readme.as_ref().map(|p| p.as_path())
+    }
+
+    /// The versions of the Idris compiler which the package can be built with.
+    pub fn idris_version(&self) -> Option<&Constraint> {
+        self.package.idris_version.as_ref()
+    }
+
+    pub fn targets(&self) -> &Targets {
+        &self.targets
+    }
+
+    pub fn lib(&self) -> Option<&LibTarget> {
+        self.targets.lib.as_ref()
+    }
+
+    /// The root modules of the package's library. Unless the manifest says otherwise, this is
+    /// just `Group.Name` (see `Name::module`).
+    pub fn modules(&self) -> Vec<String> {
+        match &self.targets.lib {
+            Some(lib) if !lib.modules.is_empty() => lib.modules.clone(),
+            _ => vec![self.package.name.module()],
+        }
+    }
+
+    /// The settings of each build profile, which the config file's can override.
+    pub fn profiles(&self) -> &Profiles {
+        &self.profile
+    }
+
+    /// The module prefixes which dependencies are imported under instead of their own.
+    pub fn aliases(&self) -> &IndexMap<Name, String> {
+        &self.aliases
+    }
+
+    /// The metadata which goes into the index entry of the package when it's published.
+    pub fn metadata(&self) -> Metadata {
+        let p = &self.package;
+
+        Metadata {
+            description: p.description.clone(),
+            license: p.license.clone(),
+            repository: p.repository.as_ref().map(|u| u.to_string()),
+            homepage: p.homepage.as_ref().map(|u| u.to_string()),
+            documentation: p.documentation.as_ref().map(|u| u.to_string()),
+            keywords: p.keywords.clone(),
+            categories: p.categories.clone(),
+            readme: p.readme.as_ref().map(|r| r.to_string_lossy().into_owned()),
+            idris_version: p.idris_version.clone(),
+        }
+    }
 }
 
 impl FromStr for Manifest {
     type Err = Error;
 
     fn from_str(raw: &str) -> Result<Self, Self::Err> {
-        toml::from_str(raw)
-            .context(ErrorKind::InvalidManifestFile)
-            .map_err(Error::from)
+        let manifest: Manifest = toml::from_str(raw).context(ErrorKind::InvalidManifestFile)?;
+        manifest
+            .validate()
+            .context(ErrorKind::InvalidManifestFile)?;
+
+        Ok(manifest)
+    }
+}
+
+impl Manifest {
+    /// Fills in the targets of a manifest which doesn't declare any, going by the files in the
+    /// package at `root`: `src/Main.idr` becomes a binary named after the package, and if there's
+    /// anything else in `src/`, it becomes the library.
+    pub fn fill_targets(&mut self, root: &Path) {
+        let targets = &mut self.targets;
+        if targets.lib.is_some() || !targets.bin.is_empty() {
+            return;
+        }
+
+        let src = root.join("src");
+        let main = src.join("Main.idr");
+        if main.is_file() {
+            targets.bin.push(BinTarget {
+                name: self.package.name.name().to_owned(),
+                main: PathBuf::from("src/Main.idr"),
+                backends: vec![],
+                opts: TargetOpts::default(),
+            });
+        }
+
+        let has_lib = fs::read_dir(&src)
+            .map(|mut entries| entries.any(|e| e.map(|e| e.path() != main).unwrap_or(false)))
+            .unwrap_or(false);
+        if has_lib {
+            targets.lib = Some(LibTarget {
+                path: PathBuf::from("src/"),
+                modules: vec![],
+                opts: TargetOpts::default(),
+            });
+        }
+    }
+
+    /// Checks that the targets of the package at `root` point at files which exist, that no two
+    /// binaries have the same name, and that the main file of every binary is the module `Main`.
+    pub fn validate_targets(&self, root: &Path) -> Result<(), Error> {
+        let targets = &self.targets;
+        if targets.lib.is_none() && targets.bin.is_empty() {
+            bail!("the package has no targets; it needs a [targets.lib] or a [[targets.bin]]")
+        }
+
+        if let Some(lib) = &targets.lib {
+            if !root.join(&lib.path).is_dir() {
+                bail!(
+                    "[targets.lib]: the directory `{}` doesn't exist",
+                    lib.path.display()
+                )
+            }
+        }
+
+        let mut names = HashSet::new();
+        for bin in &targets.bin {
+            if !names.insert(&bin.name) {
+                bail!(
+                    "[[targets.bin]] `{}`: there's already a binary with this name",
+                    bin.name
+                )
+            }
+
+            let main = root.join(&bin.main);
+            if !main.is_file() {
+                bail!(
+                    "[[targets.bin]] `{}`: the main file `{}` doesn't exist",
+                    bin.name,
+                    bin.main.display()
+                )
+            }
+
+            let contents = fs::read_to_string(&main)
+                .context(format_err!("could not read `{}`", main.display()))?;
+            let literate = bin.main.extension().map_or(false, |ext| ext == "lidr");
+            match declared_module(&contents, literate) {
+                Some(module) if module != "Main" => bail!(
+                    "[[targets.bin]] `{}`: `{}` should be the module `Main`, not `{}`",
+                    bin.name,
+                    bin.main.display(),
+                    module
+                ),
+                _ => {}
+            }
+        }
+
+        for (kind, targets) in &[("test", &targets.test), ("bench", &targets.bench)] {
+            for target in targets.iter() {
+                if !root.join(&target.path).exists() {
+                    bail!(
+                        "[[targets.{}]]: `{}` doesn't exist",
+                        kind,
+                        target.path.display()
+                    )
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        self.package.validate()?;
+
+        if let Some(lib) = &self.targets.lib {
+            for module in &lib.modules {
+                if !valid_module(module) {
+                    bail!("the lib module `{}` isn't a valid module name", module)
+                }
+            }
+            lib.opts
+                .validate()
+                .context(format_err!("invalid [targets.lib]"))?;
+        }
+        for bin in &self.targets.bin {
+            for backend in &bin.backends {
+                let valid = !backend.is_empty()
+                    && backend
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+                if !valid {
+                    bail!(
+                        "[[targets.bin]] `{}`: `{}` isn't a valid backend name",
+                        bin.name,
+                        backend
+                    )
+                }
+            }
+            bin.opts
+                .validate()
+                .context(format_err!("invalid [[targets.bin]] `{}`", bin.name))?;
+        }
+        for target in self.targets.test.iter().chain(self.targets.bench.iter()) {
+            target
+                .opts
+                .validate()
+                .context(format_err!("invalid target `{}`", target.path.display()))?;
+        }
+
+        let mut prefixes = IndexMap::new();
+        for (name, prefix) in &self.aliases {
+            if !self.dependencies.contains_key(name) && !self.dev_dependencies.contains_key(name) {
+                bail!("the alias for {} doesn't refer to a dependency", name)
+            }
+            if !valid_module(prefix) {
+                bail!(
+                    "the alias `{}` for {} isn't a valid module name",
+                    prefix,
+                    name
+                )
+            }
+            if let Some(other) = prefixes.insert(prefix, name) {
+                bail!("{} and {} are both aliased as `{}`", other, name, prefix)
+            }
+        }
+
+        Ok(())
     }
 }
 
@@ -74,6 +298,72 @@ struct PackageInfo {
     version: Version,
     authors: Vec<String>,
     license: Option<String>,
+    #[serde(default)]
+    include: Vec<String>,
+    #[serde(default)]
+    exclude: Vec<String>,
+    description: Option<String>,
+    #[serde(default, with = "url_serde")]
+    repository: Option<Url>,
+    #[serde(default, with = "url_serde")]
+    homepage: Option<Url>,
+    #[serde(default, with = "url_serde")]
+    documentation: Option<Url>,
+    #[serde(default)]
+    keywords: Vec<String>,
+    #[serde(default)]
+    categories: Vec<String>,
+    readme: Option<PathBuf>,
+    #[serde(rename = "idris-version")]
+    idris_version: Option<Constraint>,
+}
+
+impl PackageInfo {
+    fn validate(&self) -> Result<(), Error> {
+        if let Some(license) = &self.license {
+            if !valid_license(license) {
+                bail!("`{}` isn't a valid SPDX license expression", license)
+            }
+        }
+
+        for url in &[&self.repository, &self.homepage, &self.documentation] {
+            if let Some(url) = url {
+                if url.scheme() != "http" && url.scheme() != "https" {
+                    bail!("`{}` isn't a web url", url)
+                }
+            }
+        }
+
+        if self.keywords.len() > 5 {
+            bail!("packages can't have more than 5 keywords")
+        }
+        for keyword in &self.keywords {
+            let valid = !keyword.is_empty()
+                && keyword.len() <= 20
+                && keyword
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+            if !valid {
+                bail!(
+                    "the keyword `{}` should be at most 20 letters, numbers, `-`s or `_`s",
+                    keyword
+                )
+            }
+        }
+
+        // Whether the readme actually exists (and gets packaged) is checked when packaging.
+        if let Some(readme) = &self.readme {
+            let inside = readme.components().all(|c| match c {
+                Component::CurDir | Component::Normal(_) => true,
+                _ => false,
+            });
+            if !inside {
+                bail!("the readme `{}` isn't inside the package", readme.display())
+            }
+        }
+
+        Ok(())
+    }
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -82,7 +372,8 @@ pub enum DepReq {
     Registry(Constraint),
     RegLong {
         con: Constraint,
-        registry: IndexRes,
+        /// The name of the index the package comes from, or a full index url.
+        registry: String,
     },
     Local {
         path: PathBuf,
@@ -97,20 +388,28 @@ pub enum DepReq {
 }
 
 impl DepReq {
-    pub fn into_dep(self, def_index: IndexRes, n: Name) -> (PackageId, Constraint) {
+    /// Turns this dependency into the id of the package it depends on, along with the versions
+    /// of it which are acceptable. The names of indices are looked up in `names`.
+    pub fn into_dep(
+        self,
+        def_index: IndexRes,
+        names: &IndexNames,
+        n: Name,
+    ) -> Result<(PackageId, Constraint), Error> {
         match self {
             DepReq::Registry(c) => {
                 let pi = PackageId::new(n, def_index.into());
-                (pi, c)
+                Ok((pi, c))
             }
             DepReq::RegLong { con, registry } => {
+                let registry = names.resolve(&registry)?;
                 let pi = PackageId::new(n, registry.into());
-                (pi, con)
+                Ok((pi, con))
             }
             DepReq::Local { path } => {
                 let res = DirectRes::Dir { url: path };
                 let pi = PackageId::new(n, res.into());
-                (pi, Constraint::any())
+                Ok((pi, Constraint::any()))
             }
             DepReq::Git { git, spec } => {
                 let res = DirectRes::Git {
@@ -118,7 +417,7 @@ impl DepReq {
                     tag: spec,
                 };
                 let pi = PackageId::new(n, res.into());
-                (pi, Constraint::any())
+                Ok((pi, Constraint::any()))
             }
         }
     }
@@ -168,9 +467,9 @@ impl Default for PkgGitSpecifier {
     }
 }
 
-#[derive(Deserialize, Debug)]
-struct Targets {
-    lib: Option<Target>,
+#[derive(Deserialize, Debug, Default)]
+pub struct Targets {
+    lib: Option<LibTarget>,
     #[serde(default = "Vec::new")]
     bin: Vec<BinTarget>,
     #[serde(default = "Vec::new")]
@@ -179,21 +478,225 @@ struct Targets {
     bench: Vec<Target>,
 }
 
+impl Targets {
+    pub fn lib(&self) -> Option<&LibTarget> {
+        self.lib.as_ref()
+    }
+
+    pub fn bins(&self) -> &[BinTarget] {
+        &self.bin
+    }
+
+    pub fn tests(&self) -> &[Target] {
+        &self.test
+    }
+
+    pub fn benches(&self) -> &[Target] {
+        &self.bench
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Target {
+    path: PathBuf,
+    #[serde(flatten)]
+    opts: TargetOpts,
+}
+
+impl Target {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn opts(&self) -> &TargetOpts {
+        &self.opts
+    }
+}
+
+/// Settings for how the compiler is run when building a target.
+#[derive(Clone, Deserialize, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub struct TargetOpts {
+    /// Extra flags to pass to the compiler, like `--total` or `--warnreach`.
+    #[serde(default)]
+    pub idris_opts: Vec<String>,
+    /// The packages which come with the compiler to enable, like `contrib` or `effects`.
+    #[serde(default)]
+    pub packages: Vec<String>,
+    /// Environment variables to set for the compiler.
+    #[serde(default)]
+    pub env: IndexMap<String, String>,
+}
+
+impl TargetOpts {
+    fn validate(&self) -> Result<(), Error> {
+        for package in &self.packages {
+            if package.is_empty() || package.starts_with('-') {
+                bail!("`{}` isn't a valid package name", package)
+            }
+        }
+
+        for var in self.env.keys() {
+            if var.is_empty() || var.contains('=') || var.contains('\0') {
+                bail!("`{}` isn't a valid environment variable name", var)
+            }
+        }
+
+        Ok(())
+    }
+}
+
 #[derive(Deserialize, Debug)]
-struct Target {
+pub struct LibTarget {
     path: PathBuf,
+    /// The root modules which every module of the library lives under.
+    #[serde(default)]
+    modules: Vec<String>,
+    #[serde(flatten)]
+    opts: TargetOpts,
+}
+
+impl LibTarget {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn opts(&self) -> &TargetOpts {
+        &self.opts
+    }
 }
 
 #[derive(Deserialize, Debug)]
-struct BinTarget {
+pub struct BinTarget {
     name: String,
     // For binaries, benches, and tests, this should point to a file with a Main module.
     main: PathBuf,
+    /// The codegen backends to build the binary with.
+    #[serde(default)]
+    backends: Vec<String>,
+    #[serde(flatten)]
+    opts: TargetOpts,
+}
+
+impl BinTarget {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn main(&self) -> &Path {
+        &self.main
+    }
+
+    pub fn backends(&self) -> &[String] {
+        &self.backends
+    }
+
+    pub fn opts(&self) -> &TargetOpts {
+        &self.opts
+    }
+}
+
+/// The module which the Idris source `contents` declares, if it has a module declaration at all.
+/// In literate files, only the lines which start with `>` are code.
+///
+/// This only looks at the start of the file, skipping blank lines, line comments and pragmas.
+fn declared_module(contents: &str, literate: bool) -> Option<&str> {
+    for line in contents.lines() {
+        let line = if literate {
+            match line.trim_left() {
+                line if line.starts_with('>') => line[1..].trim(),
+                _ => continue,
+            }
+        } else {
+            line.trim()
+        };
+
+        if line.starts_with("module ") {
+            return line["module".len()..].split_whitespace().next();
+        }
+
+        let skipped = line.is_empty()
+            || line.starts_with("--")
+            || line.starts_with("|||")
+            || line.starts_with('%');
+        if !skipped {
+            return None;
+        }
+    }
+
+    None
+}
+
+/// Checks that `license` is a well-formed SPDX license expression, like `MIT OR Apache-2.0` or
+/// `(GPL-3.0-or-later WITH Classpath-exception-2.0) AND MIT`.
+///
+/// Only the syntax of the expression is checked; the licenses themselves aren't looked up in the
+/// SPDX license list.
+fn valid_license(license: &str) -> bool {
+    let spaced = license.replace('(', " ( ").replace(')', " ) ");
+    let tokens = spaced.split_whitespace().collect::<Vec<_>>();
+
+    let mut pos = 0;
+    license_expr(&tokens, &mut pos) && pos == tokens.len()
+}
+
+/// `expr := term (("AND" | "OR") term)*`
+fn license_expr(tokens: &[&str], pos: &mut usize) -> bool {
+    if !license_term(tokens, pos) {
+        return false;
+    }
+
+    while *pos < tokens.len() && (tokens[*pos] == "AND" || tokens[*pos] == "OR") {
+        *pos += 1;
+        if !license_term(tokens, pos) {
+            return false;
+        }
+    }
+
+    true
+}
+
+/// `term := "(" expr ")" | id ["WITH" id]`
+fn license_term(tokens: &[&str], pos: &mut usize) -> bool {
+    match tokens.get(*pos) {
+        Some(&"(") => {
+            *pos += 1;
+            if !license_expr(tokens, pos) || tokens.get(*pos) != Some(&")") {
+                return false;
+            }
+            *pos += 1;
+            true
+        }
+        Some(id) if license_id(id) => {
+            *pos += 1;
+            if tokens.get(*pos) == Some(&"WITH") {
+                *pos += 1;
+                if !tokens.get(*pos).map_or(false, |id| license_id(id)) {
+                    return false;
+                }
+                *pos += 1;
+            }
+            true
+        }
+        _ => false,
+    }
+}
+
+/// License ids are made of letters, numbers, `.`s and `-`s, and can end in a `+`.
+fn license_id(id: &str) -> bool {
+    let id = id.trim_right_matches('+');
+
+    !id.is_empty()
+        && !["AND", "OR", "WITH"].contains(&id)
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-')
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::env;
 
     #[test]
     fn valid_manifest() {
@@ -208,6 +711,7 @@ license = 'MIT'
 'awesome/a' = '>= 1.0.0 < 2.0.0'
 'cool/b' = { git = 'https://github.com/super/cool', tag = "v1.0.0" }
 'great/c' = { path = 'file://here/right/now' }
+'super/d' = { con = '1.0.0', registry = 'official' }
 
 [dev_dependencies]
 'ayy/x' = '2.0'
@@ -222,4 +726,218 @@ path = "src/lib/"
 
         assert!(Manifest::from_str(manifest).is_ok());
     }
+
+    #[test]
+    fn dep_registry_name() {
+        let official = IndexRes::from_str("index+dir+file://official").unwrap();
+        let names = IndexNames::new(indexmap!("official".to_owned() => official.clone()));
+        let def_index = IndexRes::from_str("index+dir+file://default").unwrap();
+        let name = Name::from_str("super/d").unwrap();
+
+        let dep = DepReq::RegLong {
+            con: Constraint::any(),
+            registry: "official".to_owned(),
+        };
+        let (pid, _) = dep
+            .into_dep(def_index.clone(), &names, name.clone())
+            .unwrap();
+        assert_eq!(pid.resolution(), &Resolution::Index(official));
+
+        let dep = DepReq::RegLong {
+            con: Constraint::any(),
+            registry: "unofficial".to_owned(),
+        };
+        assert!(dep.into_dep(def_index, &names, name).is_err());
+    }
+
+    #[test]
+    fn package_metadata() {
+        let manifest = r#"
+[package]
+name = 'ring_ding/test'
+version = '1.0.0'
+authors = ['me']
+license = '(MIT OR Apache-2.0) AND GPL-3.0+ WITH Classpath-exception-2.0'
+description = 'A test'
+repository = 'https://github.com/ring_ding/test'
+keywords = ['testing', 'idris-2']
+readme = 'README.md'
+idris-version = '>= 1.3.0'
+
+[targets.lib]
+path = "src/lib/"
+"#;
+
+        let manifest = Manifest::from_str(manifest).unwrap();
+        let metadata = manifest.metadata();
+        assert_eq!(metadata.description, Some("A test".to_owned()));
+        assert_eq!(
+            metadata.repository,
+            Some("https://github.com/ring_ding/test".to_owned())
+        );
+        assert_eq!(metadata.keywords, vec!["testing", "idris-2"]);
+        assert_eq!(manifest.readme(), Some(Path::new("README.md")));
+        assert_eq!(metadata.readme, Some("README.md".to_owned()));
+        assert!(metadata.idris_version.is_some());
+    }
+
+    #[test]
+    fn invalid_metadata() {
+        let manifest = |extra: &str| {
+            format!(
+                "[package]\nname = 'ring_ding/test'\nversion = '1.0.0'\nauthors = []\n{}\n\n\
+                 [targets.lib]\npath = 'src/'\n",
+                extra
+            )
+        };
+
+        assert!(Manifest::from_str(&manifest("license = 'MIT'")).is_ok());
+        for bad in &[
+            "license = 'MIT OR'",
+            "license = '(MIT'",
+            "license = 'MIT AND AND Apache-2.0'",
+            "license = 'MIT WITH'",
+            "homepage = 'not a url'",
+            "homepage = 'ftp://ring.ding'",
+            "keywords = ['no spaces']",
+            "readme = '../README.md'",
+            "readme = '/README.md'",
+        ] {
+            assert!(Manifest::from_str(&manifest(bad)).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn package_aliases() {
+        let manifest = |aliases: &str| {
+            format!(
+                "[package]\nname = 'ring_ding/test'\nversion = '1.0.0'\nauthors = []\n\n\
+                 [dependencies]\n'me/lightyear' = '1.0'\n'you/lightyear' = '1.0'\n\n\
+                 [aliases]\n{}\n\n[targets.lib]\npath = 'src/'\n",
+                aliases
+            )
+        };
+
+        let ok = Manifest::from_str(&manifest("'me/lightyear' = 'Yeet.Lightyeet'")).unwrap();
+        assert_eq!(
+            ok.aliases()[&Name::from_str("me/lightyear").unwrap()],
+            "Yeet.Lightyeet"
+        );
+
+        for bad in &[
+            "'they/lightyear' = 'Yeet.Lightyeet'",
+            "'me/lightyear' = 'Yeet..Lightyeet'",
+            "'me/lightyear' = 'Yeet'\n'you/lightyear' = 'Yeet'",
+        ] {
+            assert!(Manifest::from_str(&manifest(bad)).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn main_module() {
+        assert_eq!(
+            declared_module("module Main\n\nmain : IO ()", false),
+            Some("Main")
+        );
+        assert_eq!(
+            declared_module("-- A comment\n%default total\nmodule Other", false),
+            Some("Other")
+        );
+        assert_eq!(declared_module("main : IO ()\nmodule Other", false), None);
+        assert_eq!(
+            declared_module("Some prose.\n\n> module Lit\n", true),
+            Some("Lit")
+        );
+    }
+
+    #[test]
+    fn default_targets() {
+        let root = env::temp_dir().join("elba-manifest-test-targets");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("src/Ring")).unwrap();
+        fs::write(root.join("src/Main.idr"), "module Main").unwrap();
+        fs::write(root.join("src/Ring/Ding.idr"), "module Ring.Ding").unwrap();
+
+        let raw = "[package]\nname = 'ring/ding'\nversion = '1.0.0'\nauthors = []\n";
+        let mut manifest = Manifest::from_str(raw).unwrap();
+        assert!(manifest.validate_targets(&root).is_err());
+
+        manifest.fill_targets(&root);
+        assert!(manifest.validate_targets(&root).is_ok());
+        assert_eq!(manifest.lib().unwrap().path(), Path::new("src/"));
+        assert_eq!(manifest.targets().bins()[0].name(), "ding");
+        assert_eq!(
+            manifest.targets().bins()[0].main(),
+            Path::new("src/Main.idr")
+        );
+
+        let bad = |targets: &str| {
+            let manifest = Manifest::from_str(&format!("{}\n{}", raw, targets)).unwrap();
+            manifest.validate_targets(&root).is_err()
+        };
+        assert!(bad("[targets.lib]\npath = 'lib/'"));
+        assert!(bad("[[targets.bin]]\nname = 'a'\nmain = 'src/Nope.idr'"));
+        assert!(bad(
+            "[[targets.bin]]\nname = 'a'\nmain = 'src/Ring/Ding.idr'"
+        ));
+        let bin = "[[targets.bin]]\nname = 'a'\nmain = 'src/Main.idr'\n";
+        assert!(bad(&format!("{}{}", bin, bin)));
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn target_opts() {
+        let manifest = r#"
+[package]
+name = 'ring_ding/test'
+version = '1.0.0'
+authors = []
+
+[targets.lib]
+path = 'src/'
+idris-opts = ['--total', '--warnreach']
+packages = ['contrib', 'effects']
+env = { IDRIS_CC = 'clang' }
+
+[[targets.bin]]
+name = 'bin'
+main = 'src/Main.idr'
+"#;
+
+        let manifest = Manifest::from_str(manifest).unwrap();
+        let opts = manifest.lib().unwrap().opts();
+        assert_eq!(opts.idris_opts, vec!["--total", "--warnreach"]);
+        assert_eq!(opts.packages, vec!["contrib", "effects"]);
+        assert_eq!(opts.env["IDRIS_CC"], "clang");
+        assert_eq!(manifest.targets().bins()[0].opts(), &TargetOpts::default());
+
+        let bad = "[package]\nname = 'a/b'\nversion = '1.0.0'\nauthors = []\n\
+                   [targets.lib]\npath = 'src/'\npackages = ['-p']\n";
+        assert!(Manifest::from_str(bad).is_err());
+    }
+
+    #[test]
+    fn manifest_profiles() {
+        let manifest = r#"
+[package]
+name = 'ring_ding/test'
+version = '1.0.0'
+authors = []
+
+[profile.release]
+total = true
+codegen-opts = ['-O3']
+warnings-as-errors = true
+docs = true
+"#;
+
+        let manifest = Manifest::from_str(manifest).unwrap();
+        let release = &manifest.profiles().release;
+        assert_eq!(release.total, Some(true));
+        assert_eq!(release.codegen_opts, Some(vec!["-O3".to_owned()]));
+        assert_eq!(release.warnings_as_errors, Some(true));
+        assert_eq!(release.docs, Some(true));
+        assert_eq!(manifest.profiles().dev, Default::default());
+    }
 }
@@ -7,6 +7,7 @@ pub mod version;
 
 use self::resolution::Resolution;
 use failure::Error;
+use inflector::Inflector;
 use semver::Version;
 use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use std::{fmt, rc::Rc, str::FromStr};
@@ -61,6 +62,26 @@ impl Name {
     pub fn as_bytes(&self) -> &[u8] {
         &self.inner.serialization.as_bytes()
     }
+
+    /// The module which the package's modules live under by default: its group and name in
+    /// PascalCase, separated by a dot. For example, `ring_ding/my-pkg` becomes `RingDing.MyPkg`.
+    pub fn module(&self) -> String {
+        format!(
+            "{}.{}",
+            self.group().to_pascal_case(),
+            self.name().to_pascal_case()
+        )
+    }
+}
+
+/// Checks that `module` is a valid name for an Idris module, like `Data.Vect`: a dot-separated
+/// list of identifiers which start with a letter.
+pub fn valid_module(module: &str) -> bool {
+    module.split('.').all(|part| {
+        let mut chars = part.chars();
+        chars.next().map_or(false, |c| c.is_alphabetic())
+            && chars.all(|c| c.is_alphanumeric() || c == '_' || c == '\'')
+    })
 }
 
 impl FromStr for Name {
@@ -287,3 +308,18 @@ impl fmt::Debug for Summary {
         write!(f, "{}", self)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn name_module() {
+        let name = Name::from_str("ring_ding/my-pkg").unwrap();
+        assert_eq!(name.module(), "RingDing.MyPkg");
+        assert!(valid_module(&name.module()));
+        assert!(valid_module("Data.Vect'"));
+        assert!(!valid_module("Data."));
+        assert!(!valid_module("Data.1Vect"));
+    }
+}
@@ -1,15 +1,26 @@
-use super::{manifest::PkgGitSpecifier, Checksum};
+use super::{manifest::PkgGitSpecifier, Checksum, ChecksumFmt};
 use failure::{Error, ResultExt};
 use flate2::read::GzDecoder;
-use git2::Repository;
-use reqwest::Client;
+use git2::{build::RepoBuilder, RemoteCallbacks, Repository, ResetType};
+use reqwest::header::ContentLength;
 use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use sha2::{Digest, Sha256};
-use std::{fmt, fs, io::BufReader, path::PathBuf, str::FromStr};
+use std::{
+    fmt, fs,
+    io::{prelude::*, BufReader, SeekFrom},
+    path::PathBuf,
+    str::FromStr,
+};
 use tar::Archive;
 use url::Url;
 use util::errors::ErrorKind;
-use util::{hexify_hash, lock::DirLock};
+use util::{
+    archive::{unpack, ExtractLimits},
+    hexify_hash,
+    lock::DirLock,
+    net::Net,
+    shell::Progress,
+};
 
 /// The possible places from which a package can be resolved.
 ///
@@ -91,43 +102,67 @@ pub enum DirectRes {
     /// itself. Checksums are stored in the fragment of the resolution url, with they key being the
     /// checksum format.
     Tar { url: Url, cksum: Option<Checksum> },
+    /// Sparse: a directory served over HTTP, whose files are fetched one at a time as they're
+    /// needed.
+    ///
+    /// This only makes sense for indices: retrieving a sparse resolution only retrieves its
+    /// `index.toml`, and `Index::entries` takes care of fetching everything else.
+    Sparse { url: Url },
 }
 
 impl DirectRes {
-    // TODO: argument progress: impl Fn(u8) (u8 = 0-100)
-    pub fn retrieve(&self, client: &Client, target: &DirLock) -> Result<(), Error> {
+    /// Retrieves the package at this location into the directory `target`, reporting how far
+    /// along the retrieval is to `progress`.
+    pub fn retrieve(
+        &self,
+        net: &Net,
+        target: &DirLock,
+        limits: &ExtractLimits,
+        progress: &Progress,
+    ) -> Result<(), Error> {
         match self {
             DirectRes::Tar { url, cksum } => match url.scheme() {
-                "http" | "https" => client
-                    .get(url.clone())
-                    .send()
-                    .map_err(|_| Error::from(ErrorKind::CannotDownload))
-                    .and_then(|mut r| {
-                        let mut buf: Vec<u8> = vec![];
-                        r.copy_to(&mut buf).context(ErrorKind::CannotDownload)?;
-
-                        let hash = hexify_hash(Sha256::digest(&buf[..]).as_slice());
-                        if let Some(cksum) = cksum {
-                            if cksum.hash == hash {
-                                return Err(ErrorKind::Checksum)?;
+                "http" | "https" => {
+                    let buf = net
+                        .retry(|| {
+                            let mut r = net.get(url)?;
+                            let total = r.headers().get::<ContentLength>().map(|l| l.0);
+
+                            let mut buf: Vec<u8> = vec![];
+                            let mut chunk = [0; 16 * 1024];
+                            loop {
+                                if progress.cancelled() {
+                                    bail!("download of `{}` was cancelled", url);
+                                }
+
+                                let n = r.read(&mut chunk)?;
+                                if n == 0 {
+                                    break;
+                                }
+                                buf.extend_from_slice(&chunk[..n]);
+                                progress.bytes(buf.len() as u64, total);
                             }
-                        }
 
-                        let archive = BufReader::new(&buf[..]);
-                        let archive = GzDecoder::new(archive);
-                        let mut archive = Archive::new(archive);
+                            Ok(buf)
+                        })
+                        .context(ErrorKind::CannotDownload)?;
+
+                    let hash = hexify_hash(Sha256::digest(&buf[..]).as_slice());
+                    verify(url, cksum, &hash)?;
 
-                        fs::create_dir(target.path())?;
+                    let archive = BufReader::new(&buf[..]);
+                    let archive = GzDecoder::new(archive);
+                    let mut archive = Archive::new(archive);
 
-                        archive
-                            .unpack(target.path())
-                            .context(ErrorKind::CannotDownload)?;
+                    unpack(&mut archive, target.path(), limits)?;
 
-                        Ok(())
-                    }),
+                    Ok(())
+                }
                 "file" => {
-                    let mut archive =
-                        fs::File::open(target.path()).context(ErrorKind::CannotDownload)?;
+                    let path = url
+                        .to_file_path()
+                        .map_err(|_| ErrorKind::InvalidSourceUrl)?;
+                    let mut archive = fs::File::open(path).context(ErrorKind::CannotDownload)?;
 
                     let hash = hexify_hash(
                         Sha256::digest_reader(&mut archive)
@@ -135,61 +170,136 @@ impl DirectRes {
                             .as_slice(),
                     );
 
-                    if let Some(cksum) = cksum {
-                        if cksum.hash == hash {
-                            return Err(ErrorKind::Checksum)?;
-                        }
-                    }
+                    verify(url, cksum, &hash)?;
+
+                    let len = archive.metadata().context(ErrorKind::CannotDownload)?.len();
+                    progress.bytes(len, Some(len));
+                    archive
+                        .seek(SeekFrom::Start(0))
+                        .context(ErrorKind::CannotDownload)?;
 
                     let archive = BufReader::new(archive);
                     let archive = GzDecoder::new(archive);
                     let mut archive = Archive::new(archive);
 
-                    archive
-                        .unpack(target.path())
-                        .context(ErrorKind::CannotDownload)?;
+                    unpack(&mut archive, target.path(), limits)?;
 
                     Ok(())
                 }
                 _ => Err(Error::from(ErrorKind::CannotDownload)),
             },
             DirectRes::Git { repo, tag } => {
-                // If we find a directory which already has a repo, we just check out the correct
-                // version of it. Whether or not a new dir is created isn't our job, that's for the
-                // Cache. If the Cache points to a directory that already exists, it means that the
-                // branch data or w/e is irrelevant.
-                let repo = if target.path().is_dir() {
-                    Repository::open(target.path()).context(ErrorKind::CannotDownload)?
-                // TODO: Update submodules...
-                // TODO: Fetch from remote...
-                } else {
-                    let url = repo.as_str();
-                    Repository::clone_recurse(url, target.path())
-                        .context(ErrorKind::CannotDownload)?
+                let callbacks = || {
+                    let mut callbacks = RemoteCallbacks::new();
+                    callbacks.transfer_progress(move |stats| {
+                        progress.objects(stats.received_objects(), stats.total_objects());
+                        !progress.cancelled()
+                    });
+                    callbacks
                 };
 
-                let branch = match tag {
-                    PkgGitSpecifier::Branch(a) => a,
-                    PkgGitSpecifier::Commit(a) => a,
-                    PkgGitSpecifier::Tag(a) => a,
-                };
+                // If we find a directory which already has a repo, we fetch whatever's new from
+                // the remote and check out the correct version of it. Whether or not a new dir is
+                // created isn't our job, that's for the Cache. If the Cache points to a directory
+                // that already exists, it means that the branch data or w/e is irrelevant.
+                let r = if target.path().is_dir() {
+                    let r = Repository::open(target.path()).context(ErrorKind::CannotDownload)?;
 
-                let obj = repo
-                    .revparse_single(&branch)
+                    net.retry(|| {
+                        let mut opts = net.fetch_options(repo, callbacks());
+                        r.find_remote("origin")?.fetch(&[], Some(&mut opts), None)?;
+                        Ok(())
+                    })
                     .context(ErrorKind::CannotDownload)?;
-                repo.checkout_tree(&obj, None)
+
+                    r
+                } else {
+                    net.retry(|| {
+                        // Get rid of whatever a previous failed attempt left behind.
+                        let _ = fs::remove_dir_all(target.path());
+
+                        let opts = net.fetch_options(repo, callbacks());
+                        let r = RepoBuilder::new()
+                            .fetch_options(opts)
+                            .clone(repo.as_str(), target.path())?;
+
+                        Ok(r)
+                    })
+                    .context(ErrorKind::CannotDownload)?
+                };
+
+                // Branches are looked up on the remote, since our local copy of a branch never
+                // moves after a fetch.
+                let obj = match tag {
+                    PkgGitSpecifier::Branch(a) => r
+                        .revparse_single(&format!("origin/{}", a))
+                        .or_else(|_| r.revparse_single(a)),
+                    PkgGitSpecifier::Commit(a) => r.revparse_single(a),
+                    PkgGitSpecifier::Tag(a) => r.revparse_single(a),
+                }
+                .context(ErrorKind::CannotDownload)?;
+
+                r.reset(&obj, ResetType::Hard, None)
                     .context(ErrorKind::CannotDownload)?;
 
+                net.retry(|| {
+                    for mut sub in r.submodules()? {
+                        sub.update(true, None)?;
+                    }
+                    Ok(())
+                })
+                .context(ErrorKind::CannotDownload)?;
+
                 Ok(())
             }
             DirectRes::Dir { url: _url } => {
                 // If this package is located on disk, we don't have to do anything...
                 Ok(())
             }
+            DirectRes::Sparse { url } => {
+                let url = sparse_url(url, "index.toml")?;
+                let buf = net
+                    .retry(|| {
+                        let mut buf = vec![];
+                        net.get(&url)?.read_to_end(&mut buf)?;
+                        Ok(buf)
+                    })
+                    .context(ErrorKind::CannotDownload)?;
+                progress.bytes(buf.len() as u64, Some(buf.len() as u64));
+
+                fs::create_dir_all(target.path()).context(ErrorKind::CannotDownload)?;
+                fs::write(target.path().join("index.toml"), buf)
+                    .context(ErrorKind::CannotDownload)?;
+
+                Ok(())
+            }
         }
     }
 }
 
+/// The url of the file at `path` (relative to the root) in the sparse index at `base`.
+pub fn sparse_url(base: &Url, path: &str) -> Result<Url, Error> {
+    let url = format!("{}/{}", base.as_str().trim_right_matches('/'), path);
+    Ok(Url::parse(&url).context(ErrorKind::InvalidSourceUrl)?)
+}
+
+/// Makes sure that the sha256 `hash` of the tarball at `url` matches its expected checksum, if it
+/// has one.
+fn verify(url: &Url, cksum: &Option<Checksum>, hash: &str) -> Result<(), Error> {
+    if let Some(cksum) = cksum {
+        if cksum.fmt != ChecksumFmt::Sha256 || !cksum.hash.eq_ignore_ascii_case(hash) {
+            return Err(ErrorKind::Checksum).context(format_err!(
+                "the checksum of `{}` should be `{}`, but it was `sha256={}`",
+                url,
+                cksum,
+                hash
+            ))?;
+        }
+    }
+
+    Ok(())
+}
+
 impl FromStr for DirectRes {
     type Err = Error;
 
@@ -228,6 +338,13 @@ impl FromStr for DirectRes {
                 url.set_fragment(None);
                 Ok(DirectRes::Tar { url, cksum })
             }
+            "sparse" => {
+                let url = Url::parse(url).context(ErrorKind::InvalidSourceUrl)?;
+                if url.scheme() != "http" && url.scheme() != "https" {
+                    return Err(ErrorKind::InvalidSourceUrl)?;
+                }
+                Ok(DirectRes::Sparse { url })
+            }
             _ => Err(ErrorKind::InvalidSourceUrl)?,
         }
     }
@@ -251,6 +368,7 @@ impl fmt::Display for DirectRes {
                     },
                 )
             }
+            DirectRes::Sparse { url } => write!(f, "sparse+{}", url),
         }
     }
 }
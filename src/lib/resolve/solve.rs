@@ -5,7 +5,7 @@ use package::{
 };
 use petgraph::{
     graph::NodeIndex,
-    visit::{Bfs, IntoNodeReferences, Walker},
+    visit::{Bfs, DfsPostOrder, IntoNodeReferences, Walker},
     Direction, Graph,
 };
 use retrieve::cache::Source;
@@ -34,6 +34,17 @@ impl Solve {
         )
     }
 
+    /// Traverses `root` and all of its (transitive) dependencies, such that every package comes
+    /// after all of its dependencies. This is the order packages have to be built in.
+    pub fn deps_first<'a>(&'a self, root: &Summary) -> Option<impl Iterator<Item = &Summary> + 'a> {
+        let root = self.find_node(root)?;
+        Some(
+            DfsPostOrder::new(&self.graph, root)
+                .iter(&self.graph)
+                .map(move |node_id| &self.graph[node_id]),
+        )
+    }
+
     pub fn find_node(&self, node: &Summary) -> Option<NodeIndex> {
         self.graph
             .node_references()
@@ -41,6 +52,11 @@ impl Solve {
             .map(|(index, _)| index)
     }
 
+    /// Iterates over every package in the solve, in no particular order.
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item = &Summary> + 'a {
+        self.graph.node_references().map(|(_, sum)| sum)
+    }
+
     pub fn get_pkg_version(&self, node: &PackageId) -> Option<Version> {
         self.graph
             .node_references()
@@ -48,28 +48,41 @@
 //! #### Build caching
 //! If we want to cache builds, we can just have a separate subfolder for ibcs.
 
+use build::context::BuildContext;
 use copy_dir::copy_dir;
 use failure::{Error, ResultExt};
-use index::{Index, Indices};
-use indexmap::IndexMap;
+use index::{keys::PinnedKeys, Index, IndexNames, Indices};
+use indexmap::{IndexMap, IndexSet};
 use package::{
     manifest::Manifest,
-    resolution::{DirectRes, IndexRes},
+    resolution::{DirectRes, IndexRes, Resolution},
     version::Constraint,
     Name, PackageId, Summary,
 };
-use reqwest::Client;
 use resolve::solve::Solve;
+use retrieve::download::{download_all, Download, MAX_DOWNLOADS};
 use semver::Version;
 use sha2::{Digest, Sha256};
 use slog::Logger;
 use std::{
     fs,
     io::{prelude::*, BufReader},
-    path::PathBuf,
+    path::{Path, PathBuf},
     str::FromStr,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use toml;
+use util::{
+    archive::ExtractLimits,
+    config::Verbosity,
+    credentials::Credentials,
+    errors::ErrorKind,
+    hexify_hash,
+    lock::DirLock,
+    net::{Net, NetConfig},
+    shell::{Progress, Shell},
 };
-use util::{errors::ErrorKind, hexify_hash, lock::DirLock};
 
 /// Metadata for a package in the Cache.
 ///
@@ -82,6 +95,29 @@ pub struct CacheMeta {
     pub deps: IndexMap<PackageId, Constraint>,
 }
 
+/// When cached indices should be brought up to date with their source.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct UpdatePolicy {
+    /// How many seconds a cached index stays fresh for. Once an index is older than this, it gets
+    /// updated the next time it's used. A TTL of 0 updates indices every time.
+    pub ttl: u64,
+}
+
+impl Default for UpdatePolicy {
+    fn default() -> Self {
+        UpdatePolicy { ttl: 60 * 60 }
+    }
+}
+
+/// What the Cache remembers about a cached index. This lives in a file next to the index's
+/// directory.
+#[derive(Debug, Deserialize, Serialize)]
+struct IndexMeta {
+    /// When the index was last downloaded or updated, in seconds since the Unix epoch.
+    last_updated: u64,
+}
+
 /// A Cache of downloaded packages and packages with no other Index.
 ///
 /// A Cache is located in a directory, and it has two directories of its own:
@@ -95,7 +131,13 @@ pub struct CacheMeta {
 pub struct Cache {
     location: PathBuf,
     def_index: IndexRes,
-    client: Client,
+    names: IndexNames,
+    /// The network client to download with. Unless one is given with `with_net`, one with the
+    /// default settings is made the first time it's needed.
+    net: Arc<Mutex<Option<Net>>>,
+    limits: ExtractLimits,
+    policy: UpdatePolicy,
+    shell: Shell,
     pub logger: Logger,
 }
 
@@ -105,17 +147,61 @@ impl Cache {
         let _ = fs::create_dir_all(location.join("build"));
         let _ = fs::create_dir_all(location.join("indices"));
 
-        let client = Client::new();
         let logger = plog.new(o!("location" => location.to_string_lossy().into_owned()));
 
         Cache {
             location,
             def_index,
-            client,
+            names: IndexNames::default(),
+            net: Arc::new(Mutex::new(None)),
+            limits: ExtractLimits::default(),
+            policy: UpdatePolicy::default(),
+            // By default, we don't report any progress; that's up to whoever uses the Cache.
+            shell: Shell::new(Verbosity::Quiet, false),
             logger,
         }
     }
 
+    /// Sets the names which the dependencies of packages can refer to indices by.
+    pub fn with_names(mut self, names: IndexNames) -> Self {
+        self.names = names;
+        self
+    }
+
+    /// Sets the limits which downloaded archives have to stay within when they get extracted.
+    pub fn with_limits(mut self, limits: ExtractLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Sets the network client (and so the timeouts, retries and proxies) used for downloads.
+    pub fn with_net(mut self, net: Net) -> Self {
+        self.net = Arc::new(Mutex::new(Some(net)));
+        self
+    }
+
+    /// Sets how often cached indices get updated.
+    pub fn with_policy(mut self, policy: UpdatePolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Sets the shell which reports the progress of downloads to the user.
+    pub fn with_shell(mut self, shell: Shell) -> Self {
+        self.shell = shell;
+        self
+    }
+
+    /// The network client to download with.
+    fn net(&self) -> Result<Net, Error> {
+        let mut net = self.net.lock().unwrap();
+        if net.is_none() {
+            *net = Some(Net::new(NetConfig::default(), Credentials::default())?);
+        }
+
+        Ok(net.clone().unwrap())
+    }
+
     /// Retrieve the metadata of a package, loading it into the cache if necessary. This should
     /// only be used for non-index dependencies.
     pub fn checkout_source(
@@ -125,7 +211,7 @@ impl Cache {
         v: Option<&Version>,
     ) -> Result<Source, Error> {
         let p = self.load(pkg, loc, v)?;
-        let mf_path = p.path().join("Cargo.toml");
+        let mf_path = p.path().join("elba.toml");
 
         let file = fs::File::open(mf_path).context(ErrorKind::MissingManifest)?;
         let mut file = BufReader::new(file);
@@ -140,7 +226,7 @@ impl Cache {
         // We ignore dev-dependencies because those are only relevant if that package is the root
         for (n, dep) in &manifest.dependencies {
             let dep = dep.clone();
-            let (pid, c) = dep.into_dep(self.def_index.clone(), n.clone());
+            let (pid, c) = dep.into_dep(self.def_index.clone(), &self.names, n.clone())?;
             deps.insert(pid, c);
         }
 
@@ -155,17 +241,11 @@ impl Cache {
         Ok(source)
     }
 
-    // TODO: In the future (heh), return Box<Future<Item = PathBuf, Error = Error>> and use async
-    // reqwest. For now, it seems like too much trouble for not that much gain.
-    // Info on async:
-    // https://stackoverflow.com/questions/49087958/getting-multiple-urls-concurrently-with-hyper
-    // Info on downloading things in general:
-    // https://rust-lang-nursery.github.io/rust-cookbook/web/clients/download.html
-    /// Returns a future pointing to the path to a downloaded (and potentially extracted, if it's a
-    /// tarball) package.
+    /// Returns the path to a downloaded (and potentially extracted, if it's a tarball) package.
     ///
     /// If the package has been cached, this function does no I/O. If it hasn't, it goes wherever
-    /// it needs to in order to retrieve the package.
+    /// it needs to in order to retrieve the package. To retrieve many packages at once, use
+    /// `load_all` instead, which downloads them concurrently.
     pub fn load(
         &self,
         pkg: &PackageId,
@@ -180,12 +260,73 @@ impl Cache {
             p.push(Self::get_src_dir(pkg.name(), loc, v));
 
             let dir = DirLock::acquire(&p)?;
-            loc.retrieve(&self.client, &dir)?;
+            let progress = self.shell.progress(&pkg.to_string());
+            let res = loc.retrieve(&self.net()?, &dir, &self.limits, &progress);
+            progress.finish(res.is_ok());
+            if let Err(e) = res {
+                // Otherwise, `check` would take whatever got retrieved for the whole package.
+                let _ = fs::remove_dir_all(dir.path());
+                return Err(e);
+            }
 
             Ok(dir)
         }
     }
 
+    /// Loads every package in a `Solve` into the cache, returning the locked directory of each
+    /// package.
+    ///
+    /// All of the packages which aren't cached yet are downloaded concurrently. The first download
+    /// to fail stops all of the others, and its error is returned.
+    pub fn load_all(
+        &self,
+        solve: &Solve,
+        indices: &mut Indices,
+    ) -> Result<IndexMap<Summary, DirLock>, Error> {
+        let mut res = indexmap!();
+        let mut pending = vec![];
+        let mut downloads = vec![];
+
+        for sum in solve.iter() {
+            let loc = match sum.resolution() {
+                Resolution::Root => continue,
+                Resolution::Direct(loc) => loc.clone(),
+                Resolution::Index(_) => indices.select(sum)?.location.clone(),
+            };
+
+            if let Some(path) = self.check(sum.name(), &loc, Some(sum.version())) {
+                res.insert(sum.clone(), DirLock::acquire(&path)?);
+                continue;
+            }
+
+            let path = self.location.join("src").join(Self::get_src_dir(
+                sum.name(),
+                &loc,
+                Some(sum.version()),
+            ));
+
+            downloads.push(Download {
+                name: sum.to_string(),
+                res: loc,
+                dir: DirLock::acquire(&path)?,
+            });
+            pending.push(sum.clone());
+        }
+
+        let dirs = download_all(
+            &self.net()?,
+            &self.limits,
+            &self.shell,
+            downloads,
+            MAX_DOWNLOADS,
+            &self.logger,
+        )?;
+
+        res.extend(pending.into_iter().zip(dirs));
+
+        Ok(res)
+    }
+
     // TODO: Workspaces for git repos.
     /// Check if package is downloaded and in the cache. If so, returns the path of the cached
     /// package.
@@ -245,54 +386,244 @@ impl Cache {
         Ok(Binary { build, binary_path })
     }
 
-    // TODO: We do a lot of silent erroring. Is that good?
-    pub fn get_indices(&self, index_reses: &[DirectRes]) -> Indices {
+    /// Locks the directory for the build of an alias of `build` under the module `prefix`. Like
+    /// the builds of packages, the builds of aliases are shared between every package which uses
+    /// them.
+    pub fn checkout_alias(&self, build: &Build, prefix: &str) -> Result<DirLock, Error> {
+        let path = self
+            .location
+            .join("build")
+            .join(format!("{}~{}", build.dir_name(), prefix));
+
+        DirLock::acquire(&path)
+    }
+
+    /// Loads all of the given indices, along with every index they (transitively) depend on,
+    /// downloading the ones which aren't cached yet concurrently.
+    ///
+    /// Cached indices which have gone stale according to the Cache's `UpdatePolicy` are updated
+    /// first. If updating a stale index fails (e.g. because we're offline), the stale copy is used
+    /// instead.
+    pub fn get_indices(&self, index_reses: &[DirectRes]) -> Result<Indices, Error> {
+        self.load_indices(index_reses, false)
+    }
+
+    /// Loads all of the given indices and their dependencies, updating every one of them which was
+    /// already cached.
+    pub fn update_indices(&self, index_reses: &[DirectRes]) -> Result<Indices, Error> {
+        self.load_indices(index_reses, true)
+    }
+
+    fn load_indices(&self, index_reses: &[DirectRes], force: bool) -> Result<Indices, Error> {
         let mut indices = vec![];
+        let mut seen = index_reses.iter().cloned().collect::<IndexSet<_>>();
+        let mut next = seen.iter().cloned().collect::<Vec<_>>();
+
+        // Every round loads the indices which the previous round's indices depend on, until we
+        // stop finding new ones.
+        while !next.is_empty() {
+            let loaded = self.load_index_batch(&next, force)?;
+
+            next = loaded
+                .iter()
+                .flat_map(|index| index.config.dependencies().indices())
+                .map(|index| index.res.clone())
+                .filter(|res| seen.insert(res.clone()))
+                .collect();
+
+            indices.extend(loaded);
+        }
+
+        Ok(Indices::new(indices))
+    }
+
+    fn load_index_batch(
+        &self,
+        index_reses: &[DirectRes],
+        force: bool,
+    ) -> Result<Vec<Index>, Error> {
+        let net = self.net()?;
+        let mut indices = vec![];
+        let mut pending = vec![];
+        let mut downloads = vec![];
+        let mut stale = vec![];
 
         for index in index_reses {
             // We special-case a local dir index because `dir` won't exist for it.
             if let DirectRes::Dir { url } = index {
-                let lock = if let Ok(dir) = DirLock::acquire(url) {
-                    dir
-                } else {
-                    continue;
-                };
-                let ix = Index::from_disk(index.clone(), lock);
-                if let Ok(ix) = ix {
-                    indices.push(ix);
-                }
+                let lock = DirLock::acquire(url)?;
+                indices.push(Index::from_disk(index.clone(), lock)?);
                 continue;
             }
 
-            let dir = if let Ok(dir) = DirLock::acquire(
+            let dir = DirLock::acquire(
                 &self
                     .location
                     .join("indices")
                     .join(Self::get_index_dir(index)),
-            ) {
-                dir
-            } else {
-                continue;
-            };
+            )?;
 
             if dir.path().exists() {
-                let ix = Index::from_disk(index.clone(), dir);
-                if let Ok(ix) = ix {
-                    indices.push(ix);
+                if force || self.is_stale(dir.path()) {
+                    stale.push((index.clone(), dir));
+                } else {
+                    indices.push(Index::from_disk(index.clone(), dir)?);
                 }
                 continue;
             }
 
-            if index.retrieve(&self.client, &dir).is_ok() {
-                let ix = Index::from_disk(index.clone(), dir);
-                if let Ok(ix) = ix {
-                    indices.push(ix);
+            downloads.push(Download {
+                name: index.to_string(),
+                res: index.clone(),
+                dir,
+            });
+            pending.push(index.clone());
+        }
+
+        let dirs = download_all(
+            &net,
+            &self.limits,
+            &self.shell,
+            downloads,
+            MAX_DOWNLOADS,
+            &self.logger,
+        )?;
+
+        for (index, dir) in pending.into_iter().zip(dirs) {
+            Self::touch(dir.path())?;
+            indices.push(Index::from_disk(index, dir)?);
+        }
+
+        for (index, dir) in stale {
+            match self.update_index(&index, &dir) {
+                Ok(()) => Self::touch(dir.path())?,
+                Err(e) => {
+                    if force {
+                        return Err(e);
+                    }
+                    warn!(
+                        self.logger, "couldn't update index, using cached copy";
+                        "index" => index.to_string(), "cause" => e.to_string()
+                    );
                 }
             }
+            indices.push(Index::from_disk(index, dir)?);
+        }
+
+        // Sparse indices fetch their entries as they're needed, so they need a network client.
+        let indices = indices
+            .into_iter()
+            .map(|index| index.with_net(net.clone()))
+            .collect::<Vec<_>>();
+
+        for index in &indices {
+            self.pin_keys(index)?;
         }
 
-        Indices::new(indices)
+        Ok(indices)
     }
+
+    /// Makes sure that the keys an index publishes are ones we trust, pinning its keys if this is
+    /// the first time we've seen any.
+    fn pin_keys(&self, index: &Index) -> Result<(), Error> {
+        let path = self
+            .location
+            .join("indices")
+            .join(format!("{}.keys", Self::get_index_dir(&index.id.res)));
+
+        let pinned = PinnedKeys::load(&path)?;
+        let trusted = PinnedKeys::check(
+            pinned.clone(),
+            &index.id,
+            index.config.keys(),
+            index.config.rotations(),
+        )?;
+
+        if pinned.as_ref() != Some(&trusted) && !trusted.keys.is_empty() {
+            trusted.save(&path)?;
+        }
+
+        Ok(())
+    }
+
+    /// Brings the cached index in `dir` up to date.
+    ///
+    /// Git indices are fetched and checked out again in place. Sparse indices only have their
+    /// `index.toml` downloaded again in place; their entries are kept up to date as they're used.
+    /// Tarball indices are downloaded from scratch into a separate directory, which only replaces
+    /// the cached copy once the download succeeds.
+    fn update_index(&self, index: &DirectRes, dir: &DirLock) -> Result<(), Error> {
+        info!(self.logger, "updating index"; "index" => index.to_string());
+
+        match index {
+            DirectRes::Git { .. } | DirectRes::Sparse { .. } => {
+                let progress = self.shell.progress(&index.to_string());
+                let res = index.retrieve(&self.net()?, dir, &self.limits, &progress);
+                progress.finish(res.is_ok());
+                return res;
+            }
+            _ => {}
+        }
+
+        let staging = DirLock::acquire(
+            &self
+                .location
+                .join("indices")
+                .join(format!("{}-update", Self::get_index_dir(index))),
+        )?;
+        let _ = fs::remove_dir_all(staging.path());
+
+        let mut staged = download_all(
+            &self.net()?,
+            &self.limits,
+            &self.shell,
+            vec![Download {
+                name: index.to_string(),
+                res: index.clone(),
+                dir: staging,
+            }],
+            1,
+            &self.logger,
+        )?;
+        let staging = staged.remove(0);
+
+        fs::remove_dir_all(dir.path()).context(ErrorKind::CannotDownload)?;
+        fs::rename(staging.path(), dir.path()).context(ErrorKind::CannotDownload)?;
+
+        Ok(())
+    }
+
+    /// Checks if the cached index in the directory `path` is older than the update policy allows.
+    /// Indices which we don't know the age of are always stale.
+    fn is_stale(&self, path: &Path) -> bool {
+        let meta = fs::read_to_string(path.with_extension("toml"))
+            .ok()
+            .and_then(|s| toml::from_str::<IndexMeta>(&s).ok());
+
+        match meta {
+            Some(meta) => now().saturating_sub(meta.last_updated) >= self.policy.ttl,
+            None => true,
+        }
+    }
+
+    /// Records that the cached index in the directory `path` was just brought up to date.
+    fn touch(path: &Path) -> Result<(), Error> {
+        let meta = IndexMeta {
+            last_updated: now(),
+        };
+        fs::write(path.with_extension("toml"), toml::to_string(&meta)?)
+            .context(ErrorKind::CannotDownload)?;
+
+        Ok(())
+    }
+}
+
+/// The current time, in seconds since the Unix epoch.
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
 }
 
 /// Information about the source of package that is available somewhere in the file system.
@@ -378,14 +709,47 @@ impl Build {
     // hash method that tells us if their contents have changed.
     // Instead, we should just take a single Graph of Sources (maybe sources are the edge idk),
     // and hash the hash() of every source from the root and down to its deps.
-    pub fn new(summary: Summary, resolve: &Solve) -> Self {
+    /// The build of `summary` with the settings in `bcx`: the packages it's built against in
+    /// `bcx.resolve`, the compiler it's built with, the compiler flags, packages and environment of
+    /// its library, and the profile it's built in along with the profile's settings.
+    pub fn new(summary: Summary, bcx: &BuildContext) -> Self {
         let mut hasher = Sha256::default();
         // We assume here that the summary is for-sure in the resolution tree.
-        for src in resolve.sub_tree(&summary).unwrap() {
+        for src in bcx.resolve.sub_tree(&summary).unwrap() {
             hasher.input(src.summary.id.to_string().as_bytes());
             hasher.input(src.summary.version.to_string().as_bytes());
             hasher.input(src.summary.hash.to_string().as_bytes());
         }
+
+        // Artifacts only load in the compiler which built them, and the two families don't even
+        // build the same kind of artifact.
+        let compiler = vec![
+            bcx.compiler.family.name().to_owned(),
+            bcx.compiler.version.to_string(),
+        ];
+        hash_list(&mut hasher, &compiler);
+
+        let opts = &bcx.opts;
+        let env = opts
+            .env
+            .iter()
+            .map(|(var, val)| format!("{}={}", var, val))
+            .collect::<Vec<_>>();
+        for list in &[&opts.idris_opts, &opts.packages, &env] {
+            hash_list(&mut hasher, list);
+        }
+
+        // Each profile gets builds of its own, so that switching between them doesn't clobber
+        // the other's.
+        let config = &bcx.config;
+        let profile = vec![
+            config.profile.name().to_owned(),
+            config.total.to_string(),
+            config.warnings_as_errors.to_string(),
+        ];
+        hash_list(&mut hasher, &profile);
+        hash_list(&mut hasher, &config.codegen_opts);
+
         let hash = hexify_hash(hasher.result().as_slice());
 
         Build { summary, hash }
@@ -411,11 +775,22 @@ impl Build {
     }
 }
 
+/// Hashes the strings in `list` so that no two different lists hash the same (e.g. `["ab", "c"]`
+/// and `["a", "bc"]`).
+fn hash_list(hasher: &mut Sha256, list: &[String]) {
+    hasher.input(list.len().to_string().as_bytes());
+    for item in list {
+        hasher.input(b"\0");
+        hasher.input(item.as_bytes());
+    }
+    hasher.input(b"\0");
+}
+
 /// Information about the build of library that is available somewhere in the file system.
 #[derive(Debug)]
 pub struct Binary {
     // The built version of the library
-    build: Build,
+    pub build: Build,
     /// The path to ibc binary
-    binary_path: DirLock,
+    pub binary_path: DirLock,
 }
@@ -0,0 +1,181 @@
+//! Downloading several packages at once.
+//!
+//! `reqwest`'s blocking `Client` (and so `Net`) is perfectly happy to be shared between threads,
+//! so rather than pulling in futures for this, we just hand out downloads to a small pool of
+//! worker threads.
+//! Note that `PackageId`s and `Name`s aren't `Send`, so downloads are described purely in terms of
+//! where they come from and where they go.
+
+use failure::Error;
+use package::resolution::DirectRes;
+use slog::Logger;
+use std::{
+    cmp,
+    collections::VecDeque,
+    fs,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread,
+};
+use util::{
+    archive::ExtractLimits,
+    errors::ErrorKind,
+    lock::DirLock,
+    net::Net,
+    shell::{Progress, Shell},
+};
+
+/// The default number of downloads which are allowed to run at the same time.
+pub const MAX_DOWNLOADS: usize = 8;
+
+/// A single thing to download: `res` gets retrieved into the directory locked by `dir`.
+pub struct Download {
+    /// A human-readable name for the download, used in logs and when reporting progress.
+    pub name: String,
+    pub res: DirectRes,
+    pub dir: DirLock,
+}
+
+/// Retrieves all of the `downloads`, running at most `max` of them at the same time. Each download
+/// reports its progress to `shell` once it's started, rather than while it's waiting its turn.
+///
+/// The returned locks are in the same order as `downloads`. As soon as one download fails, the
+/// others are cancelled, and its error is returned once they've all stopped. The directory of a failed download is removed so that a half-extracted
+/// package never ends up looking like it's been cached.
+pub fn download_all(
+    net: &Net,
+    limits: &ExtractLimits,
+    shell: &Shell,
+    downloads: Vec<Download>,
+    max: usize,
+    logger: &Logger,
+) -> Result<Vec<DirLock>, Error> {
+    let total = downloads.len();
+    if total == 0 {
+        return Ok(vec![]);
+    }
+
+    let queue = Arc::new(Mutex::new(
+        downloads.into_iter().enumerate().collect::<VecDeque<_>>(),
+    ));
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let (tx, rx) = mpsc::channel();
+
+    let workers = (0..cmp::min(cmp::max(max, 1), total))
+        .map(|_| {
+            let queue = Arc::clone(&queue);
+            let cancelled = Arc::clone(&cancelled);
+            let tx = tx.clone();
+            let net = net.clone();
+            let limits = *limits;
+            let shell = shell.clone();
+            let logger = logger.clone();
+
+            thread::spawn(move || loop {
+                if cancelled.load(Ordering::SeqCst) {
+                    break;
+                }
+
+                let (ix, dl) = match queue.lock().unwrap().pop_front() {
+                    Some(next) => next,
+                    None => break,
+                };
+
+                info!(
+                    logger, "downloading";
+                    "package" => &dl.name, "source" => dl.res.to_string()
+                );
+
+                let progress = Cancellable {
+                    inner: shell.progress(&dl.name),
+                    cancelled: Arc::clone(&cancelled),
+                };
+                let res = dl.res.retrieve(&net, &dl.dir, &limits, &progress);
+                progress.finish(res.is_ok());
+
+                let res = match res {
+                    Ok(()) => {
+                        info!(logger, "downloaded"; "package" => &dl.name);
+                        Ok(dl.dir)
+                    }
+                    Err(e) => {
+                        let _ = fs::remove_dir_all(dl.dir.path());
+
+                        // Only the first failure gets reported; any after it are most likely just
+                        // downloads which it cancelled.
+                        if cancelled.swap(true, Ordering::SeqCst) {
+                            info!(logger, "download stopped"; "package" => &dl.name);
+                            continue;
+                        }
+
+                        error!(
+                            logger, "download failed";
+                            "package" => &dl.name, "cause" => e.to_string()
+                        );
+                        Err(e)
+                    }
+                };
+
+                if tx.send((ix, res)).is_err() {
+                    break;
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+
+    // The channel closes once every worker has dropped its sender.
+    drop(tx);
+
+    let mut done = (0..total).map(|_| None).collect::<Vec<_>>();
+    let mut err = None;
+
+    for (ix, res) in rx {
+        match res {
+            Ok(dir) => done[ix] = Some(dir),
+            Err(e) => err = Some(e),
+        }
+    }
+
+    for worker in workers {
+        let _ = worker.join();
+    }
+
+    if let Some(e) = err {
+        return Err(e);
+    }
+
+    // If a worker panicked, some downloads might never have finished.
+    let done = done
+        .into_iter()
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| ErrorKind::CannotDownload)?;
+
+    Ok(done)
+}
+
+/// Reports the progress of a download through `inner`, and stops the download once `cancelled`
+/// is set.
+struct Cancellable<P> {
+    inner: P,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl<P: Progress> Progress for Cancellable<P> {
+    fn bytes(&self, done: u64, total: Option<u64>) {
+        self.inner.bytes(done, total)
+    }
+
+    fn objects(&self, done: usize, total: usize) {
+        self.inner.objects(done, total)
+    }
+
+    fn finish(&self, ok: bool) {
+        self.inner.finish(ok)
+    }
+
+    fn cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
@@ -5,6 +5,7 @@
 //! retrieval of packages from various different sources (hopefully in parallel).
 
 pub mod cache;
+mod download;
 
 pub use self::cache::Cache;
 use failure::Error;
@@ -0,0 +1,656 @@
+//! Packing packages into tarballs, and safely extracting untrusted tarballs.
+//!
+//! Packages and indices are downloaded from sources we have no control over, so we can't trust
+//! the archives we get from them. `tar::Archive::unpack` will happily follow a symlink out of the
+//! destination directory or create device files, so instead we look at every entry before it
+//! touches the disk and bail as soon as anything looks suspicious.
+//!
+//! The tarballs we make ourselves only depend on the paths and contents of the files that go into
+//! them, so that packaging the same files twice gives the same bytes (and the same checksum).
+
+use failure::{Error, ResultExt};
+use flate2::{write::GzEncoder, Compression};
+use glob::Pattern;
+use std::{
+    fs,
+    io::Read,
+    path::{Component, Path, PathBuf},
+};
+use tar::{Archive, Builder, EntryType, Header};
+use util::errors::ErrorKind;
+use walkdir::WalkDir;
+
+/// Top-level directories which never end up in a package: build output and version control.
+const IGNORED: &[&str] = &["target", ".git", ".hg", ".svn"];
+
+/// Limits on the contents of an archive which is about to be extracted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ExtractLimits {
+    /// The maximum number of bytes that all of the files in the archive can add up to.
+    pub max_size: u64,
+    /// The maximum number of entries (files, directories and links) in the archive.
+    pub max_files: u64,
+}
+
+impl Default for ExtractLimits {
+    fn default() -> Self {
+        ExtractLimits {
+            max_size: 512 * 1024 * 1024,
+            max_files: 32 * 1024,
+        }
+    }
+}
+
+/// The reasons an archive can be rejected during extraction.
+#[derive(Clone, Debug, PartialEq, Eq, Fail)]
+pub enum ExtractError {
+    #[fail(display = "archive entry `{}` has an absolute path", _0)]
+    AbsolutePath(String),
+    #[fail(display = "archive entry `{}` contains a `..` component", _0)]
+    ParentDir(String),
+    #[fail(
+        display = "archive entry `{}` links to `{}`, which is outside of the destination",
+        _0, _1
+    )]
+    LinkEscapes(String, String),
+    #[fail(display = "archive entry `{}` is a link with no target", _0)]
+    MissingLinkTarget(String),
+    #[fail(display = "archive entry `{}` would be written through a symlink", _0)]
+    ThroughSymlink(String),
+    #[fail(display = "archive entry `{}` is a device file or fifo", _0)]
+    SpecialFile(String),
+    #[fail(display = "archive is larger than the limit of {} bytes", _0)]
+    TooLarge(u64),
+    #[fail(display = "archive has more than the limit of {} entries", _0)]
+    TooManyFiles(u64),
+}
+
+/// Unpacks `archive` into the directory `dest`, rejecting any entries which would end up outside
+/// of `dest` and any archives which go over the given `limits`.
+///
+/// Extraction stops at the first bad entry, in which case `dest` is removed along with whatever
+/// made it there.
+pub fn unpack<R: Read>(
+    archive: &mut Archive<R>,
+    dest: &Path,
+    limits: &ExtractLimits,
+) -> Result<(), Error> {
+    fs::create_dir_all(dest).context(ErrorKind::CannotDownload)?;
+
+    let res = unpack_entries(archive, dest, limits);
+    if res.is_err() {
+        let _ = fs::remove_dir_all(dest);
+    }
+
+    res
+}
+
+fn unpack_entries<R: Read>(
+    archive: &mut Archive<R>,
+    dest: &Path,
+    limits: &ExtractLimits,
+) -> Result<(), Error> {
+    let root = fs::canonicalize(dest).context(ErrorKind::CannotDownload)?;
+    let inside = |path: &Path| fs::canonicalize(path).map_or(false, |p| p.starts_with(&root));
+    let link_inside = |rel: &Path| {
+        let mut hops = MAX_HOPS;
+        resolve(&root.join(rel), &mut hops).map_or(false, |p| p.starts_with(&root))
+    };
+
+    let mut files = 0;
+    let mut size = 0;
+    let mut dangling = vec![];
+
+    for entry in archive.entries().context(ErrorKind::CannotDownload)? {
+        let mut entry = entry.context(ErrorKind::CannotDownload)?;
+
+        files += 1;
+        if files > limits.max_files {
+            return Err(ExtractError::TooManyFiles(limits.max_files))?;
+        }
+
+        size += entry.header().size().context(ErrorKind::CannotDownload)?;
+        if size > limits.max_size {
+            return Err(ExtractError::TooLarge(limits.max_size))?;
+        }
+
+        let path = entry
+            .path()
+            .context(ErrorKind::CannotDownload)?
+            .into_owned();
+        let shown = path.display().to_string();
+        let rel = normalize(&path, &shown)?;
+
+        let kind = entry.header().entry_type();
+        if kind.is_character_special() || kind.is_block_special() || kind.is_fifo() {
+            return Err(ExtractError::SpecialFile(shown))?;
+        }
+
+        let mut symlink = None;
+        if kind.is_symlink() || kind.is_hard_link() {
+            let target = entry
+                .link_name()
+                .context(ErrorKind::CannotDownload)?
+                .ok_or_else(|| ExtractError::MissingLinkTarget(shown.clone()))?
+                .into_owned();
+
+            // Symlinks are relative to the directory they live in, whereas hard links are
+            // relative to the root of the archive.
+            let base = if kind.is_symlink() {
+                rel.parent().map(Path::to_path_buf).unwrap_or_default()
+            } else {
+                PathBuf::new()
+            };
+
+            // The target can also lead through a symlink from an earlier entry (e.g. `a -> .`
+            // and then `a/../..`), which only shows once the path is resolved for real. For hard
+            // links, that has to happen before they're made; symlinks get resolved right after.
+            let escapes = !stays_inside(&base, &target)
+                || (kind.is_hard_link() && !inside(&dest.join(&target)));
+            if escapes {
+                return Err(ExtractError::LinkEscapes(
+                    shown,
+                    target.display().to_string(),
+                ))?;
+            }
+            if kind.is_symlink() {
+                symlink = Some(target);
+            }
+        }
+
+        // A previous entry might have been a symlink pointing somewhere inside the destination;
+        // we still refuse to write through it, since the link could be swapped out from under us.
+        let mut cur = dest.to_path_buf();
+        if let Some(parent) = rel.parent() {
+            for comp in parent.components() {
+                cur.push(comp);
+                if let Ok(meta) = fs::symlink_metadata(&cur) {
+                    if meta.file_type().is_symlink() {
+                        return Err(ExtractError::ThroughSymlink(shown))?;
+                    }
+                }
+            }
+        }
+
+        entry
+            .unpack_in(dest)
+            .context(format_err!("failed to unpack archive entry `{}`", shown))?;
+
+        if let Some(target) = symlink {
+            if !link_inside(&rel) {
+                return Err(ExtractError::LinkEscapes(
+                    shown,
+                    target.display().to_string(),
+                ))?;
+            }
+
+            // A link to something which isn't there yet could still be redirected by a later
+            // link along its way, so it has to be checked again at the end.
+            if !inside(&dest.join(&rel)) {
+                dangling.push((rel, shown, target));
+            }
+        }
+    }
+
+    for (rel, shown, target) in dangling {
+        if !link_inside(&rel) {
+            return Err(ExtractError::LinkEscapes(
+                shown,
+                target.display().to_string(),
+            ))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Collects the files under `root` which belong in a package, as sorted paths relative to `root`.
+///
+/// If there are any `include` globs, only the files matching one of them are packaged, and files
+/// matching any of the `exclude` globs never are. A glob which matches a directory matches
+/// everything inside of it. The manifest is always packaged, and symlinks never are.
+pub fn package_files(
+    root: &Path,
+    include: &[String],
+    exclude: &[String],
+) -> Result<Vec<PathBuf>, Error> {
+    let include = globs(include)?;
+    let exclude = globs(exclude)?;
+
+    let walker = WalkDir::new(root)
+        .into_iter()
+        .filter_entry(|e| e.depth() != 1 || !IGNORED.iter().any(|i| e.file_name() == *i));
+
+    let mut files = vec![];
+    for entry in walker {
+        let entry = entry.context(format_err!("could not read `{}`", root.display()))?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+
+        let rel = entry.path().strip_prefix(root).unwrap().to_path_buf();
+        let manifest = rel == Path::new("elba.toml");
+        let included = include.is_empty() || matches(&include, &rel);
+        if manifest || (included && !matches(&exclude, &rel)) {
+            files.push(rel);
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+fn globs(globs: &[String]) -> Result<Vec<Pattern>, Error> {
+    globs
+        .iter()
+        .map(|g| Ok(Pattern::new(g).context(format_err!("invalid glob `{}`", g))?))
+        .collect()
+}
+
+/// Checks if `path` or any of the directories it's in match one of `globs`.
+fn matches(globs: &[Pattern], path: &Path) -> bool {
+    let mut prefix = PathBuf::new();
+    path.components().any(|comp| {
+        prefix.push(comp);
+        globs.iter().any(|g| g.matches_path(&prefix))
+    })
+}
+
+/// Packs `files` (relative to `root`) into a gzipped tarball, in the order they're given.
+///
+/// Timestamps and owners are zeroed out, and every file is either `0644` or (if it's executable)
+/// `0755`, so that the archive doesn't depend on when or by whom it was made.
+pub fn pack(root: &Path, files: &[PathBuf]) -> Result<Vec<u8>, Error> {
+    let mut b = Builder::new(GzEncoder::new(vec![], Compression::default()));
+
+    for rel in files {
+        let path = root.join(rel);
+        let mut file =
+            fs::File::open(&path).context(format_err!("could not read `{}`", path.display()))?;
+        let meta = file.metadata()?;
+
+        // `append_data` sets the path and checksum, and writes paths which don't fit in the header
+        // as GNU long names.
+        let mut h = Header::new_gnu();
+        h.set_entry_type(EntryType::Regular);
+        h.set_size(meta.len());
+        h.set_mode(if is_executable(&meta) { 0o755 } else { 0o644 });
+        h.set_mtime(0);
+        h.set_uid(0);
+        h.set_gid(0);
+
+        b.append_data(&mut h, rel, &mut file)
+            .context(format_err!("could not package `{}`", path.display()))?;
+    }
+
+    Ok(b.into_inner()?.finish()?)
+}
+
+#[cfg(unix)]
+fn is_executable(meta: &fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    meta.permissions().mode() & 0o111 != 0
+}
+
+#[cfg(not(unix))]
+fn is_executable(_meta: &fs::Metadata) -> bool {
+    false
+}
+
+/// Turns the path of an archive entry into a relative path with no `.` or `..` components.
+fn normalize(path: &Path, shown: &str) -> Result<PathBuf, ExtractError> {
+    let mut res = PathBuf::new();
+
+    for comp in path.components() {
+        match comp {
+            Component::Prefix(_) | Component::RootDir => {
+                return Err(ExtractError::AbsolutePath(shown.to_owned()))
+            }
+            Component::ParentDir => return Err(ExtractError::ParentDir(shown.to_owned())),
+            Component::CurDir => {}
+            Component::Normal(p) => res.push(p),
+        }
+    }
+
+    Ok(res)
+}
+
+/// How many symlinks `resolve` follows before giving up on a path, like `MAXSYMLINKS` on Linux.
+const MAX_HOPS: u32 = 40;
+
+/// Resolves every symlink along the absolute path `path`, the same way the OS would. Unlike
+/// `fs::canonicalize`, this also works for paths which don't exist (yet), whose missing components
+/// are taken as they are. Returns `None` if following the links takes more than `hops` hops.
+fn resolve(path: &Path, hops: &mut u32) -> Option<PathBuf> {
+    let mut res = PathBuf::new();
+
+    for comp in path.components() {
+        match comp {
+            Component::ParentDir => {
+                res.pop();
+            }
+            Component::CurDir => {}
+            Component::Normal(p) => {
+                res.push(p);
+                if let Ok(target) = fs::read_link(&res) {
+                    if *hops == 0 {
+                        return None;
+                    }
+                    *hops -= 1;
+                    res.pop();
+                    let next = res.join(target);
+                    res = resolve(&next, hops)?;
+                }
+            }
+            Component::Prefix(_) | Component::RootDir => res.push(comp.as_os_str()),
+        }
+    }
+
+    Some(res)
+}
+
+/// Checks if following `target` from the directory `base` (both relative to the destination)
+/// keeps us inside of the destination.
+fn stays_inside(base: &Path, target: &Path) -> bool {
+    let mut depth = base.components().count();
+
+    for comp in target.components() {
+        match comp {
+            Component::Prefix(_) | Component::RootDir => return false,
+            Component::ParentDir => {
+                if depth == 0 {
+                    return false;
+                }
+                depth -= 1;
+            }
+            Component::CurDir => {}
+            Component::Normal(_) => depth += 1,
+        }
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::env;
+    use tar::{Builder, EntryType, Header};
+
+    fn header(path: &str, kind: EntryType, size: u64) -> Header {
+        let mut h = Header::new_old();
+        // We write the path by hand since `set_path` refuses to create malicious archives.
+        h.as_old_mut().name[..path.len()].copy_from_slice(path.as_bytes());
+        h.set_entry_type(kind);
+        h.set_size(size);
+        h.set_mode(0o644);
+        h
+    }
+
+    fn archive(entries: Vec<(Header, Vec<u8>)>) -> Vec<u8> {
+        let mut b = Builder::new(vec![]);
+        for (mut h, data) in entries {
+            h.set_cksum();
+            b.append(&h, &data[..]).unwrap();
+        }
+        b.into_inner().unwrap()
+    }
+
+    fn link(path: &str, kind: EntryType, target: &str) -> Header {
+        let mut h = header(path, kind, 0);
+        h.as_old_mut().linkname[..target.len()].copy_from_slice(target.as_bytes());
+        h
+    }
+
+    fn extract(name: &str, bytes: Vec<u8>, limits: &ExtractLimits) -> Result<(), Error> {
+        let dest = env::temp_dir().join(format!("elba-archive-test-{}", name));
+        let _ = fs::remove_dir_all(&dest);
+        let res = unpack(&mut Archive::new(&bytes[..]), &dest, limits);
+        let _ = fs::remove_dir_all(&dest);
+        res
+    }
+
+    fn rejection(res: Result<(), Error>) -> ExtractError {
+        res.unwrap_err().downcast::<ExtractError>().unwrap()
+    }
+
+    #[test]
+    fn unpack_ok() {
+        let bytes = archive(vec![
+            (
+                header("pkg/elba.toml", EntryType::Regular, 4),
+                b"abcd".to_vec(),
+            ),
+            (link("pkg/link", EntryType::Symlink, "elba.toml"), vec![]),
+        ]);
+        assert!(extract("ok", bytes, &ExtractLimits::default()).is_ok());
+
+        // Links can come before whatever they point to.
+        let bytes = archive(vec![
+            (link("pkg/link", EntryType::Symlink, "src/A.idr"), vec![]),
+            (
+                header("pkg/src/A.idr", EntryType::Regular, 4),
+                b"abcd".to_vec(),
+            ),
+        ]);
+        assert!(extract("forward", bytes, &ExtractLimits::default()).is_ok());
+    }
+
+    #[test]
+    fn unpack_parent_dir() {
+        let bytes = archive(vec![(header("../evil", EntryType::Regular, 0), vec![])]);
+        assert_eq!(
+            rejection(extract("parent", bytes, &ExtractLimits::default())),
+            ExtractError::ParentDir("../evil".to_owned())
+        );
+    }
+
+    #[test]
+    fn unpack_absolute() {
+        let bytes = archive(vec![(header("/etc/evil", EntryType::Regular, 0), vec![])]);
+        assert_eq!(
+            rejection(extract("absolute", bytes, &ExtractLimits::default())),
+            ExtractError::AbsolutePath("/etc/evil".to_owned())
+        );
+    }
+
+    #[test]
+    fn unpack_symlink_escape() {
+        let bytes = archive(vec![(
+            link("pkg/link", EntryType::Symlink, "../../etc"),
+            vec![],
+        )]);
+        assert_eq!(
+            rejection(extract("symlink", bytes, &ExtractLimits::default())),
+            ExtractError::LinkEscapes("pkg/link".to_owned(), "../../etc".to_owned())
+        );
+
+        // Each link looks fine on its own, but `a` makes `a/..` go up a level.
+        let bytes = archive(vec![
+            (link("pkg/a", EntryType::Symlink, "."), vec![]),
+            (link("pkg/b", EntryType::Symlink, "a/../.."), vec![]),
+        ]);
+        assert_eq!(
+            rejection(extract("symlink-chain", bytes, &ExtractLimits::default())),
+            ExtractError::LinkEscapes("pkg/b".to_owned(), "a/../..".to_owned())
+        );
+
+        // `b` doesn't lead anywhere yet, but once `m` shows up it leads out.
+        let bytes = archive(vec![
+            (link("pkg/b", EntryType::Symlink, "m/../.."), vec![]),
+            (link("pkg/m", EntryType::Symlink, ".."), vec![]),
+        ]);
+        assert_eq!(
+            rejection(extract("symlink-later", bytes, &ExtractLimits::default())),
+            ExtractError::LinkEscapes("pkg/b".to_owned(), "m/../..".to_owned())
+        );
+    }
+
+    #[test]
+    fn unpack_cleans_up() {
+        let dest = env::temp_dir().join("elba-archive-test-cleanup");
+        let _ = fs::remove_dir_all(&dest);
+
+        let bytes = archive(vec![
+            (
+                header("pkg/elba.toml", EntryType::Regular, 4),
+                b"abcd".to_vec(),
+            ),
+            (link("pkg/a", EntryType::Symlink, "."), vec![]),
+            (link("pkg/b", EntryType::Symlink, "a/../.."), vec![]),
+        ]);
+        let res = unpack(
+            &mut Archive::new(&bytes[..]),
+            &dest,
+            &ExtractLimits::default(),
+        );
+        assert!(res.is_err());
+        assert!(!dest.exists());
+    }
+
+    #[test]
+    fn unpack_hardlink_escape() {
+        let bytes = archive(vec![(
+            link("pkg/link", EntryType::Link, "/etc/passwd"),
+            vec![],
+        )]);
+        assert_eq!(
+            rejection(extract("hardlink", bytes, &ExtractLimits::default())),
+            ExtractError::LinkEscapes("pkg/link".to_owned(), "/etc/passwd".to_owned())
+        );
+
+        let bytes = archive(vec![
+            (link("pkg/a", EntryType::Symlink, "."), vec![]),
+            (
+                link("pkg/link", EntryType::Link, "pkg/a/../../etc/passwd"),
+                vec![],
+            ),
+        ]);
+        assert_eq!(
+            rejection(extract("hardlink-chain", bytes, &ExtractLimits::default())),
+            ExtractError::LinkEscapes("pkg/link".to_owned(), "pkg/a/../../etc/passwd".to_owned())
+        );
+    }
+
+    #[test]
+    fn unpack_device() {
+        let bytes = archive(vec![(header("pkg/dev", EntryType::Char, 0), vec![])]);
+        assert_eq!(
+            rejection(extract("device", bytes, &ExtractLimits::default())),
+            ExtractError::SpecialFile("pkg/dev".to_owned())
+        );
+    }
+
+    #[test]
+    fn unpack_limits() {
+        let limits = ExtractLimits {
+            max_size: 4,
+            max_files: 2,
+        };
+
+        let big = archive(vec![(
+            header("a", EntryType::Regular, 5),
+            b"abcde".to_vec(),
+        )]);
+        assert_eq!(
+            rejection(extract("size", big, &limits)),
+            ExtractError::TooLarge(4)
+        );
+
+        let many = archive(vec![
+            (header("a", EntryType::Regular, 0), vec![]),
+            (header("b", EntryType::Regular, 0), vec![]),
+            (header("c", EntryType::Regular, 0), vec![]),
+        ]);
+        assert_eq!(
+            rejection(extract("files", many, &limits)),
+            ExtractError::TooManyFiles(2)
+        );
+    }
+
+    #[test]
+    fn package_files_globs() {
+        let root = env::temp_dir().join("elba-archive-test-package");
+        let _ = fs::remove_dir_all(&root);
+        for f in &[
+            "elba.toml",
+            "src/A.idr",
+            "src/B.idr",
+            "docs/a.md",
+            "target/bin/a",
+            ".git/HEAD",
+        ] {
+            let path = root.join(f);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, f).unwrap();
+        }
+
+        let files = |include: &[&str], exclude: &[&str]| {
+            let include = include.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+            let exclude = exclude.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+            package_files(&root, &include, &exclude)
+                .unwrap()
+                .into_iter()
+                .map(|p| p.to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            files(&[], &[]),
+            vec!["docs/a.md", "elba.toml", "src/A.idr", "src/B.idr"]
+        );
+        assert_eq!(
+            files(&["src"], &["src/B.*"]),
+            vec!["elba.toml", "src/A.idr"]
+        );
+
+        let all = files(&[], &[])
+            .into_iter()
+            .map(PathBuf::from)
+            .collect::<Vec<_>>();
+        let bytes = pack(&root, &all).unwrap();
+        assert_eq!(bytes, pack(&root, &all).unwrap());
+        assert!(extract(
+            "packed",
+            GzDecoder::new(&bytes[..])
+                .bytes()
+                .collect::<Result<_, _>>()
+                .unwrap(),
+            &ExtractLimits::default()
+        )
+        .is_ok());
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn pack_long_paths() {
+        let root = env::temp_dir().join("elba-archive-test-long");
+        let dest = env::temp_dir().join("elba-archive-test-long-dest");
+        let _ = fs::remove_dir_all(&root);
+        let _ = fs::remove_dir_all(&dest);
+
+        // Far more than the 100 bytes a tar header has room for.
+        let rel = PathBuf::from(format!(
+            "src/{}/{}.idr",
+            "Nested".repeat(10),
+            "Module".repeat(10)
+        ));
+        fs::create_dir_all(root.join(&rel).parent().unwrap()).unwrap();
+        fs::write(root.join(&rel), "module Long").unwrap();
+
+        let bytes = pack(&root, &[rel.clone()]).unwrap();
+        unpack(
+            &mut Archive::new(GzDecoder::new(&bytes[..])),
+            &dest,
+            &ExtractLimits::default(),
+        )
+        .unwrap();
+        assert_eq!(fs::read_to_string(dest.join(&rel)).unwrap(), "module Long");
+
+        let _ = fs::remove_dir_all(&root);
+        let _ = fs::remove_dir_all(&dest);
+    }
+}
@@ -6,9 +6,12 @@
 //!
 //! Environment variables (.env files?) should also be able to modify the configuration.
 
+use build::context::Profiles;
 use directories::BaseDirs;
-use indexmap::{IndexMap, IndexSet};
-use package::resolution::DirectRes;
+use index::IndexNames;
+use indexmap::IndexMap;
+use retrieve::cache::UpdatePolicy;
+use util::{archive::ExtractLimits, net::NetConfig};
 use std::path::PathBuf;
 
 /// The requested verbosity of output
@@ -32,7 +35,16 @@ pub struct Config {
     // First index = default.
     // In future, default for "indices" should be only official index..
     #[serde(default)]
-    pub indices: IndexSet<DirectRes>,
+    pub indices: IndexNames,
+    #[serde(default)]
+    pub extract: ExtractLimits,
+    #[serde(default)]
+    pub net: NetConfig,
+    #[serde(default, rename = "index-update")]
+    pub index_update: UpdatePolicy,
+    /// The Idris compiler to use, either as a path or as a name to look up on the `PATH`.
+    #[serde(default)]
+    pub compiler: Option<PathBuf>,
 }
 
 impl Config {
@@ -59,7 +71,11 @@ impl Default for Config {
             term: Term::default(),
             alias: default_aliases(),
             directories: Directories::default(),
-            indices: IndexSet::default(),
+            indices: IndexNames::default(),
+            extract: ExtractLimits::default(),
+            net: NetConfig::default(),
+            index_update: UpdatePolicy::default(),
+            compiler: None,
         }
     }
 }
@@ -75,8 +91,11 @@ fn default_aliases() -> IndexMap<String, String> {
 
 #[derive(Deserialize, Serialize)]
 pub struct Profile {
-    pub name: String,
-    pub email: String,
+    pub name: Option<String>,
+    pub email: Option<String>,
+    /// Settings for the build profiles, which override those in the manifests of packages.
+    #[serde(flatten)]
+    pub build: Profiles,
 }
 
 #[derive(Deserialize, Serialize)]
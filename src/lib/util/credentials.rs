@@ -0,0 +1,312 @@
+//! Credentials for private indices and git repositories.
+//!
+//! Credentials live in their own file (`~/.elba/credentials.toml`) rather than in the config,
+//! since config files are meant to be shared and checked into repos, whereas credentials are
+//! secret. Because of that, we refuse to read the credentials file if anyone other than its owner
+//! can read it.
+//!
+//! ```toml
+//! # API tokens, keyed by the host of the index (or tarball server) they're for.
+//! [tokens]
+//! "elba.io" = "5ecr3t"
+//!
+//! # How to authenticate to git hosts over ssh. If no key is given, the ssh agent is used.
+//! [git."github.com"]
+//! username = "git"
+//! ssh-key = "/home/me/.ssh/id_ed25519"
+//! ```
+
+use failure::{Error, ResultExt};
+use git2::{Cred, CredentialType};
+use indexmap::IndexMap;
+use std::{
+    fs,
+    io::{prelude::*, BufReader},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+use toml;
+use url::Url;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Credentials {
+    #[serde(default)]
+    pub tokens: IndexMap<String, String>,
+    #[serde(default)]
+    pub git: IndexMap<String, GitCred>,
+}
+
+/// How to authenticate to a single git host.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct GitCred {
+    pub username: Option<String>,
+    pub ssh_key: Option<PathBuf>,
+    pub ssh_pubkey: Option<PathBuf>,
+    pub passphrase: Option<String>,
+}
+
+impl Credentials {
+    /// Reads the credentials file at `path`. If there's no file there, there just aren't any
+    /// credentials.
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        if !path.exists() {
+            return Ok(Credentials::default());
+        }
+
+        check_permissions(path)?;
+
+        let file =
+            fs::File::open(path).context(format_err!("could not open `{}`", path.display()))?;
+        let mut file = BufReader::new(file);
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)
+            .context(format_err!("could not read `{}`", path.display()))?;
+
+        Credentials::from_str(&contents)
+            .context(format_err!("invalid credentials file `{}`", path.display()))
+            .map_err(Error::from)
+    }
+
+    /// Writes the credentials to `path`, making sure only the current user can read them.
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let contents = toml::to_string_pretty(self)?;
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .context(format_err!("could not create dir {}", parent.display()))?;
+        }
+
+        let mut opts = fs::OpenOptions::new();
+        opts.write(true).create(true).truncate(true);
+        restrict(&mut opts);
+
+        let mut file = opts.open(path).context(format_err!(
+            "could not open `{}` for writing",
+            path.display()
+        ))?;
+        // The file might have existed before with looser permissions.
+        set_private(&file)?;
+        file.write_all(contents.as_bytes())
+            .context(format_err!("could not write to `{}`", path.display()))?;
+
+        Ok(())
+    }
+
+    /// Returns the API token to use for requests to the host of `url`.
+    pub fn token(&self, url: &Url) -> Option<&str> {
+        url.host_str()
+            .and_then(|h| self.tokens.get(h))
+            .map(|t| t.as_str())
+    }
+
+    /// The username and password to authenticate git operations over HTTP(S) to the host of `url`
+    /// with, if we have a token for it. Like `git_callback`, this sends the token as the password.
+    pub fn git_userpass(&self, url: &Url) -> Option<(String, String)> {
+        let host = url.host_str()?;
+        let token = self.tokens.get(host)?;
+        let username = self
+            .git
+            .get(host)
+            .and_then(|c| c.username.clone())
+            .or_else(|| Some(url.username().to_owned()).filter(|u| !u.is_empty()))
+            .unwrap_or_else(|| "git".to_owned());
+
+        Some((username, token.clone()))
+    }
+
+    /// A git2 credentials callback which authenticates to the host of `url` using these
+    /// credentials.
+    ///
+    /// libgit2 keeps calling this callback until it either succeeds or we give up, so every kind
+    /// of credential is only ever offered once.
+    pub fn git_callback(
+        &self,
+        url: &Url,
+    ) -> impl FnMut(&str, Option<&str>, CredentialType) -> Result<Cred, ::git2::Error> {
+        let host = url.host_str().unwrap_or("").to_owned();
+        let cred = self.git.get(&host).cloned().unwrap_or_default();
+        let token = self.tokens.get(&host).cloned();
+        let mut tried = CredentialType::empty();
+
+        move |_url, from_url, allowed| {
+            let username = cred
+                .username
+                .as_ref()
+                .map(|u| u.as_str())
+                .or(from_url)
+                .unwrap_or("git");
+
+            if allowed.contains(CredentialType::USERNAME)
+                && !tried.contains(CredentialType::USERNAME)
+            {
+                tried.insert(CredentialType::USERNAME);
+                return Cred::username(username);
+            }
+
+            if allowed.contains(CredentialType::SSH_KEY) && !tried.contains(CredentialType::SSH_KEY)
+            {
+                tried.insert(CredentialType::SSH_KEY);
+                return match &cred.ssh_key {
+                    Some(key) => Cred::ssh_key(
+                        username,
+                        cred.ssh_pubkey.as_ref().map(|p| p.as_path()),
+                        key,
+                        cred.passphrase.as_ref().map(|p| p.as_str()),
+                    ),
+                    None => Cred::ssh_key_from_agent(username),
+                };
+            }
+
+            if allowed.contains(CredentialType::USER_PASS_PLAINTEXT)
+                && !tried.contains(CredentialType::USER_PASS_PLAINTEXT)
+            {
+                tried.insert(CredentialType::USER_PASS_PLAINTEXT);
+                if let Some(token) = &token {
+                    return Cred::userpass_plaintext(username, token);
+                }
+            }
+
+            if allowed.contains(CredentialType::DEFAULT) && !tried.contains(CredentialType::DEFAULT)
+            {
+                tried.insert(CredentialType::DEFAULT);
+                return Cred::default();
+            }
+
+            Err(::git2::Error::from_str(&format!(
+                "no usable credentials for `{}`",
+                host
+            )))
+        }
+    }
+}
+
+impl FromStr for Credentials {
+    type Err = Error;
+
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        toml::from_str(raw).map_err(Error::from)
+    }
+}
+
+#[cfg(unix)]
+fn check_permissions(path: &Path) -> Result<(), Error> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = fs::metadata(path)
+        .context(format_err!("could not read `{}`", path.display()))?
+        .permissions()
+        .mode();
+
+    if mode & 0o077 != 0 {
+        bail!(
+            "the credentials file `{}` can be accessed by other users (mode {:o}); \
+             run `chmod 600 {}` to fix this",
+            path.display(),
+            mode & 0o777,
+            path.display()
+        )
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_permissions(_path: &Path) -> Result<(), Error> {
+    Ok(())
+}
+
+#[cfg(unix)]
+fn restrict(opts: &mut fs::OpenOptions) {
+    use std::os::unix::fs::OpenOptionsExt;
+    opts.mode(0o600);
+}
+
+#[cfg(not(unix))]
+fn restrict(_opts: &mut fs::OpenOptions) {}
+
+#[cfg(unix)]
+fn set_private(file: &fs::File) -> Result<(), Error> {
+    use std::os::unix::fs::PermissionsExt;
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn set_private(_file: &fs::File) -> Result<(), Error> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn credentials_roundtrip() {
+        let path = env::temp_dir().join("elba-credentials-test.toml");
+        let _ = fs::remove_file(&path);
+
+        let mut creds = Credentials::default();
+        creds
+            .tokens
+            .insert("elba.io".to_owned(), "5ecr3t".to_owned());
+        creds.save(&path).unwrap();
+
+        let loaded = Credentials::load(&path).unwrap();
+        assert_eq!(loaded, creds);
+        assert_eq!(
+            loaded.token(&Url::parse("https://elba.io/index").unwrap()),
+            Some("5ecr3t")
+        );
+        assert_eq!(
+            loaded.token(&Url::parse("https://example.com/index").unwrap()),
+            None
+        );
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn credentials_git_userpass() {
+        let mut creds = Credentials::default();
+        creds
+            .tokens
+            .insert("git.corp".to_owned(), "5ecr3t".to_owned());
+
+        let url = Url::parse("https://git.corp/pkg.git").unwrap();
+        assert_eq!(
+            creds.git_userpass(&url),
+            Some(("git".to_owned(), "5ecr3t".to_owned()))
+        );
+
+        creds.git.insert(
+            "git.corp".to_owned(),
+            GitCred {
+                username: Some("me".to_owned()),
+                ..GitCred::default()
+            },
+        );
+        assert_eq!(
+            creds.git_userpass(&url),
+            Some(("me".to_owned(), "5ecr3t".to_owned()))
+        );
+
+        let url = Url::parse("https://github.com/pkg.git").unwrap();
+        assert_eq!(creds.git_userpass(&url), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn credentials_open_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = env::temp_dir().join("elba-credentials-open-test.toml");
+        Credentials::default().save(&path).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+
+        assert!(Credentials::load(&path).is_err());
+
+        let _ = fs::remove_file(&path);
+    }
+}
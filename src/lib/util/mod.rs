@@ -1,8 +1,11 @@
 //! Utility functions.
 
+pub mod archive;
 pub mod config;
+pub mod credentials;
 pub mod errors;
 pub mod lock;
+pub mod net;
 pub mod shell;
 
 use failure::ResultExt;
@@ -28,3 +31,18 @@ pub fn write(path: &Path, contents: &[u8]) -> Res<()> {
     })().context(format_err!("failed to write `{}`", path.display()))?;
     Ok(())
 }
+
+/// Whether `path` is a file which can be run.
+#[cfg(unix)]
+pub fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(path)
+        .map(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+/// Whether `path` is a file which can be run.
+#[cfg(not(unix))]
+pub fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
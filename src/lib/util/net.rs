@@ -0,0 +1,662 @@
+//! Network access for retrieving packages and indices.
+//!
+//! Every request that goes out over the network (tarball downloads, index downloads and git
+//! operations) goes through `Net`, which applies the settings in the `[net]` section of the
+//! configuration: timeouts, retries with exponential backoff for transient errors, proxies and
+//! custom CA bundles. It also takes care of authenticating with the user's credentials.
+//!
+//! Git operations over HTTP(S) don't use libgit2's own HTTP client, which has no timeouts and only
+//! trusts the system's certificates. Instead, they go through `GitHttp`, a transport which speaks
+//! git's smart HTTP protocol using the same client as everything else.
+
+use failure::{Error, Fail, ResultExt};
+use git2::{
+    self,
+    transport::{Service, SmartSubtransport, SmartSubtransportStream, Transport},
+    ErrorClass, FetchOptions, ProxyOptions, PushOptions, Remote, RemoteCallbacks,
+};
+use reqwest::{
+    self,
+    header::{Authorization, Bearer, Headers},
+    Certificate, Client, Proxy, RequestBuilder, Response, StatusCode,
+};
+use serde::Serialize;
+use std::{
+    cell::RefCell,
+    env, fmt, fs,
+    io::{self, prelude::*},
+    mem,
+    path::PathBuf,
+    sync::{Arc, Mutex, Once, ONCE_INIT},
+    thread,
+    time::Duration,
+};
+use url::Url;
+use util::{credentials::Credentials, errors::ErrorKind};
+
+/// The `[net]` section of the configuration.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct NetConfig {
+    /// How long to wait for a connection to be established, in seconds.
+    pub connect_timeout: u64,
+    /// How long to wait for a read or write on an established connection, in seconds.
+    pub read_timeout: u64,
+    /// How many times to retry a request which failed because of a transient error.
+    pub retries: u32,
+    /// A proxy to use for every request, overriding the `HTTP(S)_PROXY` environment variables.
+    pub proxy: Option<String>,
+    /// A comma-separated list of hosts which shouldn't be proxied, overriding `NO_PROXY`.
+    pub no_proxy: Option<String>,
+    /// A PEM bundle of extra CA certificates to trust.
+    pub cainfo: Option<PathBuf>,
+}
+
+impl Default for NetConfig {
+    fn default() -> Self {
+        NetConfig {
+            connect_timeout: 30,
+            read_timeout: 30,
+            retries: 2,
+            proxy: None,
+            no_proxy: None,
+            cainfo: None,
+        }
+    }
+}
+
+/// A request which came back with an unsuccessful status code.
+#[derive(Clone, Debug, Fail)]
+#[fail(display = "request to `{}` failed with status {}", url, status)]
+pub struct StatusError {
+    pub url: Url,
+    pub status: StatusCode,
+}
+
+/// The proxies which apply to each url, worked out from the config and the environment.
+#[derive(Clone, Debug, Default)]
+struct Proxies {
+    http: Option<Url>,
+    https: Option<Url>,
+    no_proxy: Vec<String>,
+}
+
+impl Proxies {
+    fn new(config: &NetConfig) -> Result<Self, Error> {
+        let parse = |s: Option<String>| -> Result<Option<Url>, Error> {
+            match s {
+                Some(s) => Ok(Some(
+                    Url::parse(&s).context(format_err!("invalid proxy url `{}`", s))?,
+                )),
+                None => Ok(None),
+            }
+        };
+
+        let (http, https) = if config.proxy.is_some() {
+            let p = parse(config.proxy.clone())?;
+            (p.clone(), p)
+        } else {
+            (parse(var("http_proxy"))?, parse(var("https_proxy"))?)
+        };
+
+        let no_proxy = config
+            .no_proxy
+            .clone()
+            .or_else(|| var("no_proxy"))
+            .map(|s| {
+                s.split(',')
+                    .map(|h| h.trim().trim_left_matches('.').to_lowercase())
+                    .filter(|h| !h.is_empty())
+                    .collect()
+            })
+            .unwrap_or_else(Vec::new);
+
+        Ok(Proxies {
+            http,
+            https,
+            no_proxy,
+        })
+    }
+
+    /// Returns the proxy which a request to `url` should go through, if any.
+    fn for_url(&self, url: &Url) -> Option<&Url> {
+        let host = url.host_str()?.to_lowercase();
+        let excluded = self
+            .no_proxy
+            .iter()
+            .any(|np| np == "*" || host == *np || host.ends_with(&format!(".{}", np)));
+
+        if excluded {
+            return None;
+        }
+
+        match url.scheme() {
+            "https" => self.https.as_ref(),
+            "http" => self.http.as_ref(),
+            _ => None,
+        }
+    }
+}
+
+/// Reads an environment variable, preferring the lowercase spelling like curl does.
+fn var(name: &str) -> Option<String> {
+    env::var(name)
+        .or_else(|_| env::var(name.to_uppercase()))
+        .ok()
+        .filter(|s| !s.is_empty())
+}
+
+/// A configured network client.
+#[derive(Clone)]
+pub struct Net {
+    client: Client,
+    config: NetConfig,
+    proxies: Proxies,
+    credentials: Credentials,
+}
+
+impl Net {
+    pub fn new(config: NetConfig, credentials: Credentials) -> Result<Self, Error> {
+        let proxies = Proxies::new(&config)?;
+
+        let mut builder = Client::builder();
+        // reqwest only has a single timeout, which bounds connecting and waiting for the response
+        // as a whole as well as each read of the body. The first gets room for both.
+        builder.timeout(Duration::from_secs(
+            config.connect_timeout + config.read_timeout,
+        ));
+
+        let ps = proxies.clone();
+        builder.proxy(Proxy::custom(move |url| ps.for_url(url).cloned()));
+
+        if let Some(cainfo) = &config.cainfo {
+            let bundle = fs::read_to_string(cainfo).context(format_err!(
+                "could not read CA bundle `{}`",
+                cainfo.display()
+            ))?;
+            for cert in pem_certs(&bundle) {
+                let cert = Certificate::from_pem(cert.as_bytes())
+                    .context(format_err!("invalid certificate in `{}`", cainfo.display()))?;
+                builder.add_root_certificate(cert);
+            }
+        }
+
+        let client = builder.build().context(ErrorKind::CannotDownload)?;
+
+        Ok(Net {
+            client,
+            config,
+            proxies,
+            credentials,
+        })
+    }
+
+    pub fn config(&self) -> &NetConfig {
+        &self.config
+    }
+
+    pub fn credentials(&self) -> &Credentials {
+        &self.credentials
+    }
+
+    /// Sends a GET request to `url`, failing if the response isn't successful. If there's a token
+    /// for the host of `url`, it's sent along.
+    ///
+    /// This doesn't retry anything by itself; wrap the whole operation (including reading the
+    /// body) in `retry` for that.
+    pub fn get(&self, url: &Url) -> Result<Response, Error> {
+        let r = self.send(url, self.client.get(url.clone()))?;
+        if !r.status().is_success() {
+            return Err(StatusError {
+                url: url.clone(),
+                status: r.status(),
+            })?;
+        }
+
+        Ok(r)
+    }
+
+    /// Like `get`, but only fetches `url` if it's changed since we last saw it. `etag` and
+    /// `modified` are the `ETag` and `Last-Modified` headers from the last time we fetched it.
+    ///
+    /// Returns `None` if the server says nothing has changed.
+    pub fn get_if_modified(
+        &self,
+        url: &Url,
+        etag: Option<&str>,
+        modified: Option<&str>,
+    ) -> Result<Option<Response>, Error> {
+        let mut headers = Headers::new();
+        if let Some(etag) = etag {
+            headers.set_raw("If-None-Match", etag.to_owned());
+        }
+        if let Some(modified) = modified {
+            headers.set_raw("If-Modified-Since", modified.to_owned());
+        }
+
+        let mut req = self.client.get(url.clone());
+        req.headers(headers);
+        let r = self.send(url, req)?;
+        if r.status() == StatusCode::NotModified {
+            return Ok(None);
+        }
+        if !r.status().is_success() {
+            return Err(StatusError {
+                url: url.clone(),
+                status: r.status(),
+            })?;
+        }
+
+        Ok(Some(r))
+    }
+
+    /// Sends `json` to `url` in a PUT request, failing if the response isn't successful. Like
+    /// `get`, this sends along the token for the host of `url` if there is one.
+    pub fn put_json<T: Serialize>(&self, url: &Url, json: &T) -> Result<Response, Error> {
+        let mut req = self.client.put(url.clone());
+        req.json(json);
+        let r = self.send(url, req)?;
+        if !r.status().is_success() {
+            return Err(StatusError {
+                url: url.clone(),
+                status: r.status(),
+            })?;
+        }
+
+        Ok(r)
+    }
+
+    fn send(&self, url: &Url, mut req: RequestBuilder) -> Result<Response, Error> {
+        if let Some(token) = self.credentials.token(url) {
+            req.header(Authorization(Bearer {
+                token: token.to_owned(),
+            }));
+        }
+
+        Ok(req.send()?)
+    }
+
+    /// Runs `op`, retrying it with exponential backoff for as long as it fails with a transient
+    /// error and we haven't run out of retries.
+    pub fn retry<T, F>(&self, mut op: F) -> Result<T, Error>
+    where
+        F: FnMut() -> Result<T, Error>,
+    {
+        let mut attempt = 0;
+        loop {
+            match op() {
+                Ok(x) => return Ok(x),
+                Err(ref e) if attempt < self.config.retries && is_transient(e) => {
+                    thread::sleep(Duration::from_millis(500 << attempt));
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Fetch options for git operations on the repository at `url`, with the proxy and
+    /// credentials set up. Any other callbacks should already be set on `callbacks`.
+    ///
+    /// This also makes git operations over HTTP(S) on the current thread go through this `Net`.
+    pub fn fetch_options<'a>(
+        &self,
+        url: &Url,
+        mut callbacks: RemoteCallbacks<'a>,
+    ) -> FetchOptions<'a> {
+        self.use_for_git();
+        callbacks.credentials(self.credentials.git_callback(url));
+
+        let mut opts = FetchOptions::new();
+        opts.proxy_options(self.proxy_options(url));
+        opts.remote_callbacks(callbacks);
+        opts
+    }
+
+    /// Like `fetch_options`, but for pushing to the repository at `url`.
+    pub fn push_options<'a>(
+        &self,
+        url: &Url,
+        mut callbacks: RemoteCallbacks<'a>,
+    ) -> PushOptions<'a> {
+        self.use_for_git();
+        callbacks.credentials(self.credentials.git_callback(url));
+
+        let mut opts = PushOptions::new();
+        opts.proxy_options(self.proxy_options(url));
+        opts.remote_callbacks(callbacks);
+        opts
+    }
+
+    /// The proxy for git operations which libgit2 carries out itself. Hosts which shouldn't be
+    /// proxied get the default options, which don't use a proxy at all.
+    fn proxy_options<'a>(&self, url: &Url) -> ProxyOptions<'a> {
+        let mut proxy = ProxyOptions::new();
+        if let Some(p) = self.proxies.for_url(url) {
+            proxy.url(p.as_str());
+        }
+
+        proxy
+    }
+
+    /// Makes git operations over HTTP(S) on the current thread go through this `Net`.
+    ///
+    /// libgit2 only lets us replace its HTTP transport for the whole process, so the transport
+    /// looks up the `Net` for the thread it runs on. Git operations run on the thread which starts
+    /// them, right after their options are set up here.
+    fn use_for_git(&self) {
+        static REGISTER: Once = ONCE_INIT;
+
+        REGISTER.call_once(|| unsafe {
+            // Registering can only fail if the scheme is already taken, which it never is.
+            git2::transport::register("http", GitHttp::transport).unwrap();
+            git2::transport::register("https", GitHttp::transport).unwrap();
+        });
+
+        GIT_NET.with(|net| *net.borrow_mut() = Some(self.clone()));
+    }
+}
+
+thread_local! {
+    /// The `Net` which git operations over HTTP(S) on this thread go through.
+    static GIT_NET: RefCell<Option<Net>> = RefCell::new(None);
+}
+
+impl fmt::Debug for Net {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Net")
+            .field("config", &self.config)
+            .field("proxies", &self.proxies)
+            .finish()
+    }
+}
+
+/// A libgit2 transport which speaks git's smart HTTP protocol through a `Net`.
+struct GitHttp {
+    net: Net,
+    /// The url of the repository. This changes if the server redirects us somewhere else.
+    base: Arc<Mutex<Option<Url>>>,
+}
+
+impl GitHttp {
+    fn transport(remote: &Remote) -> Result<Transport, git2::Error> {
+        let net = match GIT_NET.with(|net| net.borrow().clone()) {
+            Some(net) => net,
+            None => Net::new(NetConfig::default(), Credentials::default())
+                .map_err(|e| git2::Error::from_str(&e.to_string()))?,
+        };
+
+        let http = GitHttp {
+            net,
+            base: Arc::new(Mutex::new(None)),
+        };
+
+        Transport::smart(remote, true, http)
+    }
+}
+
+impl SmartSubtransport for GitHttp {
+    fn action(
+        &self,
+        url: &str,
+        action: Service,
+    ) -> Result<Box<SmartSubtransportStream>, git2::Error> {
+        let mut base = self.base.lock().unwrap();
+        if base.is_none() {
+            let url = Url::parse(url).map_err(|e| git2::Error::from_str(&e.to_string()))?;
+            *base = Some(url);
+        }
+
+        let (service, path, post) = match action {
+            Service::UploadPackLs => ("upload-pack", "/info/refs?service=git-upload-pack", false),
+            Service::UploadPack => ("upload-pack", "/git-upload-pack", true),
+            Service::ReceivePackLs => {
+                ("receive-pack", "/info/refs?service=git-receive-pack", false)
+            }
+            Service::ReceivePack => ("receive-pack", "/git-receive-pack", true),
+        };
+
+        Ok(Box::new(GitHttpStream {
+            net: self.net.clone(),
+            base: self.base.clone(),
+            service,
+            path,
+            post,
+            body: vec![],
+            response: None,
+        }))
+    }
+
+    fn close(&self) -> Result<(), git2::Error> {
+        Ok(())
+    }
+}
+
+/// A single request made by `GitHttp`. Whatever libgit2 writes is collected into the body of the
+/// request, which gets sent once libgit2 starts reading the response.
+struct GitHttpStream {
+    net: Net,
+    base: Arc<Mutex<Option<Url>>>,
+    service: &'static str,
+    path: &'static str,
+    post: bool,
+    body: Vec<u8>,
+    response: Option<Response>,
+}
+
+impl GitHttpStream {
+    fn send(&mut self) -> Result<Response, Error> {
+        let base = self.base.lock().unwrap().clone().unwrap();
+        let url = Url::parse(&format!(
+            "{}{}",
+            base.as_str().trim_right_matches('/'),
+            self.path
+        ))?;
+
+        let mut headers = Headers::new();
+        headers.set_raw("User-Agent", "git/1.0 (elba)");
+        let mut req = if self.post {
+            headers.set_raw(
+                "Content-Type",
+                format!("application/x-git-{}-request", self.service),
+            );
+            headers.set_raw(
+                "Accept",
+                format!("application/x-git-{}-result", self.service),
+            );
+            let mut req = self.net.client.post(url.clone());
+            req.body(mem::replace(&mut self.body, vec![]));
+            req
+        } else {
+            self.net.client.get(url.clone())
+        };
+        req.headers(headers);
+        if let Some((username, password)) = self.net.credentials.git_userpass(&url) {
+            req.basic_auth(username, Some(password));
+        }
+
+        let r = req.send()?;
+        if !r.status().is_success() {
+            return Err(StatusError {
+                url,
+                status: r.status(),
+            })?;
+        }
+
+        let expected = if self.post {
+            format!("application/x-git-{}-result", self.service)
+        } else {
+            format!("application/x-git-{}-advertisement", self.service)
+        };
+        let content_type = r.headers().get_raw("Content-Type").and_then(|t| t.one());
+        if content_type != Some(expected.as_bytes()) {
+            bail!("`{}` doesn't support git's smart HTTP protocol", base);
+        }
+
+        // If we got redirected, the rest of the requests should go straight to the new place.
+        if r.url() != &url {
+            let new = r.url().as_str();
+            if new.ends_with(self.path) {
+                let new = Url::parse(&new[..new.len() - self.path.len()])?;
+                *self.base.lock().unwrap() = Some(new);
+            }
+        }
+
+        Ok(r)
+    }
+}
+
+impl Read for GitHttpStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.response.is_none() {
+            let r = self
+                .send()
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+            self.response = Some(r);
+        }
+
+        self.response.as_mut().unwrap().read(buf)
+    }
+}
+
+impl Write for GitHttpStream {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if self.response.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "request was already sent",
+            ));
+        }
+
+        self.body.extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Splits a PEM bundle into its individual certificates.
+fn pem_certs(bundle: &str) -> Vec<String> {
+    const END: &str = "-----END CERTIFICATE-----";
+
+    bundle
+        .split_terminator(END)
+        .filter_map(|c| {
+            let start = c.find("-----BEGIN CERTIFICATE-----")?;
+            Some(format!("{}{}\n", &c[start..], END))
+        })
+        .collect()
+}
+
+/// Checks if an error is worth retrying: connection problems, timeouts and server errors are,
+/// everything else isn't.
+pub fn is_transient(e: &Error) -> bool {
+    e.causes().any(|c| {
+        if let Some(e) = c.downcast_ref::<StatusError>() {
+            let s = e.status;
+            return s.is_server_error()
+                || s == StatusCode::TooManyRequests
+                || s == StatusCode::RequestTimeout;
+        }
+
+        if let Some(e) = c.downcast_ref::<reqwest::Error>() {
+            return match e.status() {
+                Some(s) => s.is_server_error(),
+                None => !e.is_serialization() && !e.is_redirect(),
+            };
+        }
+
+        if let Some(e) = c.downcast_ref::<io::Error>() {
+            return match e.kind() {
+                io::ErrorKind::ConnectionRefused
+                | io::ErrorKind::ConnectionReset
+                | io::ErrorKind::ConnectionAborted
+                | io::ErrorKind::TimedOut
+                | io::ErrorKind::Interrupted
+                | io::ErrorKind::UnexpectedEof => true,
+                _ => false,
+            };
+        }
+
+        if let Some(e) = c.downcast_ref::<git2::Error>() {
+            return e.class() == ErrorClass::Net || e.class() == ErrorClass::Http;
+        }
+
+        false
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn proxies(no_proxy: &str) -> Proxies {
+        Proxies {
+            http: Some(Url::parse("http://proxy:3128").unwrap()),
+            https: Some(Url::parse("http://proxy:3129").unwrap()),
+            no_proxy: no_proxy.split(',').map(|s| s.to_owned()).collect(),
+        }
+    }
+
+    #[test]
+    fn proxy_for_url() {
+        let p = proxies("internal.corp,localhost");
+
+        let u = Url::parse("https://elba.io/index").unwrap();
+        assert_eq!(p.for_url(&u).map(|u| u.port()), Some(Some(3129)));
+
+        let u = Url::parse("http://elba.io/index").unwrap();
+        assert_eq!(p.for_url(&u).map(|u| u.port()), Some(Some(3128)));
+
+        let u = Url::parse("https://git.internal.corp/repo").unwrap();
+        assert!(p.for_url(&u).is_none());
+
+        let u = Url::parse("http://localhost:8080/").unwrap();
+        assert!(p.for_url(&u).is_none());
+    }
+
+    #[test]
+    fn proxy_wildcard() {
+        let p = proxies("*");
+        let u = Url::parse("https://elba.io/index").unwrap();
+        assert!(p.for_url(&u).is_none());
+    }
+
+    #[test]
+    fn transient_errors() {
+        let e = Error::from(StatusError {
+            url: Url::parse("https://elba.io").unwrap(),
+            status: StatusCode::ServiceUnavailable,
+        });
+        assert!(is_transient(&e));
+
+        let e = Error::from(StatusError {
+            url: Url::parse("https://elba.io").unwrap(),
+            status: StatusCode::NotFound,
+        });
+        assert!(!is_transient(&e));
+
+        let e = Error::from(io::Error::new(io::ErrorKind::TimedOut, "timed out"));
+        assert!(is_transient(&Error::from(
+            e.context(ErrorKind::CannotDownload)
+        )));
+    }
+
+    #[test]
+    fn pem_bundle() {
+        let bundle = "\
+# comment
+-----BEGIN CERTIFICATE-----
+AAAA
+-----END CERTIFICATE-----
+-----BEGIN CERTIFICATE-----
+BBBB
+-----END CERTIFICATE-----
+";
+        let certs = pem_certs(bundle);
+        assert_eq!(certs.len(), 2);
+        assert!(certs[1].contains("BBBB"));
+    }
+}
@@ -2,11 +2,200 @@
 //! purposes, not for the user to see.)
 
 use super::config::Verbosity;
+use atty;
+use indexmap::IndexMap;
+use std::{
+    io::{self, Write},
+    sync::{Arc, Mutex},
+};
 
 // TODO: impl Shell should have warn, error, info
 
+/// Receives updates about how far along the retrieval of a single package is.
+///
+/// Packages can be retrieved on any thread, so implementors have to be thread-safe.
+pub trait Progress: Send + Sync {
+    /// `done` bytes of a download out of `total` (if the total is known) have been received.
+    fn bytes(&self, done: u64, total: Option<u64>);
+    /// `done` objects of a git clone out of `total` have been received.
+    fn objects(&self, done: usize, total: usize);
+    /// Retrieval is over, and `ok` says whether it was successful.
+    fn finish(&self, ok: bool);
+    /// Whether retrieval should stop as soon as it can, e.g. because some other download that it
+    /// was part of has already failed.
+    fn cancelled(&self) -> bool {
+        false
+    }
+}
+
+/// A `Progress` which ignores every update.
+#[derive(Debug, Clone, Copy)]
+pub struct NoProgress;
+
+impl Progress for NoProgress {
+    fn bytes(&self, _done: u64, _total: Option<u64>) {}
+    fn objects(&self, _done: usize, _total: usize) {}
+    fn finish(&self, _ok: bool) {}
+}
+
 #[derive(Debug, Clone)]
 pub struct Shell {
     try_color: bool,
     verbosity: Verbosity,
+    bars: Arc<Mutex<Bars>>,
+}
+
+impl Shell {
+    pub fn new(verbosity: Verbosity, try_color: bool) -> Self {
+        Shell {
+            try_color,
+            verbosity,
+            bars: Arc::new(Mutex::new(Bars::default())),
+        }
+    }
+
+    pub fn verbosity(&self) -> Verbosity {
+        self.verbosity
+    }
+
+    /// Prints a status line like `   published group/name 1.0.0`, unless we're meant to be quiet.
+    pub fn status(&self, verb: &str, msg: &str) {
+        if self.verbosity != Verbosity::Quiet {
+            eprintln!("{:>12} {}", verb, msg);
+        }
+    }
+
+    /// Starts reporting the progress of retrieving the package `name`.
+    ///
+    /// If stderr is a terminal, every package gets its own progress bar; otherwise, a line is
+    /// printed when the retrieval starts and another when it's done.
+    pub fn progress(&self, name: &str) -> ShellProgress {
+        let tty = atty::is(atty::Stream::Stderr);
+
+        if self.verbosity != Verbosity::Quiet && !tty {
+            eprintln!("downloading {}", name);
+        }
+
+        ShellProgress {
+            name: name.to_owned(),
+            quiet: self.verbosity == Verbosity::Quiet,
+            tty,
+            bars: Arc::clone(&self.bars),
+        }
+    }
+}
+
+impl Default for Shell {
+    fn default() -> Self {
+        Shell::new(Verbosity::Normal, true)
+    }
+}
+
+/// The progress bars which are currently on screen.
+#[derive(Debug, Default)]
+struct Bars {
+    /// The state of each package's bar.
+    bars: IndexMap<String, Status>,
+    /// How many lines we drew last time, so that we know how far to move the cursor back up.
+    drawn: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Status {
+    Bytes(u64, Option<u64>),
+    Objects(usize, usize),
+    Done,
+    Failed,
+}
+
+impl Bars {
+    fn draw(&mut self) {
+        let stderr = io::stderr();
+        let mut out = stderr.lock();
+
+        if self.drawn > 0 {
+            let _ = write!(out, "\x1b[{}A", self.drawn);
+        }
+
+        for (name, status) in &self.bars {
+            let _ = writeln!(out, "\x1b[2K{}", line(name, *status));
+        }
+
+        self.drawn = self.bars.len();
+        let _ = out.flush();
+    }
+}
+
+const BAR_WIDTH: usize = 30;
+
+fn line(name: &str, status: Status) -> String {
+    let (done, total, unit) = match status {
+        Status::Bytes(done, total) => (done, total, "B"),
+        Status::Objects(done, total) => (done as u64, Some(total as u64), " objects"),
+        Status::Done => return format!("{:>12} {}", "downloaded", name),
+        Status::Failed => return format!("{:>12} {}", "failed", name),
+    };
+
+    match total {
+        Some(total) if total > 0 => {
+            let filled = (done * BAR_WIDTH as u64 / total) as usize;
+            let filled = if filled > BAR_WIDTH { BAR_WIDTH } else { filled };
+            format!(
+                "{:>12} {} [{}{}] {}/{}{}",
+                "downloading",
+                name,
+                "=".repeat(filled),
+                " ".repeat(BAR_WIDTH - filled),
+                done,
+                total,
+                unit
+            )
+        }
+        _ => format!("{:>12} {} {}{}", "downloading", name, done, unit),
+    }
+}
+
+/// Reports the progress of retrieving a single package through a `Shell`.
+#[derive(Debug)]
+pub struct ShellProgress {
+    name: String,
+    quiet: bool,
+    tty: bool,
+    bars: Arc<Mutex<Bars>>,
+}
+
+impl ShellProgress {
+    fn update(&self, status: Status) {
+        if self.quiet || !self.tty {
+            return;
+        }
+
+        if let Ok(mut bars) = self.bars.lock() {
+            bars.bars.insert(self.name.clone(), status);
+            bars.draw();
+        }
+    }
+}
+
+impl Progress for ShellProgress {
+    fn bytes(&self, done: u64, total: Option<u64>) {
+        self.update(Status::Bytes(done, total))
+    }
+
+    fn objects(&self, done: usize, total: usize) {
+        self.update(Status::Objects(done, total))
+    }
+
+    fn finish(&self, ok: bool) {
+        if self.quiet {
+            return;
+        }
+
+        match (self.tty, ok) {
+            (true, true) => self.update(Status::Done),
+            (true, false) => self.update(Status::Failed),
+            (false, true) => eprintln!("downloaded {}", self.name),
+            (false, false) => eprintln!("failed to download {}", self.name),
+        }
+    }
 }
@@ -0,0 +1,487 @@
+#![cfg(unix)]
+
+extern crate elba;
+extern crate flate2;
+extern crate sha2;
+extern crate tar;
+
+mod common;
+
+use common::{config, executable, write_files, IDRIS};
+use elba::{
+    build::context::BuildProfile,
+    cli::build::{build, doc, BuildCtx},
+};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+/// A stand-in for Idris 2, which takes the output directory and the name of the output
+/// separately, and checks one module at a time into its own build directory.
+const IDRIS2: &str = r#"#!/bin/sh
+out=""
+dir="build/exec"
+build="build"
+cg=""
+while [ $# -gt 0 ]; do
+    case "$1" in
+        --version) echo "Idris 2, version 0.2.1"; exit 0 ;;
+        --check)
+            mkdir -p "build/ttc/$(dirname "$2")"
+            echo "$2" > "build/ttc/${2%.*}.ttc"
+            exit 0 ;;
+        -o) out="$2"; shift ;;
+        --output-dir) dir="$2"; shift ;;
+        --cg) cg="$2"; shift ;;
+        --build-dir) build="$2"; shift ;;
+        --source-dir) shift ;;
+    esac
+    shift
+done
+mkdir -p "$build/ttc"
+[ -n "$out" ] && echo "$cg" > "$build/ttc/Main.ttc" && echo "$cg" > "$dir/$out"
+"#;
+
+const MANIFEST: &str = r#"
+[package]
+name = 'build/hello'
+version = '1.0.0'
+authors = []
+
+[[targets.bin]]
+name = 'hello'
+main = 'src/Main.idr'
+backends = ['node', 'c']
+"#;
+
+/// A library for the package being built to depend on.
+const DEP: &str = r#"
+[package]
+name = 'build/dep'
+version = '0.1.0'
+authors = []
+
+[targets.lib]
+path = 'src/'
+modules = ['Dep']
+"#;
+
+fn ctx(base: &Path, backends: &[&str], compiler: Option<PathBuf>) -> BuildCtx {
+    let c = config(base);
+
+    // Only the stubs in `bin/` can be found, whatever the environment the tests run in has.
+    let mut ctx = BuildCtx::from_config(&c, base.join("project")).unwrap();
+    ctx.backends = backends.iter().map(|b| b.to_string()).collect();
+    ctx.compiler = compiler;
+    ctx.path = base.join("bin").into_os_string();
+    ctx
+}
+
+#[test]
+fn build_backends() {
+    let base = env::temp_dir().join("elba-build-test-backends");
+    let _ = fs::remove_dir_all(&base);
+
+    let bin = base.join("bin");
+    write_files(
+        &bin,
+        &[
+            ("idris", IDRIS),
+            ("idris-codegen-c", "#!/bin/sh\n"),
+            ("idris-codegen-node", "#!/bin/sh\n"),
+        ],
+    );
+    for file in &["idris", "idris-codegen-c", "idris-codegen-node"] {
+        executable(&bin.join(file));
+    }
+
+    write_files(
+        &base.join("project"),
+        &[("elba.toml", MANIFEST), ("src/Main.idr", "module Main")],
+    );
+    let target = base.join("project/target/dev");
+
+    // Every backend the binary declares is built, each with its own kind of output.
+    build(&ctx(&base, &[], None)).unwrap();
+    let node = target.join("node/hello.js");
+    assert_eq!(fs::read_to_string(&node).unwrap(), "node\n");
+    let c = target
+        .join("c")
+        .join(PathBuf::from("hello").with_extension(env::consts::EXE_EXTENSION));
+    assert_eq!(fs::read_to_string(&c).unwrap(), "c\n");
+    // Nothing the compiler goes through on the way gets left behind, least of all in the sources.
+    assert!(!base.join("project/src/Main.ibc").exists());
+    assert!(!target.join("node/build").exists());
+
+    // Backends on the command line take over, and have to be available.
+    fs::remove_dir_all(&target).unwrap();
+    build(&ctx(&base, &["node"], None)).unwrap();
+    assert!(node.exists());
+    assert!(!c.exists());
+    assert!(build(&ctx(&base, &["php"], None)).is_err());
+
+    let _ = fs::remove_dir_all(&base);
+}
+
+#[test]
+fn build_idris_version() {
+    let base = env::temp_dir().join("elba-build-test-idris-version");
+    let _ = fs::remove_dir_all(&base);
+
+    let idris = base.join("bin/idris");
+    write_files(&base.join("bin"), &[("idris", IDRIS)]);
+    executable(&idris);
+    fs::create_dir_all(base.join("cache")).unwrap();
+
+    let manifest = MANIFEST.replace("authors = []", "authors = []\nidris-version = '>= 2.0.0'");
+    write_files(
+        &base.join("project"),
+        &[("elba.toml", &manifest), ("src/Main.idr", "module Main")],
+    );
+
+    // The compiler's version gets checked before anything else happens.
+    let err = build(&ctx(&base, &[], Some(idris.clone())))
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains(">= 2.0.0") && err.contains("1.3.0"));
+    assert!(!base.join("project/elba.lock").exists());
+    assert!(fs::read_to_string(base.join("cache/compilers.toml"))
+        .unwrap()
+        .contains("1.3.0"));
+
+    // A compiler which fails with `--version` doesn't get taken at its word, whatever it prints.
+    let broken = base.join("bin/broken");
+    write_files(
+        &base.join("bin"),
+        &[("broken", "#!/bin/sh\necho 2.0.0\nexit 1\n")],
+    );
+    executable(&broken);
+    let err = build(&ctx(&base, &[], Some(broken)))
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("--version"));
+
+    let _ = fs::remove_dir_all(&base);
+}
+
+#[test]
+fn build_idris2() {
+    let base = env::temp_dir().join("elba-build-test-idris2");
+    let _ = fs::remove_dir_all(&base);
+
+    let idris2 = base.join("bin/idris2");
+    write_files(&base.join("bin"), &[("idris2", IDRIS2)]);
+    executable(&idris2);
+
+    let manifest = MANIFEST.replace("backends = ['node', 'c']", "");
+    write_files(
+        &base.join("project"),
+        &[("elba.toml", &manifest), ("src/Main.idr", "module Main")],
+    );
+    let target = base.join("project/target/dev");
+
+    // Idris 2 has its own backends, and defaults to Chez Scheme.
+    build(&ctx(&base, &[], Some(idris2.clone()))).unwrap();
+    assert_eq!(
+        fs::read_to_string(target.join("chez/hello")).unwrap(),
+        "chez\n"
+    );
+    assert!(!base.join("project/build").exists());
+    assert!(!target.join("chez/build").exists());
+    build(&ctx(&base, &["node"], Some(idris2.clone()))).unwrap();
+    assert_eq!(
+        fs::read_to_string(target.join("node/hello.js")).unwrap(),
+        "node\n"
+    );
+    assert!(build(&ctx(&base, &["c"], Some(idris2.clone()))).is_err());
+
+    let _ = fs::remove_dir_all(&base);
+}
+
+#[test]
+fn build_lib() {
+    let base = env::temp_dir().join("elba-build-test-lib");
+    let _ = fs::remove_dir_all(&base);
+
+    let bin = base.join("bin");
+    write_files(
+        &bin,
+        &[
+            ("idris", IDRIS),
+            ("idris2", IDRIS2),
+            ("idris-codegen-node", "#!/bin/sh\n"),
+        ],
+    );
+    for file in &["idris", "idris2", "idris-codegen-node"] {
+        executable(&bin.join(file));
+    }
+
+    let manifest = MANIFEST.replace("backends = ['node', 'c']", "backends = ['node']")
+        + "\n[targets.lib]\npath = 'src/'\nmodules = ['Hello']\n";
+    write_files(
+        &base.join("project"),
+        &[
+            ("elba.toml", &manifest),
+            ("src/Main.idr", "module Main"),
+            ("src/Hello.idr", "module Hello"),
+            ("src/Hello/Util.lidr", "> module Hello.Util"),
+        ],
+    );
+    let lib = base.join("project/target/dev/lib");
+
+    // Every module of the library gets built, and ends up where its module says it should be.
+    build(&ctx(&base, &[], Some(bin.join("idris")))).unwrap();
+    assert_eq!(
+        fs::read_to_string(lib.join("Hello/Util.ibc")).unwrap(),
+        "Hello/Util.lidr\n"
+    );
+    assert!(lib.join("Hello.ibc").exists());
+    assert!(!lib.join("Main.ibc").exists());
+    assert!(!lib.join("build").exists());
+
+    // The same goes for Idris 2, whose builds are in a directory of their own.
+    build(&ctx(&base, &[], Some(bin.join("idris2")))).unwrap();
+    assert!(lib.join("Hello/Util.ttc").exists());
+    assert!(!lib.join("Hello.ibc").exists());
+
+    let _ = fs::remove_dir_all(&base);
+}
+
+#[test]
+fn build_deps() {
+    let base = env::temp_dir().join("elba-build-test-deps");
+    let _ = fs::remove_dir_all(&base);
+
+    let idris = base.join("bin/idris");
+    write_files(&base.join("bin"), &[("idris", IDRIS)]);
+    executable(&idris);
+
+    write_files(
+        &base.join("dep"),
+        &[("elba.toml", DEP), ("src/Dep.idr", "module Dep")],
+    );
+
+    let manifest = format!(
+        "[package]\nname = 'build/hello'\nversion = '1.0.0'\nauthors = []\n\n\
+         [dependencies]\n'build/dep' = {{ path = '{}' }}\n\n\
+         [targets.lib]\npath = 'src/'\nmodules = ['Hello']\n",
+        base.join("dep").display()
+    );
+    write_files(
+        &base.join("project"),
+        &[
+            ("elba.toml", &manifest),
+            ("src/Hello.idr", "module Hello\n\nimport Dep"),
+        ],
+    );
+
+    // The dependency is built into the global cache, and the library is checked with its build on
+    // the import path.
+    build(&ctx(&base, &[], Some(idris.clone()))).unwrap();
+    let hello = fs::read_to_string(base.join("project/target/dev/lib/Hello.ibc")).unwrap();
+    let imports = hello.split_whitespace().skip(1).collect::<Vec<_>>();
+    assert_eq!(imports.len(), 1);
+    assert!(imports[0].starts_with(&*base.join("cache/build").to_string_lossy()));
+    assert!(Path::new(imports[0]).join("Dep.ibc").exists());
+
+    // Building the dependency with other flags doesn't reuse the old build...
+    let first = imports[0].to_owned();
+    write_files(
+        &base.join("dep"),
+        &[(
+            "elba.toml",
+            &format!("{}idris-opts = ['--warnreach']\n", DEP),
+        )],
+    );
+    build(&ctx(&base, &[], Some(idris.clone()))).unwrap();
+    let hello = fs::read_to_string(base.join("project/target/dev/lib/Hello.ibc")).unwrap();
+    let imports = hello.split_whitespace().skip(1).collect::<Vec<_>>();
+    assert_ne!(imports[0], first);
+    assert!(Path::new(imports[0]).join("Dep.ibc").exists());
+
+    // ...and neither does building it in another profile...
+    let second = imports[0].to_owned();
+    let mut release = ctx(&base, &[], Some(idris));
+    release.profile = BuildProfile::Release;
+    build(&release).unwrap();
+    let hello = fs::read_to_string(base.join("project/target/release/lib/Hello.ibc")).unwrap();
+    let imports = hello.split_whitespace().skip(1).collect::<Vec<_>>();
+    assert_ne!(imports[0], second);
+    assert!(Path::new(&second).join("Dep.ibc").exists());
+
+    // ...or with another version of the compiler.
+    let newer = base.join("bin/newer");
+    write_files(
+        &base.join("bin"),
+        &[("newer", &IDRIS.replace("1.3.0-git:PRE", "1.3.1"))],
+    );
+    executable(&newer);
+    build(&ctx(&base, &[], Some(newer))).unwrap();
+    let hello = fs::read_to_string(base.join("project/target/dev/lib/Hello.ibc")).unwrap();
+    let imports = hello.split_whitespace().skip(1).collect::<Vec<_>>();
+    assert_ne!(imports[0], second);
+
+    let _ = fs::remove_dir_all(&base);
+}
+
+#[test]
+fn build_alias() {
+    let base = env::temp_dir().join("elba-build-test-alias");
+    let _ = fs::remove_dir_all(&base);
+
+    let bin = base.join("bin");
+    write_files(
+        &bin,
+        &[("idris", IDRIS), ("idris-codegen-node", "#!/bin/sh\n")],
+    );
+    for file in &["idris", "idris-codegen-node"] {
+        executable(&bin.join(file));
+    }
+
+    write_files(
+        &base.join("dep"),
+        &[("elba.toml", DEP), ("src/Dep.idr", "module Dep")],
+    );
+    let manifest = format!(
+        "[package]\nname = 'build/hello'\nversion = '1.0.0'\nauthors = []\n\n\
+         [dependencies]\n'build/dep' = {{ path = '{}' }}\n\n\
+         [aliases]\n'build/dep' = 'Yeet'\n\n\
+         [[targets.bin]]\nname = 'hello'\nmain = 'src/Main.idr'\nbackends = ['node']\n",
+        base.join("dep").display()
+    );
+    write_files(
+        &base.join("project"),
+        &[
+            ("elba.toml", &manifest),
+            ("src/Main.idr", "module Main\n\nimport Yeet"),
+        ],
+    );
+
+    // The binary gets the alias on its import path instead of the package it aliases, and the
+    // alias's build only has the renamed module, so `Dep` can't be seen from the binary at all.
+    build(&ctx(&base, &[], None)).unwrap();
+    let hello = fs::read_to_string(base.join("project/target/dev/node/hello.js")).unwrap();
+    let imports = hello.split_whitespace().skip(1).collect::<Vec<_>>();
+    assert_eq!(imports.len(), 1);
+    let alias = Path::new(imports[0]);
+    assert!(alias.to_string_lossy().ends_with("~Yeet"));
+    assert!(alias.join("Yeet.ibc").exists());
+    assert!(!alias.join("Dep.ibc").exists());
+    assert!(!alias.join("src").exists());
+
+    let _ = fs::remove_dir_all(&base);
+}
+
+#[test]
+fn build_alias_used_by_dep() {
+    let base = env::temp_dir().join("elba-build-test-alias-used");
+    let _ = fs::remove_dir_all(&base);
+
+    let idris = base.join("bin/idris");
+    write_files(&base.join("bin"), &[("idris", IDRIS)]);
+    executable(&idris);
+
+    write_files(
+        &base.join("dep"),
+        &[("elba.toml", DEP), ("src/Dep.idr", "module Dep")],
+    );
+    let other = |deps: &str| {
+        format!(
+            "[package]\nname = 'build/other'\nversion = '0.1.0'\nauthors = []\n\n\
+             [dependencies]\n{}\n\n\
+             [targets.lib]\npath = 'src/'\nmodules = ['Dep.Other']\n",
+            deps
+        )
+    };
+    write_files(
+        &base.join("other"),
+        &[
+            ("elba.toml", &other("")),
+            ("src/Dep/Other.idr", "module Dep.Other"),
+        ],
+    );
+    let manifest = format!(
+        "[package]\nname = 'build/hello'\nversion = '1.0.0'\nauthors = []\n\n\
+         [dependencies]\n'build/dep' = {{ path = '{}' }}\n'build/other' = {{ path = '{}' }}\n\n\
+         [aliases]\n'build/dep' = 'Yeet'\n\n\
+         [targets.lib]\npath = 'src/'\nmodules = ['Hello']\n",
+        base.join("dep").display(),
+        base.join("other").display()
+    );
+    write_files(
+        &base.join("project"),
+        &[("elba.toml", &manifest), ("src/Hello.idr", "module Hello")],
+    );
+
+    // With the alias, `build/dep` doesn't export `Dep` anymore, so it doesn't clash with
+    // `build/other`...
+    build(&ctx(&base, &[], Some(idris.clone()))).unwrap();
+
+    // ...unless `build/other` uses it, since then `Dep` has to be on the import path after all.
+    write_files(
+        &base.join("other"),
+        &[(
+            "elba.toml",
+            &other(&format!(
+                "'build/dep' = {{ path = '{}' }}",
+                base.join("dep").display()
+            )),
+        )],
+    );
+    let _ = fs::remove_file(base.join("project/elba.lock"));
+    let err = build(&ctx(&base, &[], Some(idris)))
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("build/dep") && err.contains("build/other"));
+
+    let _ = fs::remove_dir_all(&base);
+}
+
+#[test]
+fn build_docs() {
+    let base = env::temp_dir().join("elba-build-test-docs");
+    let _ = fs::remove_dir_all(&base);
+
+    let idris = base.join("bin/idris");
+    write_files(&base.join("bin"), &[("idris", IDRIS)]);
+    executable(&idris);
+
+    let manifest = "[package]\nname = 'build/hello-docs'\nversion = '1.0.0'\nauthors = []\n\n\
+                    [targets.lib]\npath = 'src/'\nmodules = ['Hello']\n";
+    write_files(
+        &base.join("project"),
+        &[
+            ("elba.toml", manifest),
+            ("src/Hello.idr", "module Hello"),
+            ("src/Hello/Util.idr", "module Hello.Util"),
+        ],
+    );
+    let docs = base.join("project/target/dev/doc");
+
+    // Docs aren't generated unless they're asked for...
+    build(&ctx(&base, &[], Some(idris.clone()))).unwrap();
+    assert!(!docs.exists());
+
+    // ...either with `elba doc`...
+    assert_eq!(doc(&ctx(&base, &[], Some(idris.clone()))).unwrap(), docs);
+    assert_eq!(
+        fs::read_to_string(docs.join("index.html")).unwrap(),
+        "package build_hello_docs\n\nmodules = Hello, Hello.Util\n"
+    );
+    assert!(!docs.join("build").exists());
+
+    // ...or by the profile.
+    fs::remove_dir_all(&docs).unwrap();
+    write_files(
+        &base.join("project"),
+        &[(
+            "elba.toml",
+            &format!("{}\n[profile.dev]\ndocs = true\n", manifest),
+        )],
+    );
+    build(&ctx(&base, &[], Some(idris))).unwrap();
+    assert!(docs.join("index.html").exists());
+
+    let _ = fs::remove_dir_all(&base);
+}
@@ -0,0 +1,219 @@
+//! Helpers shared between the integration tests.
+
+#![allow(dead_code)]
+
+use elba::util::config::{Config, Verbosity};
+use flate2::{write::GzEncoder, Compression};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    fs,
+    io::{prelude::*, BufRead, BufReader},
+    net::TcpListener,
+    path::Path,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+use tar::{Builder, Header};
+
+/// The config of a test which keeps everything it writes under `base`, and doesn't print anything.
+pub fn config(base: &Path) -> Config {
+    let mut c = Config::default();
+    c.directories.cache = base.join("cache");
+    c.directories.rest = base.join("rest");
+    c.term.verbosity = Verbosity::Quiet;
+    c.term.color = false;
+    c
+}
+
+/// Writes each of `files` (relative paths and their contents) under `dir`.
+pub fn write_files(dir: &Path, files: &[(&str, &str)]) {
+    for (name, contents) in files {
+        let path = dir.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+}
+
+#[cfg(unix)]
+pub fn executable(path: &Path) {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::set_permissions(path, fs::Permissions::from_mode(0o755)).unwrap();
+}
+
+/// A stand-in for the compiler, which "generates" a binary by writing the name of the backend it
+/// was asked to use to the output file (and an ibc file for its main module into the directory it
+/// was given for them), "checks" modules by writing an ibc file next to each, and "documents" a
+/// package by copying its ipkg. All of them also say where the compiler was asked to
+/// look for imports.
+pub const IDRIS: &str = r#"#!/bin/sh
+out=""
+cg=""
+imports=""
+ibc="."
+main=""
+while [ $# -gt 0 ]; do
+    case "$1" in
+        --version) echo "1.3.0-git:PRE"; exit 0 ;;
+        --check)
+            shift
+            for f in "$@"; do echo "$f$imports" > "${f%.*}.ibc"; done
+            exit 0 ;;
+        --mkdoc)
+            mkdir -p "${2%.ipkg}_doc"
+            echo "$(cat "$2")$imports" > "${2%.ipkg}_doc/index.html"
+            exit 0 ;;
+        -i) imports="$imports $2"; shift ;;
+        -o) out="$2"; shift ;;
+        --codegen) cg="$2"; shift ;;
+        --ibcsubdir) ibc="$2"; shift ;;
+        --sourcepath) shift ;;
+        *) main="$1" ;;
+    esac
+    shift
+done
+[ -n "$main" ] && echo "$main" > "$ibc/$(basename "${main%.*}").ibc"
+[ -n "$out" ] && echo "$cg$imports" > "$out"
+"#;
+
+/// Builds a gzipped tarball out of a list of file names and their contents.
+pub fn tarball(files: &[(&str, &str)]) -> Vec<u8> {
+    let mut b = Builder::new(GzEncoder::new(vec![], Compression::default()));
+    for (path, contents) in files {
+        let mut h = Header::new_gnu();
+        h.set_path(path).unwrap();
+        h.set_size(contents.len() as u64);
+        h.set_mode(0o644);
+        h.set_cksum();
+        b.append(&h, contents.as_bytes()).unwrap();
+    }
+    b.into_inner().unwrap().finish().unwrap()
+}
+
+/// The hex-encoded sha256 hash of `bytes`.
+pub fn sha256(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Starts an HTTP server on a random local port which serves `files` (keyed by path, e.g.
+/// `/a.tar.gz`) and responds with a 404 to everything else. Returns the base url of the server.
+///
+/// Every file is sent with an `ETag` (the hash of its contents), and requests with a matching
+/// `If-None-Match` get a 304 instead.
+pub fn serve(files: Vec<(String, Vec<u8>)>) -> String {
+    serve_shared(Arc::new(Mutex::new(files.into_iter().collect())))
+}
+
+/// Like `serve`, but the files being served can be changed while the server is running.
+pub fn serve_shared(files: Arc<Mutex<HashMap<String, Vec<u8>>>>) -> String {
+    serve_with(files, None, Arc::new(Mutex::new(Load::default())))
+}
+
+/// How many requests a server is handling.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Load {
+    pub active: usize,
+    /// The most requests that were ever being handled at the same time.
+    pub peak: usize,
+}
+
+/// Like `serve`, but files trickle out in small chunks, waiting for `delay` before each one.
+/// Also returns how many requests the server is handling.
+pub fn serve_slow(files: Vec<(String, Vec<u8>)>, delay: Duration) -> (String, Arc<Mutex<Load>>) {
+    let load = Arc::new(Mutex::new(Load::default()));
+    let files = Arc::new(Mutex::new(files.into_iter().collect()));
+    (serve_with(files, Some(delay), Arc::clone(&load)), load)
+}
+
+fn serve_with(
+    files: Arc<Mutex<HashMap<String, Vec<u8>>>>,
+    delay: Option<Duration>,
+    load: Arc<Mutex<Load>>,
+) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = match stream {
+                Ok(s) => s,
+                Err(_) => continue,
+            };
+            let files = Arc::clone(&files);
+            let load = Arc::clone(&load);
+
+            thread::spawn(move || {
+                {
+                    let mut load = load.lock().unwrap();
+                    load.active += 1;
+                    load.peak = load.peak.max(load.active);
+                }
+
+                let mut path = String::new();
+                let mut etag = None;
+                {
+                    let mut r = BufReader::new(&mut stream);
+                    let mut line = String::new();
+                    r.read_line(&mut line).unwrap();
+                    if let Some(p) = line.split_whitespace().nth(1) {
+                        path = p.to_owned();
+                    }
+                    loop {
+                        line.clear();
+                        if r.read_line(&mut line).unwrap() == 0 || line == "\r\n" {
+                            break;
+                        }
+                        let mut header = line.splitn(2, ':');
+                        let name = header.next().unwrap().trim().to_lowercase();
+                        if name == "if-none-match" {
+                            etag = header.next().map(|v| v.trim().to_owned());
+                        }
+                    }
+                }
+
+                let (status, body, tag) = match files.lock().unwrap().get(&path) {
+                    Some(b) => {
+                        let tag = format!("\"{}\"", sha256(b));
+                        if etag.as_ref() == Some(&tag) {
+                            ("304 Not Modified", vec![], Some(tag))
+                        } else {
+                            ("200 OK", b.clone(), Some(tag))
+                        }
+                    }
+                    None => ("404 Not Found", vec![], None),
+                };
+
+                let tag = tag.map(|t| format!("ETag: {}\r\n", t)).unwrap_or_default();
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 {}\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n",
+                    status,
+                    tag,
+                    body.len()
+                );
+                match delay {
+                    Some(delay) => {
+                        for chunk in body.chunks(256) {
+                            thread::sleep(delay);
+                            if stream.write_all(chunk).is_err() {
+                                break;
+                            }
+                        }
+                    }
+                    None => {
+                        let _ = stream.write_all(&body);
+                    }
+                }
+
+                load.lock().unwrap().active -= 1;
+            });
+        }
+    });
+
+    format!("http://{}", addr)
+}
@@ -0,0 +1,249 @@
+extern crate elba;
+extern crate flate2;
+extern crate sha2;
+#[macro_use]
+extern crate slog;
+extern crate tar;
+
+mod common;
+
+use elba::{
+    index::Indices,
+    package::{
+        lockfile::LockfileToml,
+        resolution::{DirectRes, IndexRes},
+        PackageId,
+    },
+    resolve::solve::Solve,
+    retrieve::{cache::UpdatePolicy, Cache},
+};
+use slog::Logger;
+use std::{
+    collections::HashMap,
+    env, fs,
+    path::PathBuf,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+fn cache(name: &str) -> (Cache, PathBuf) {
+    let path = env::temp_dir().join(format!("elba-download-test-{}", name));
+    let _ = fs::remove_dir_all(&path);
+
+    let def_ix = IndexRes::from_str("index+dir+file://none").unwrap();
+    let logger = Logger::root(slog::Discard, o!());
+    (Cache::from_disk(&logger, path.clone(), def_ix), path)
+}
+
+fn solve(base: &str, pkgs: &[&str]) -> Solve {
+    let mut lockfile = String::new();
+    for pkg in pkgs {
+        lockfile.push_str(&format!(
+            "[[packages]]\nid = \"download/{}@tar+{}/{}.tar.gz\"\nversion = \"1.0.0\"\n\n",
+            pkg, base, pkg
+        ));
+    }
+
+    LockfileToml::from_str(&lockfile).unwrap().into()
+}
+
+#[test]
+fn download_concurrent() {
+    let pkgs = ["a", "b", "c", "d", "e", "f", "g", "h", "i", "j"];
+    let files = pkgs
+        .iter()
+        .map(|p| {
+            let contents = format!("[package]\nname = \"download/{}\"", p);
+            (
+                format!("/{}.tar.gz", p),
+                common::tarball(&[("elba.toml", contents.as_str())]),
+            )
+        })
+        .collect::<Vec<_>>();
+    let (base, load) = common::serve_slow(files, Duration::from_millis(200));
+    let (cache, path) = cache("concurrent");
+
+    let dirs = cache
+        .load_all(&solve(&base, &pkgs), &mut Indices::new(vec![]))
+        .unwrap();
+
+    assert_eq!(dirs.len(), pkgs.len());
+    for dir in dirs.values() {
+        assert!(dir.path().join("elba.toml").exists());
+    }
+    assert!(load.lock().unwrap().peak > 1);
+
+    let _ = fs::remove_dir_all(&path);
+}
+
+#[test]
+fn download_failure() {
+    let files = vec![(
+        "/a.tar.gz".to_owned(),
+        common::tarball(&[("elba.toml", "[package]")]),
+    )];
+    let base = common::serve(files);
+    let (cache, path) = cache("failure");
+
+    let res = cache.load_all(&solve(&base, &["a", "missing"]), &mut Indices::new(vec![]));
+    assert!(res.is_err());
+
+    let _ = fs::remove_dir_all(&path);
+}
+
+#[test]
+fn download_cancelled() {
+    // Enough data that doesn't compress well that `a` takes a few seconds to trickle in.
+    let mut x = 1u32;
+    let big = (0..16 * 1024)
+        .map(|_| {
+            x = x.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (b'a' + (x >> 16) as u8 % 26) as char
+        })
+        .collect::<String>();
+    let files = vec![(
+        "/a.tar.gz".to_owned(),
+        common::tarball(&[("elba.toml", "[package]"), ("big", big.as_str())]),
+    )];
+    let (base, _) = common::serve_slow(files, Duration::from_millis(100));
+    let (cache, path) = cache("cancelled");
+
+    // `missing` fails straight away, which stops `a` long before it would have finished.
+    let start = Instant::now();
+    let res = cache.load_all(&solve(&base, &["a", "missing"]), &mut Indices::new(vec![]));
+    assert!(start.elapsed() < Duration::from_secs(2));
+    assert!(res
+        .unwrap_err()
+        .causes()
+        .any(|c| c.to_string().contains("404")));
+
+    let _ = fs::remove_dir_all(&path);
+}
+
+fn index_tarball(versions: &[&str]) -> Vec<u8> {
+    let entries = versions
+        .iter()
+        .map(|v| {
+            format!(
+                "{{ \"name\": \"download/a\", \"version\": \"{}\", \"dependencies\": [], \
+                 \"yanked\": false, \"location\": \"dir+file://test\" }}\n",
+                v
+            )
+        })
+        .collect::<String>();
+
+    common::tarball(&[
+        ("index.toml", "[index]\nsecure = false\n"),
+        ("download/a", entries.as_str()),
+    ])
+}
+
+fn versions(indices: &mut Indices, base: &str) -> usize {
+    let pkg = PackageId::from_str(&format!("download/a@index+tar+{}/index.tar.gz", base)).unwrap();
+    indices.entries(&pkg).unwrap().len()
+}
+
+#[test]
+fn index_update() {
+    let files = Arc::new(Mutex::new(HashMap::new()));
+    files
+        .lock()
+        .unwrap()
+        .insert("/index.tar.gz".to_owned(), index_tarball(&["1.0.0"]));
+    let base = common::serve_shared(Arc::clone(&files));
+    let index = vec![DirectRes::from_str(&format!("tar+{}/index.tar.gz", base)).unwrap()];
+    let (cache, path) = cache("index-update");
+
+    assert_eq!(versions(&mut cache.get_indices(&index).unwrap(), &base), 1);
+
+    // A freshly downloaded index isn't updated until it goes stale...
+    files.lock().unwrap().insert(
+        "/index.tar.gz".to_owned(),
+        index_tarball(&["1.0.0", "1.1.0"]),
+    );
+    assert_eq!(versions(&mut cache.get_indices(&index).unwrap(), &base), 1);
+
+    // ...unless we ask for it.
+    assert_eq!(
+        versions(&mut cache.update_indices(&index).unwrap(), &base),
+        2
+    );
+
+    // If a stale index can't be updated, the cached copy is used.
+    files.lock().unwrap().clear();
+    let cache = cache.with_policy(UpdatePolicy { ttl: 0 });
+    assert_eq!(versions(&mut cache.get_indices(&index).unwrap(), &base), 2);
+    assert!(cache.update_indices(&index).is_err());
+
+    let _ = fs::remove_dir_all(&path);
+}
+
+#[test]
+fn index_sparse() {
+    let entry = |v: &str| {
+        format!(
+            "{{ \"name\": \"download/a\", \"version\": \"{}\", \"dependencies\": [], \
+             \"yanked\": false, \"location\": \"dir+file://test\" }}\n",
+            v
+        )
+    };
+
+    let files = Arc::new(Mutex::new(HashMap::new()));
+    files.lock().unwrap().insert(
+        "/index.toml".to_owned(),
+        b"[index]\nsecure = false\n".to_vec(),
+    );
+    files
+        .lock()
+        .unwrap()
+        .insert("/download/a".to_owned(), entry("1.0.0").into_bytes());
+    let base = common::serve_shared(Arc::clone(&files));
+    let index = vec![DirectRes::from_str(&format!("sparse+{}", base)).unwrap()];
+    let (cache, path) = cache("sparse");
+    let pkg = |name: &str| PackageId::from_str(&format!("{}@index+sparse+{}", name, base)).unwrap();
+
+    // Entries are fetched as they're asked for.
+    let mut indices = cache.get_indices(&index).unwrap();
+    assert_eq!(indices.entries(&pkg("download/a")).unwrap().len(), 1);
+    assert!(indices.entries(&pkg("download/missing")).is_err());
+
+    // Unchanged entries are kept, and changed ones are fetched again.
+    let mut indices = cache.get_indices(&index).unwrap();
+    assert_eq!(indices.entries(&pkg("download/a")).unwrap().len(), 1);
+
+    let entries = format!("{}{}", entry("1.0.0"), entry("1.1.0"));
+    files
+        .lock()
+        .unwrap()
+        .insert("/download/a".to_owned(), entries.into_bytes());
+    let mut indices = cache.get_indices(&index).unwrap();
+    assert_eq!(indices.entries(&pkg("download/a")).unwrap().len(), 2);
+
+    let _ = fs::remove_dir_all(&path);
+}
+
+#[test]
+fn download_checksum() {
+    let tarball = common::tarball(&[("elba.toml", "[package]")]);
+    let hash = common::sha256(&tarball);
+    let base = common::serve(vec![("/a.tar.gz".to_owned(), tarball)]);
+    let (cache, path) = cache("checksum");
+
+    let lockfile = |hash: &str| -> Solve {
+        let lockfile = format!(
+            "[[packages]]\nid = \"download/a@tar+{}/a.tar.gz#sha256={}\"\nversion = \"1.0.0\"\n",
+            base, hash
+        );
+        LockfileToml::from_str(&lockfile).unwrap().into()
+    };
+
+    assert!(cache
+        .load_all(&lockfile(&"0".repeat(64)), &mut Indices::new(vec![]))
+        .is_err());
+    assert!(cache
+        .load_all(&lockfile(&hash), &mut Indices::new(vec![]))
+        .is_ok());
+
+    let _ = fs::remove_dir_all(&path);
+}
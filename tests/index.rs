@@ -1,12 +1,33 @@
+extern crate base64;
 extern crate elba;
+extern crate ring;
+extern crate serde_json;
+#[macro_use]
+extern crate slog;
+extern crate untrusted;
 extern crate url;
 
 use elba::{
-    index::Index,
-    package::{resolution::DirectRes, Name},
+    index::{
+        keys::{KeyError, PublicKey},
+        EntryError, Index,
+    },
+    package::{
+        resolution::{DirectRes, IndexRes, Resolution},
+        Name, PackageId,
+    },
+    retrieve::Cache,
     util::lock::DirLock,
 };
-use std::{path::PathBuf, str::FromStr};
+use ring::signature::Ed25519KeyPair;
+use serde_json::Value;
+use slog::Logger;
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+use untrusted::Input;
 
 #[test]
 fn index_success() {
@@ -22,3 +43,211 @@ fn index_success() {
     vs.unwrap();
     // assert!(vs.is_ok())
 }
+
+fn write_index(dir: &Path, config: &str, entries: &[(&str, &str)]) {
+    let _ = fs::remove_dir_all(dir);
+    fs::create_dir_all(dir).unwrap();
+    fs::write(dir.join("index.toml"), config).unwrap();
+    for (name, entry) in entries {
+        let path = dir.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, entry).unwrap();
+    }
+}
+
+#[test]
+fn index_dependencies() {
+    let base = env::temp_dir().join("elba-index-deps-test");
+    let a = base.join("a");
+    let b = base.join("b");
+    let a_res = IndexRes::from_str(&format!("index+dir+file://{}", a.display())).unwrap();
+    let b_res = IndexRes::from_str(&format!("index+dir+file://{}", b.display())).unwrap();
+
+    write_index(
+        &a,
+        &format!(
+            "[index]\nsecure = false\n\n[index.dependencies]\nb = \"{}\"\n",
+            b_res
+        ),
+        &[
+            (
+                "deps/a",
+                r#"{ "name": "deps/a", "version": "1.0.0", "dependencies": [{ "name": "deps/b", "index": "b", "req": "1.0.0" }], "yanked": false, "location": "dir+file://test" }"#,
+            ),
+            (
+                "deps/c",
+                r#"{ "name": "deps/c", "version": "1.0.0", "dependencies": [{ "name": "deps/x", "index": "index+dir+file://elsewhere", "req": "1.0.0" }], "yanked": false, "location": "dir+file://test" }"#,
+            ),
+        ],
+    );
+    write_index(
+        &b,
+        "[index]\nsecure = false\n",
+        &[(
+            "deps/b",
+            r#"{ "name": "deps/b", "version": "1.0.0", "dependencies": [], "yanked": false, "location": "dir+file://test" }"#,
+        )],
+    );
+
+    let logger = Logger::root(slog::Discard, o!());
+    let cache = Cache::from_disk(&logger, base.join("cache"), a_res.clone());
+    let mut indices = cache.get_indices(&[a_res.res.clone()]).unwrap();
+
+    // The index `a` depends on gets loaded too.
+    let pkg = |name: &str, index: &IndexRes| {
+        PackageId::new(
+            Name::from_str(name).unwrap(),
+            Resolution::Index(index.clone()),
+        )
+    };
+    assert_eq!(indices.entries(&pkg("deps/b", &b_res)).unwrap().len(), 1);
+
+    let a_entries = indices.entries(&pkg("deps/a", &a_res)).unwrap();
+    let a_entry = a_entries.values().next().unwrap();
+    assert_eq!(a_entry.dependencies[0].index, b_res);
+
+    let err = indices.entries(&pkg("deps/c", &a_res)).unwrap_err();
+    match err.downcast::<EntryError>() {
+        Ok(EntryError::UndeclaredIndex { .. }) => {}
+        e => panic!("expected an undeclared index, got {:?}", e),
+    }
+
+    drop(indices);
+    let _ = fs::remove_dir_all(&base);
+}
+
+fn keypair(seed: u8) -> (Ed25519KeyPair, PublicKey) {
+    let pair = Ed25519KeyPair::from_seed_unchecked(Input::from(&[seed; 32])).unwrap();
+    let key = PublicKey::from_bytes(pair.public_key_bytes());
+    (pair, key)
+}
+
+/// Signs an index entry the same way an index would, returning the signed entry.
+fn sign(pair: &Ed25519KeyPair, entry: &str) -> String {
+    let mut value: Value = serde_json::from_str(entry).unwrap();
+    let message = serde_json::to_string(&value).unwrap();
+    let sig = base64::encode(pair.sign(message.as_bytes()).as_ref());
+    value["signature"] = Value::String(sig);
+    serde_json::to_string(&value).unwrap()
+}
+
+fn entry(name: &str, version: &str, location: &str) -> String {
+    format!(
+        r#"{{ "name": "{}", "version": "{}", "dependencies": [], "yanked": false, "location": "{}" }}"#,
+        name, version, location
+    )
+}
+
+#[test]
+fn index_secure() {
+    let dir = env::temp_dir().join("elba-index-secure-test");
+    let res = IndexRes::from_str(&format!("index+dir+file://{}", dir.display())).unwrap();
+    let (pair, key) = keypair(1);
+    let tarball = "tar+https://example.com/a.tar.gz#sha256=abcd";
+
+    let ok = sign(&pair, &entry("secure/ok", "1.0.0", tarball));
+    let git = sign(
+        &pair,
+        &entry("secure/git", "1.0.0", "git+https://example.com/git"),
+    );
+    let unsigned = entry("secure/unsigned", "1.0.0", tarball);
+    let tampered =
+        sign(&pair, &entry("secure/tampered", "1.0.0", tarball)).replace("1.0.0", "1.0.1");
+    let other = sign(&pair, &entry("secure/other", "1.0.0", tarball));
+
+    write_index(
+        &dir,
+        &format!("[index]\nsecure = true\nkeys = [\"{}\"]\n", key),
+        &[
+            ("secure/ok", ok.as_str()),
+            ("secure/git", git.as_str()),
+            ("secure/unsigned", unsigned.as_str()),
+            ("secure/tampered", tampered.as_str()),
+            ("secure/moved", other.as_str()),
+        ],
+    );
+
+    let index = Index::from_disk(res.res.clone(), DirLock::acquire(&dir).unwrap()).unwrap();
+    let rejection = |name: &str| {
+        index
+            .entries(&Name::from_str(name).unwrap())
+            .unwrap_err()
+            .downcast::<EntryError>()
+            .unwrap()
+    };
+
+    assert!(index.entries(&Name::from_str("secure/ok").unwrap()).is_ok());
+    match rejection("secure/git") {
+        EntryError::Insecure { .. } => {}
+        e => panic!("expected an insecure entry, got {:?}", e),
+    }
+    match rejection("secure/unsigned") {
+        EntryError::Unsigned { .. } => {}
+        e => panic!("expected an unsigned entry, got {:?}", e),
+    }
+    match rejection("secure/tampered") {
+        EntryError::BadSignature { .. } => {}
+        e => panic!("expected a bad signature, got {:?}", e),
+    }
+    // A properly signed entry is still rejected if it's under the wrong name.
+    match rejection("secure/moved") {
+        EntryError::WrongName { ref found, .. } if found == "secure/other" => {}
+        e => panic!("expected an entry with the wrong name, got {:?}", e),
+    }
+    drop(index);
+
+    // A secure index has to publish its keys.
+    write_index(&dir, "[index]\nsecure = true\n", &[]);
+    assert!(Index::from_disk(res.res.clone(), DirLock::acquire(&dir).unwrap()).is_err());
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn index_keys_pinned() {
+    let base = env::temp_dir().join("elba-index-keys-test");
+    let dir = base.join("index");
+    let res = DirectRes::from_str(&format!("dir+file://{}", dir.display())).unwrap();
+    let (old_pair, old) = keypair(1);
+    let (_, new) = keypair(2);
+
+    let logger = Logger::root(slog::Discard, o!());
+    let cache = Cache::from_disk(&logger, base.join("cache"), res.clone().into());
+
+    // The first time we see the index, its keys get pinned.
+    write_index(
+        &dir,
+        &format!("[index]\nsecure = false\nkeys = [\"{}\"]\n", old),
+        &[],
+    );
+    assert!(cache.get_indices(&[res.clone()]).is_ok());
+
+    // Switching to another key out of the blue isn't allowed...
+    write_index(
+        &dir,
+        &format!("[index]\nsecure = false\nkeys = [\"{}\"]\n", new),
+        &[],
+    );
+    match cache
+        .get_indices(&[res.clone()])
+        .unwrap_err()
+        .downcast::<KeyError>()
+    {
+        Ok(KeyError::Untrusted { .. }) => {}
+        e => panic!("expected an untrusted key, got {:?}", e),
+    }
+
+    // ...but rotating to it is.
+    let sig = base64::encode(old_pair.sign(new.to_string().as_bytes()).as_ref());
+    write_index(
+        &dir,
+        &format!(
+            "[index]\nsecure = false\nkeys = [\"{}\"]\n\n[[index.rotations]]\nold = \"{}\"\nnew = \"{}\"\nsignature = \"{}\"\n",
+            new, old, new, sig
+        ),
+        &[],
+    );
+    assert!(cache.get_indices(&[res.clone()]).is_ok());
+
+    let _ = fs::remove_dir_all(&base);
+}
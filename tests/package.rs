@@ -0,0 +1,162 @@
+#![cfg(unix)]
+
+extern crate elba;
+extern crate flate2;
+extern crate sha2;
+extern crate tar;
+
+mod common;
+
+use common::{config, executable, write_files, IDRIS};
+use elba::cli::{
+    build::BuildCtx,
+    package::{list, package},
+};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+const MANIFEST: &str = r#"
+[package]
+name = 'package/a'
+version = '1.0.0'
+authors = ['me']
+license = 'MIT'
+exclude = ['docs']
+
+[targets.lib]
+path = "src/"
+modules = ["A"]
+"#;
+
+fn project(name: &str) -> BuildCtx {
+    project_with(name, MANIFEST)
+}
+
+fn project_with(name: &str, manifest: &str) -> BuildCtx {
+    let project = env::temp_dir().join(format!("elba-package-test-{}", name));
+    let _ = fs::remove_dir_all(&project);
+
+    write_files(
+        &project,
+        &[
+            ("elba.toml", manifest),
+            ("src/A.idr", "module A"),
+            ("src/A/B.idr", "module A.B"),
+            ("docs/index.md", "# a"),
+            ("target/junk", "junk"),
+        ],
+    );
+
+    let bin = env::temp_dir().join(format!("elba-package-test-{}-bin", name));
+    compiler(&bin, IDRIS);
+
+    let c = config(&env::temp_dir().join(format!("elba-package-test-{}-cache", name)));
+
+    let mut ctx = BuildCtx::from_config(&c, project).unwrap();
+    ctx.compiler = Some(bin.join("idris"));
+    ctx.path = bin.into_os_string();
+    ctx
+}
+
+/// Puts a compiler which runs `script` in `bin/`, for packages to be verified with.
+fn compiler(bin: &Path, script: &str) {
+    write_files(bin, &[("idris", script)]);
+    executable(&bin.join("idris"));
+}
+
+#[test]
+fn package_list() {
+    let ctx = project("list");
+
+    let files = list(&ctx.project).unwrap();
+    assert_eq!(
+        files,
+        vec![
+            PathBuf::from("elba.toml"),
+            PathBuf::from("src/A.idr"),
+            PathBuf::from("src/A/B.idr"),
+        ]
+    );
+
+    let _ = fs::remove_dir_all(&ctx.project);
+}
+
+#[test]
+fn package_reproducible() {
+    let ctx = project("reproducible");
+
+    let first = package(&ctx, true).unwrap();
+    assert!(first.path.exists());
+
+    // Touching a file doesn't change the archive.
+    thread::sleep(Duration::from_millis(1100));
+    fs::write(ctx.project.join("src/A.idr"), "module A").unwrap();
+    let second = package(&ctx, true).unwrap();
+    assert_eq!(first.tarball, second.tarball);
+    assert_eq!(first.cksum, second.cksum);
+
+    // But changing one does.
+    fs::write(ctx.project.join("src/A.idr"), "module A\n").unwrap();
+    let third = package(&ctx, false).unwrap();
+    assert_ne!(first.cksum, third.cksum);
+
+    let _ = fs::remove_dir_all(&ctx.project);
+}
+
+#[test]
+fn package_stray_module() {
+    let ctx = project("stray-module");
+
+    // Modules outside of the declared ones are caught when the package is verified.
+    fs::write(ctx.project.join("src/Other.idr"), "module Other").unwrap();
+    assert!(package(&ctx, false).is_ok());
+    assert!(package(&ctx, true).is_err());
+
+    let _ = fs::remove_dir_all(&ctx.project);
+}
+
+#[test]
+fn package_verify_builds() {
+    let ctx = project("verify-builds");
+    let bin = PathBuf::from(&ctx.path);
+
+    // The packaged copy is built, in a directory of its own which doesn't outlive the build.
+    assert!(package(&ctx, true).is_ok());
+    assert!(!ctx.project.join("target/package/package_a-1.0.0").exists());
+
+    // So a package which can't be built can't be packaged either, unless it isn't verified.
+    compiler(
+        &bin,
+        "#!/bin/sh\n[ \"$1\" = --version ] && echo 1.3.0 || exit 1\n",
+    );
+    assert!(package(&ctx, false).is_ok());
+    assert!(package(&ctx, true).is_err());
+
+    let _ = fs::remove_dir_all(&ctx.project);
+}
+
+#[test]
+fn package_readme() {
+    let manifest =
+        |readme: &str| MANIFEST.replace("exclude", &format!("readme = '{}'\nexclude", readme));
+
+    // The readme has to exist...
+    let ctx = project_with("readme-missing", &manifest("README.md"));
+    assert!(package(&ctx, false).is_err());
+    let _ = fs::remove_dir_all(&ctx.project);
+
+    // ...and be packaged.
+    let ctx = project_with("readme-excluded", &manifest("docs/index.md"));
+    assert!(package(&ctx, false).is_err());
+    let _ = fs::remove_dir_all(&ctx.project);
+
+    let ctx = project_with("readme", &manifest("README.md"));
+    fs::write(ctx.project.join("README.md"), "# a").unwrap();
+    let packaged = package(&ctx, false).unwrap();
+    assert!(packaged.files.contains(&PathBuf::from("README.md")));
+    let _ = fs::remove_dir_all(&ctx.project);
+}
@@ -0,0 +1,320 @@
+extern crate elba;
+extern crate flate2;
+extern crate git2;
+#[macro_use]
+extern crate indexmap;
+extern crate semver;
+extern crate sha2;
+#[macro_use]
+extern crate slog;
+extern crate tar;
+
+mod common;
+
+use common::{config, write_files};
+use elba::{
+    cli::{
+        build::BuildCtx,
+        publish::{publish, PublishCtx},
+        yank::{yank, YankCtx},
+    },
+    index::IndexNames,
+    package::{
+        resolution::{DirectRes, IndexRes},
+        Name, PackageId,
+    },
+    retrieve::Cache,
+};
+use git2::{IndexAddOption, Repository, Signature};
+use semver::Version;
+use slog::Logger;
+use std::{
+    env,
+    ffi::OsString,
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+const INDEX: &str = "[index]\nsecure = false\n";
+
+/// Sets up an empty local index with the config `index_toml` and a project to publish to it.
+///
+/// The packaged copy of the project isn't verified, since there's no compiler to build it with.
+fn setup(name: &str, manifest: &str, index_toml: &str) -> (PathBuf, IndexRes, PublishCtx) {
+    let base = env::temp_dir().join(format!("elba-publish-test-{}", name));
+    let _ = fs::remove_dir_all(&base);
+
+    let index = base.join("index");
+    write_files(&index, &[("index.toml", index_toml)]);
+    let res = IndexRes::from_str(&format!("index+dir+file://{}", index.display())).unwrap();
+
+    let project = base.join("project");
+    write_files(
+        &project,
+        &[
+            ("elba.toml", manifest),
+            ("src/A.idr", "module A"),
+            ("target/junk", "junk"),
+        ],
+    );
+
+    let mut c = config(&base);
+    c.indices = IndexNames::new(indexmap!("local".to_owned() => res.clone()));
+
+    let ctx = PublishCtx {
+        build: BuildCtx::from_config(&c, project).unwrap(),
+        index: None,
+        verify: false,
+    };
+
+    (base, res, ctx)
+}
+
+#[test]
+fn publish_dir_index() {
+    let manifest = r#"
+[package]
+name = 'publish/a'
+version = '1.0.0'
+authors = ['me']
+license = 'MIT'
+
+[dependencies]
+'publish/b' = '>= 1.0.0'
+
+[targets.lib]
+path = "src/"
+"#;
+    let (base, res, ctx) = setup("dir", manifest, INDEX);
+
+    publish(&ctx).unwrap();
+
+    let logger = Logger::root(slog::Discard, o!());
+    let cache = Cache::from_disk(&logger, base.join("cache"), res.clone());
+    let mut indices = cache.get_indices(&[res.res.clone()]).unwrap();
+    let pkg = PackageId::from_str(&format!("publish/a@{}", res)).unwrap();
+    let entry = indices.entries(&pkg).unwrap()[&Version::parse("1.0.0").unwrap()].clone();
+
+    assert_eq!(entry.dependencies.len(), 1);
+    assert_eq!(entry.dependencies[0].index, res);
+    match entry.location {
+        DirectRes::Tar {
+            url,
+            cksum: Some(cksum),
+        } => {
+            let tarball = fs::read(url.to_file_path().unwrap()).unwrap();
+            assert_eq!(cksum.hash, common::sha256(&tarball));
+        }
+        loc => panic!("unexpected location {}", loc),
+    }
+
+    // The same version can't be published twice.
+    assert!(publish(&ctx).is_err());
+
+    let _ = fs::remove_dir_all(&base);
+}
+
+#[test]
+fn publish_path_dep() {
+    let manifest = r#"
+[package]
+name = 'publish/a'
+version = '1.0.0'
+authors = ['me']
+license = 'MIT'
+
+[dependencies]
+'publish/b' = { path = 'file://here' }
+
+[targets.lib]
+path = "src/"
+"#;
+    let (base, _, ctx) = setup("path-dep", manifest, INDEX);
+
+    assert!(publish(&ctx).is_err());
+
+    let _ = fs::remove_dir_all(&base);
+}
+
+#[test]
+fn publish_yank() {
+    let manifest = r#"
+[package]
+name = 'publish/a'
+version = '1.0.0'
+authors = ['me']
+license = 'MIT'
+
+[targets.lib]
+path = "src/"
+"#;
+    let (base, res, ctx) = setup("yank", manifest, INDEX);
+    publish(&ctx).unwrap();
+
+    let yanked = |undo: bool| {
+        let ctx = YankCtx {
+            name: Name::from_str("publish/a").unwrap(),
+            version: Version::parse("1.0.0").unwrap(),
+            undo,
+            index: Some("local".to_owned()),
+            env: ctx.build.env.clone(),
+        };
+        yank(&ctx).unwrap();
+
+        let logger = Logger::root(slog::Discard, o!());
+        let cache = Cache::from_disk(&logger, base.join("cache"), res.clone());
+        let mut indices = cache.get_indices(&[res.res.clone()]).unwrap();
+        let pkg = PackageId::from_str(&format!("publish/a@{}", res)).unwrap();
+        indices.entries(&pkg).unwrap()[&Version::parse("1.0.0").unwrap()].yanked
+    };
+
+    assert!(yanked(false));
+    assert!(!yanked(true));
+
+    let _ = fs::remove_dir_all(&base);
+}
+
+#[test]
+fn publish_verify() {
+    let manifest = r#"
+[package]
+name = 'publish/a'
+version = '1.0.0'
+authors = ['me']
+license = 'MIT'
+
+[targets.lib]
+path = "src/"
+"#;
+    let (base, _, mut ctx) = setup("verify", manifest, INDEX);
+    ctx.verify = true;
+    ctx.build.compiler = None;
+    ctx.build.path = OsString::new();
+
+    // Without a compiler, the packaged copy can't be built, so nothing gets published.
+    assert!(publish(&ctx).is_err());
+    assert!(!base.join("index/publish/a").exists());
+
+    let _ = fs::remove_dir_all(&base);
+}
+
+#[test]
+fn publish_remote_dl() {
+    let manifest = r#"
+[package]
+name = 'publish/a'
+version = '1.0.0'
+authors = ['me']
+license = 'MIT'
+
+[targets.lib]
+path = "src/"
+"#;
+    let index =
+        "[index]\nsecure = false\ndl = 'https://example.com/{group}/{name}/{version}.tar.gz'\n";
+    let (base, _, ctx) = setup("remote-dl", manifest, index);
+
+    // There's no way to get the tarball to where the entry would point.
+    assert!(publish(&ctx).is_err());
+    assert!(!base.join("index/publish/a").exists());
+
+    let _ = fs::remove_dir_all(&base);
+}
+
+#[test]
+fn publish_dir_index_commit() {
+    let manifest = r#"
+[package]
+name = 'publish/a'
+version = '1.0.0'
+authors = ['me']
+license = 'MIT'
+
+[targets.lib]
+path = "src/"
+"#;
+    let (base, _, ctx) = setup("commit", manifest, INDEX);
+
+    let repo = Repository::init(base.join("index")).unwrap();
+    let mut staged = repo.index().unwrap();
+    staged
+        .add_all(["*"].iter(), IndexAddOption::DEFAULT, None)
+        .unwrap();
+    let tree = repo.find_tree(staged.write_tree().unwrap()).unwrap();
+    let sig = Signature::now("me", "me@example.com").unwrap();
+    repo.commit(Some("HEAD"), &sig, &sig, "index", &tree, &[])
+        .unwrap();
+
+    publish(&ctx).unwrap();
+
+    let head = repo.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(head.message(), Some("Publish publish/a 1.0.0"));
+    let tree = head.tree().unwrap();
+    assert!(tree.get_path(Path::new("publish/a")).is_ok());
+    // The tarball is kept inside of the index, so it's committed too.
+    assert!(tree
+        .get_path(Path::new("dl/publish/a/1.0.0.tar.gz"))
+        .is_ok());
+
+    let _ = fs::remove_dir_all(&base);
+}
+
+#[test]
+fn publish_git_index() {
+    let manifest = r#"
+[package]
+name = 'publish/a'
+version = '1.0.0'
+authors = ['me']
+license = 'MIT'
+
+[targets.lib]
+path = "src/"
+"#;
+    let (base, _, mut ctx) = setup("git", manifest, INDEX);
+
+    let dl = base.join("dl");
+    let index_toml = format!(
+        "[index]\nsecure = false\ndl = 'file://{}/{{group}}/{{name}}/{{version}}.tar.gz'\n",
+        dl.display()
+    );
+    let remote = Repository::init_bare(base.join("remote")).unwrap();
+    let mut tree = remote.treebuilder(None).unwrap();
+    let blob = remote.blob(index_toml.as_bytes()).unwrap();
+    tree.insert("index.toml", blob, 0o100_644).unwrap();
+    let tree = remote.find_tree(tree.write().unwrap()).unwrap();
+    let sig = Signature::now("me", "me@example.com").unwrap();
+    remote
+        .commit(Some("refs/heads/master"), &sig, &sig, "index", &tree, &[])
+        .unwrap();
+
+    let res = IndexRes::from_str(&format!(
+        "index+git+file://{}",
+        base.join("remote").display()
+    ))
+    .unwrap();
+    ctx.build.env.indices = IndexNames::new(indexmap!("local".to_owned() => res));
+
+    publish(&ctx).unwrap();
+
+    let head = remote.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(head.message(), Some("Publish publish/a 1.0.0"));
+    assert!(head
+        .tree()
+        .unwrap()
+        .get_path(Path::new("publish/a"))
+        .is_ok());
+    assert!(dl.join("publish/a/1.0.0.tar.gz").exists());
+
+    // Our clone of the index doesn't pick up the entry until it's updated.
+    for clone in fs::read_dir(base.join("cache/indices")).unwrap() {
+        let clone = clone.unwrap().path();
+        if let Ok(r) = Repository::open(&clone) {
+            assert!(!clone.join("publish/a").exists());
+            assert!(r.statuses(None).unwrap().is_empty());
+        }
+    }
+
+    let _ = fs::remove_dir_all(&base);
+}
@@ -9,14 +9,13 @@ extern crate slog_term;
 extern crate url;
 
 use elba::{
-    index::{Index, Indices},
+    index::Indices,
     package::{
-        resolution::{DirectRes, IndexRes, Resolution},
+        resolution::{IndexRes, Resolution},
         Name, PackageId, Summary,
     },
     resolve::{solve::Solve, Resolver},
     retrieve::{Cache, Retriever},
-    util::lock::DirLock,
 };
 use semver::Version;
 use slog::*;
@@ -30,10 +29,7 @@ lazy_static! {
 macro_rules! sum {
     ($a:tt, $b:tt) => {{
         let root_name = Name::from_str($a).unwrap();
-        let root_pkg = PackageId::new(
-            root_name,
-            Resolution::Index(IndexRes::from_str("index+dir+file://data/index/").unwrap()),
-        );
+        let root_pkg = PackageId::new(root_name, Resolution::Index(index()));
         Summary::new(root_pkg, Version::parse($b).unwrap())
     }};
 }
@@ -49,29 +45,18 @@ fn new_logger() -> Logger {
     Logger::root(slog::Discard, o!())
 }
 
-// Even though we could use &CACHE.get_indices, we don't here.
-//
-// Cache assumes that for an Index located on disk, the public-facing DirectRes and the local path
-// perfectly match, which is a reasonable real-world assumption. However, we can't do the env! trick
-// in the indices themselves, where you HAVE to specify the index of a package's dependencies,
-// which would mean that I'd end up having to hard-code my personal directory tree :v
-//
-// One reasonable solution would be to change index.toml such that index dependencies are specified
-// in IndexMap form, and indices would be referred to in dependencies by their "short" name. A method
-// dependencies() would turn short names into proper `DirectRes` structs. By doing this, we could
-// do the env! trick within just the index.toml file.
-fn indices() -> Indices {
-    let url = DirectRes::from_str("dir+file://data/index/").unwrap();
-    let start = env!("CARGO_MANIFEST_DIR");
-    let mut path = PathBuf::new();
-    path.push(start);
-    path.push("tests/data/index");
-
-    let path = DirLock::acquire(&path).unwrap();
-
-    let v = vec![Index::from_disk(url, path).unwrap()];
+// The index's entries don't name the index their dependencies come from, so they come from the
+// index itself, wherever it happens to be on disk.
+fn index() -> IndexRes {
+    IndexRes::from_str(&format!(
+        "index+dir+file://{}/tests/data/index",
+        env!("CARGO_MANIFEST_DIR")
+    ))
+    .unwrap()
+}
 
-    Indices::new(v)
+fn indices() -> Indices {
+    CACHE.get_indices(&[index().res]).unwrap()
 }
 
 fn cache() -> Cache {
@@ -80,10 +65,7 @@ fn cache() -> Cache {
     path.push(start);
     path.push("tests/data/cache");
 
-    let def_ix = IndexRes {
-        res: DirectRes::from_str("dir+file://data/index").unwrap(),
-    };
-    Cache::from_disk(&LOGGER, path, def_ix)
+    Cache::from_disk(&LOGGER, path, index())
 }
 
 fn retriever(root: Summary) -> Retriever<'static> {
@@ -0,0 +1,136 @@
+extern crate elba;
+extern crate flate2;
+extern crate git2;
+#[macro_use]
+extern crate indexmap;
+extern crate sha2;
+extern crate tar;
+
+mod common;
+
+use common::{config, write_files};
+use elba::{
+    cli::{
+        search::{search, SearchCtx},
+        Env,
+    },
+    index::IndexNames,
+    package::resolution::IndexRes,
+};
+use git2::{IndexAddOption, Repository, Signature};
+use std::{env, fs, path::Path, str::FromStr};
+
+fn entry(name: &str, version: &str, yanked: bool) -> String {
+    format!(
+        "{{ \"name\": \"{}\", \"version\": \"{}\", \"dependencies\": [], \"yanked\": {}, \
+         \"location\": \"dir+file://test\" }}\n",
+        name, version, yanked
+    )
+}
+
+/// Writes an index with a few packages in it to `dir`.
+fn write_index(dir: &Path) {
+    let files = vec![
+        ("index.toml", "[index]\nsecure = false\n".to_owned()),
+        (
+            "me/lightyear",
+            entry("me/lightyear", "1.0.0", false)
+                + &entry("me/lightyear", "1.1.0", false)
+                + &entry("me/lightyear", "2.0.0", true),
+        ),
+        ("me/lights", entry("me/lights", "1.0.0", true)),
+        (
+            "you/parser",
+            entry("you/parser", "0.1.0", false).replace(
+                " }",
+                ", \"metadata\": { \"description\": \"Parser combinators\" } }",
+            ),
+        ),
+    ];
+    let files = files
+        .iter()
+        .map(|(path, contents)| (*path, contents.as_str()))
+        .collect::<Vec<_>>();
+    write_files(dir, &files);
+}
+
+/// The settings for searching `res` and nothing else, with everything else under `base`.
+fn search_env(base: &Path, res: &IndexRes) -> Env {
+    let mut c = config(base);
+    c.indices = IndexNames::new(indexmap!("local".to_owned() => res.clone()));
+    Env::from_config(&c).unwrap()
+}
+
+fn ctx(env: &Env, query: &str) -> SearchCtx {
+    SearchCtx {
+        query: query.to_owned(),
+        regex: false,
+        index: None,
+        env: env.clone(),
+    }
+}
+
+#[test]
+fn search_index() {
+    let base = env::temp_dir().join("elba-search-test");
+    let _ = fs::remove_dir_all(&base);
+    let index = base.join("index");
+    write_index(&index);
+    let res = IndexRes::from_str(&format!("index+dir+file://{}", index.display())).unwrap();
+
+    let env = search_env(&base, &res);
+    let ctx = |query: &str, regex: bool| SearchCtx {
+        regex,
+        ..ctx(&env, query)
+    };
+
+    // Yanked versions (and packages with nothing but yanked versions) are left out.
+    let results = search(&ctx("light", false)).unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].name.as_str(), "me/lightyear");
+    assert_eq!(results[0].version.to_string(), "1.1.0");
+    assert_eq!(results[0].index, res);
+
+    let results = search(&ctx("^(me|you)/", true)).unwrap();
+    let names = results.iter().map(|r| r.name.as_str()).collect::<Vec<_>>();
+    assert_eq!(names, vec!["me/lightyear", "you/parser"]);
+
+    // Descriptions are searched too.
+    let results = search(&ctx("combinator", false)).unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].name.as_str(), "you/parser");
+    assert_eq!(
+        results[0].description,
+        Some("Parser combinators".to_owned())
+    );
+
+    let _ = fs::remove_dir_all(&base);
+}
+
+#[test]
+fn search_git_index() {
+    let base = env::temp_dir().join("elba-search-test-git");
+    let _ = fs::remove_dir_all(&base);
+    let index = base.join("index");
+    write_index(&index);
+    fs::write(index.join("me/broken"), "not an entry").unwrap();
+
+    let repo = Repository::init(&index).unwrap();
+    let mut staged = repo.index().unwrap();
+    staged
+        .add_all(["*"].iter(), IndexAddOption::DEFAULT, None)
+        .unwrap();
+    let tree = repo.find_tree(staged.write_tree().unwrap()).unwrap();
+    let sig = Signature::now("me", "me@example.com").unwrap();
+    repo.commit(Some("HEAD"), &sig, &sig, "index", &tree, &[])
+        .unwrap();
+    let res = IndexRes::from_str(&format!("index+git+file://{}", index.display())).unwrap();
+
+    // The clone's `.git/` isn't taken for a group of packages, and an entry which can't be read
+    // is passed over instead of failing the whole search.
+    let results = search(&ctx(&search_env(&base, &res), "me/")).unwrap();
+    let names = results.iter().map(|r| r.name.as_str()).collect::<Vec<_>>();
+    assert_eq!(names, vec!["me/lightyear"]);
+
+    let _ = fs::remove_dir_all(&base);
+}